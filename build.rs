@@ -0,0 +1,20 @@
+//! Picks the initramfs archive to embed in the kernel image.
+//!
+//! `cargo xtask` packs one and names it in `ROS_INITRAMFS`; other builds
+//! embed an empty archive.
+
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=ROS_INITRAMFS");
+    let archive = match env::var_os("ROS_INITRAMFS") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let path = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("empty.cpio");
+            fs::write(&path, []).unwrap();
+            path
+        }
+    };
+    println!("cargo:rerun-if-changed={}", archive.display());
+    println!("cargo:rustc-env=INITRAMFS={}", archive.display());
+}
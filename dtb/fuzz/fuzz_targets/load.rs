@@ -0,0 +1,24 @@
+#![no_main]
+
+use dtb::{DeviceTree, MAX_DEPTH};
+use libfuzzer_sys::fuzz_target;
+
+fn depth(node: &dtb::Node) -> usize {
+    1 + node.children.iter().map(depth).max().unwrap_or(0)
+}
+
+fuzz_target!(|data: &[u8]| {
+    let _ = DeviceTree::total_size(data);
+    if let Ok(tree) = DeviceTree::load(data) {
+        assert!(depth(&tree.root) <= MAX_DEPTH);
+        let mut dts = String::new();
+        dtb::dts::render(&tree, &mut dts).unwrap();
+        tree.walk(|node, _| {
+            for name in node.props.keys() {
+                let _ = node.prop_str_list(name);
+                let _ = node.prop_cells(name);
+                let _ = node.prop_u64(name);
+            }
+        });
+    }
+});
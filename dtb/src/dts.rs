@@ -0,0 +1,251 @@
+//! Rendering of a parsed tree back into DTS source.
+//!
+//! The output is accepted by `dtc`, so a dump taken on the target can be
+//! compiled or diffed against the tree QEMU generates with `dumpdtb`.
+
+use crate::{be_u32, DeviceTree, Node};
+use alloc::{collections::BTreeMap, format, string::String};
+use core::fmt::{self, Write};
+
+/// Properties whose value is always a list of 32-bit cells.
+const CELL_PROPS: &[&str] = &[
+    "reg",
+    "ranges",
+    "dma-ranges",
+    "interrupts",
+    "interrupt-map",
+    "interrupt-map-mask",
+    "bus-range",
+    "phandle",
+    "linux,phandle",
+    "clock-frequency",
+    "timebase-frequency",
+    "reg-shift",
+    "reg-io-width",
+];
+
+/// Properties whose value is always a string or a list of strings.
+const STRING_PROPS: &[&str] = &[
+    "compatible",
+    "model",
+    "status",
+    "device_type",
+    "name",
+    "bootargs",
+    "stdout-path",
+    "mmu-type",
+    "riscv,isa",
+];
+
+/// Properties holding a single phandle.
+const PHANDLE_PROPS: &[&str] = &["interrupt-parent", "cpu", "regmap", "msi-parent"];
+
+/// Renders a device tree as DTS source, so the output can be fed back to `dtc`.
+struct DtsRenderer {
+    /// Label given to every node carrying a `phandle` property.
+    labels: BTreeMap<u32, String>,
+    /// `#interrupt-cells` of every node carrying a `phandle` property.
+    interrupt_cells: BTreeMap<u32, u32>,
+}
+
+impl DtsRenderer {
+    fn new(root: &Node) -> Self {
+        let mut renderer = DtsRenderer {
+            labels: BTreeMap::new(),
+            interrupt_cells: BTreeMap::new(),
+        };
+        renderer.collect(root);
+        renderer
+    }
+
+    /// Walks the tree and assigns a unique label to each node that can be referenced.
+    fn collect(&mut self, node: &Node) {
+        if let Some(phandle) = node
+            .prop_u32("phandle")
+            .or_else(|| node.prop_u32("linux,phandle"))
+        {
+            let (base, unit) = match node.name.split_once('@') {
+                Some((base, unit)) => (base, Some(unit)),
+                None => (node.name.as_str(), None),
+            };
+            let mut label = sanitize_label(base);
+            if let Some(unit) = unit {
+                label.push('_');
+                label.push_str(&sanitize_label(unit));
+            }
+            if self.labels.values().any(|l| *l == label) {
+                label = format!("{}_{}", label, phandle);
+            }
+            self.labels.insert(phandle, label);
+            if let Some(cells) = node.prop_u32("#interrupt-cells") {
+                self.interrupt_cells.insert(phandle, cells);
+            }
+        }
+        for child in node.children.iter() {
+            self.collect(child);
+        }
+    }
+
+    fn render(&self, root: &Node, w: &mut impl Write) -> fmt::Result {
+        writeln!(w, "/dts-v1/;")?;
+        writeln!(w)?;
+        self.render_node(root, 0, w)
+    }
+
+    fn render_node(&self, node: &Node, level: usize, w: &mut impl Write) -> fmt::Result {
+        indent(w, level)?;
+        if let Some(label) = node
+            .prop_u32("phandle")
+            .and_then(|phandle| self.labels.get(&phandle))
+        {
+            write!(w, "{}: ", label)?;
+        }
+        let name = if level == 0 { "/" } else { node.name.as_str() };
+        writeln!(w, "{} {{", name)?;
+        for (name, value) in &node.props {
+            indent(w, level + 1)?;
+            self.render_prop(name, value, w)?;
+        }
+        for child in node.children.iter() {
+            writeln!(w)?;
+            self.render_node(child, level + 1, w)?;
+        }
+        indent(w, level)?;
+        writeln!(w, "}};")
+    }
+
+    fn render_prop(&self, name: &str, value: &[u8], w: &mut impl Write) -> fmt::Result {
+        if value.is_empty() {
+            return writeln!(w, "{};", name);
+        }
+        write!(w, "{} = ", name)?;
+        if PHANDLE_PROPS.contains(&name) && value.len() == 4 {
+            self.render_phandle_cells(value, |_| 0, w)?;
+        } else if name == "interrupts-extended" && value.len().is_multiple_of(4) {
+            let args = |phandle| self.interrupt_cells.get(&phandle).copied().unwrap_or(1);
+            self.render_phandle_cells(value, args, w)?;
+        } else if STRING_PROPS.contains(&name) || name.ends_with("-names") {
+            if is_string_list(value) {
+                render_strings(value, w)?;
+            } else {
+                render_bytes(value, w)?;
+            }
+        } else if CELL_PROPS.contains(&name) || name.starts_with('#') {
+            if value.len().is_multiple_of(4) {
+                render_cells(value, w)?;
+            } else {
+                render_bytes(value, w)?;
+            }
+        } else if is_string_list(value) {
+            render_strings(value, w)?;
+        } else if value.len().is_multiple_of(4) {
+            render_cells(value, w)?;
+        } else {
+            render_bytes(value, w)?;
+        }
+        writeln!(w, ";")
+    }
+
+    /// Renders a cell list made of `<phandle arg...>` groups, where the number
+    /// of arguments following each phandle is given by `args`.
+    fn render_phandle_cells(
+        &self,
+        value: &[u8],
+        args: impl Fn(u32) -> u32,
+        w: &mut impl Write,
+    ) -> fmt::Result {
+        let mut cells = value.chunks_exact(4).map(be_u32);
+        write!(w, "<")?;
+        let mut first = true;
+        while let Some(phandle) = cells.next() {
+            if !first {
+                write!(w, " ")?;
+            }
+            first = false;
+            match self.labels.get(&phandle) {
+                Some(label) => write!(w, "&{}", label)?,
+                None => write!(w, "{:#04x}", phandle)?,
+            }
+            for _ in 0..args(phandle) {
+                match cells.next() {
+                    Some(cell) => write!(w, " {:#04x}", cell)?,
+                    None => break,
+                }
+            }
+        }
+        write!(w, ">")
+    }
+}
+
+fn indent(w: &mut impl Write, level: usize) -> fmt::Result {
+    for _ in 0..level {
+        w.write_char('\t')?;
+    }
+    Ok(())
+}
+
+fn sanitize_label(s: &str) -> String {
+    let mut label: String = s
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if label.starts_with(|c: char| c.is_ascii_digit()) {
+        label.insert(0, '_');
+    }
+    label
+}
+
+/// Whether `value` is a list of non-empty, NUL-terminated printable strings.
+fn is_string_list(value: &[u8]) -> bool {
+    match value.split_last() {
+        Some((0, strings)) => strings
+            .split(|b| *b == 0)
+            .all(|s| !s.is_empty() && s.iter().all(|b| (0x20..0x7f).contains(b))),
+        _ => false,
+    }
+}
+
+fn render_strings(value: &[u8], w: &mut impl Write) -> fmt::Result {
+    for (i, s) in value[..value.len() - 1].split(|b| *b == 0).enumerate() {
+        if i != 0 {
+            write!(w, ", ")?;
+        }
+        w.write_char('"')?;
+        for b in s {
+            match *b {
+                b'"' => w.write_str("\\\"")?,
+                b'\\' => w.write_str("\\\\")?,
+                b => w.write_char(b as char)?,
+            }
+        }
+        w.write_char('"')?;
+    }
+    Ok(())
+}
+
+fn render_cells(value: &[u8], w: &mut impl Write) -> fmt::Result {
+    write!(w, "<")?;
+    for (i, cell) in value.chunks_exact(4).map(be_u32).enumerate() {
+        if i != 0 {
+            write!(w, " ")?;
+        }
+        write!(w, "{:#04x}", cell)?;
+    }
+    write!(w, ">")
+}
+
+fn render_bytes(value: &[u8], w: &mut impl Write) -> fmt::Result {
+    write!(w, "[")?;
+    for (i, b) in value.iter().enumerate() {
+        if i != 0 {
+            write!(w, " ")?;
+        }
+        write!(w, "{:02x}", b)?;
+    }
+    write!(w, "]")
+}
+
+/// Writes the DTS source for `tree` into `w`.
+pub fn render(tree: &DeviceTree, w: &mut impl Write) -> fmt::Result {
+    DtsRenderer::new(&tree.root).render(&tree.root, w)
+}
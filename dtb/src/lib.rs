@@ -0,0 +1,351 @@
+//! Flattened device tree (DTB) parsing.
+//!
+//! Every read is bounds checked against the blob, so a corrupt or truncated
+//! tree yields an [`Error`] instead of a panic. The crate is `no_std` so the
+//! kernel can use it, and builds on the host for tests and fuzzing
+//! (`cargo fuzz run load` from this directory).
+#![no_std]
+
+extern crate alloc;
+
+pub mod dts;
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::fmt;
+
+pub const DEVICE_TREE_MAGIC: u32 = 0xD00DFEED;
+
+/// Size of the fixed `fdt_header` structure.
+pub const HEADER_SIZE: usize = 40;
+
+/// Deepest node nesting accepted, keeping recursion over the tree bounded.
+pub const MAX_DEPTH: usize = 64;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The blob ends before a structure that should be there.
+    Truncated,
+    /// The header does not start with [`DEVICE_TREE_MAGIC`].
+    BadMagic(u32),
+    /// The blob uses a format version this parser does not understand.
+    UnsupportedVersion(u32),
+    /// A header offset or size points outside the blob.
+    BadOffset,
+    /// An unknown token was found in the structure block.
+    BadToken(u32),
+    /// A node or property name is not valid UTF-8 or not NUL terminated.
+    BadString,
+    /// Nodes are not properly nested.
+    BadNesting,
+    /// Nodes are nested deeper than [`MAX_DEPTH`].
+    TooDeep,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "device tree is truncated"),
+            Error::BadMagic(magic) => write!(f, "bad device tree magic {:#x}", magic),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported device tree version {}", version)
+            }
+            Error::BadOffset => write!(f, "device tree block out of bounds"),
+            Error::BadToken(token) => write!(f, "bad device tree token {:#x}", token),
+            Error::BadString => write!(f, "bad string in device tree"),
+            Error::BadNesting => write!(f, "device tree nodes are not properly nested"),
+            Error::TooDeep => write!(f, "device tree is nested too deep"),
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Node {
+    /// Node name including the unit address, empty for the root node.
+    pub name: String,
+    pub props: BTreeMap<String, Vec<u8>>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    /// Name without the `@unit-address` suffix.
+    pub fn base_name(&self) -> &str {
+        self.name.split('@').next().unwrap_or("")
+    }
+
+    /// The `@unit-address` suffix, if any.
+    pub fn unit_address(&self) -> Option<&str> {
+        self.name.split_once('@').map(|(_, unit)| unit)
+    }
+
+    pub fn prop_raw(&self, name: &str) -> Option<&[u8]> {
+        self.props.get(name).map(|v| v.as_slice())
+    }
+
+    pub fn prop_u32(&self, name: &str) -> Option<u32> {
+        let value = self.prop_raw(name)?;
+        Some(u32::from_be_bytes(value.try_into().ok()?))
+    }
+
+    pub fn prop_u64(&self, name: &str) -> Option<u64> {
+        let value = self.prop_raw(name)?;
+        match value.len() {
+            4 => Some(u32::from_be_bytes(value.try_into().ok()?) as u64),
+            8 => Some(u64::from_be_bytes(value.try_into().ok()?)),
+            _ => None,
+        }
+    }
+
+    /// The value as a list of 32-bit big-endian cells.
+    pub fn prop_cells(&self, name: &str) -> Option<Vec<u32>> {
+        let value = self.prop_raw(name)?;
+        if !value.len().is_multiple_of(4) {
+            return None;
+        }
+        Some(value.chunks_exact(4).map(be_u32).collect())
+    }
+
+    /// The value as a single string.
+    pub fn prop_str(&self, name: &str) -> Option<&str> {
+        let (last, value) = self.prop_raw(name)?.split_last()?;
+        if *last != 0 {
+            return None;
+        }
+        core::str::from_utf8(value).ok()
+    }
+
+    /// The value as a list of NUL-separated strings.
+    pub fn prop_str_list(&self, name: &str) -> Option<Vec<&str>> {
+        let (last, value) = self.prop_raw(name)?.split_last()?;
+        if *last != 0 {
+            return None;
+        }
+        value
+            .split(|b| *b == 0)
+            .map(|s| core::str::from_utf8(s).ok())
+            .collect()
+    }
+
+    /// Whether any entry of `compatible` equals `compatible`.
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.prop_str_list("compatible")
+            .is_some_and(|list| list.contains(&compatible))
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// Looks a node up by a `/`-separated path relative to this node.
+    pub fn find(&self, path: &str) -> Option<&Node> {
+        path.split('/')
+            .filter(|part| !part.is_empty())
+            .try_fold(self, |node, part| node.child(part))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceTree {
+    pub version: u32,
+    pub boot_cpuid_phys: u32,
+    /// Memory reservation block, as `(address, size)` pairs.
+    pub reserved: Vec<(u64, u64)>,
+    pub root: Node,
+}
+
+impl DeviceTree {
+    /// Reads `totalsize` from a header, so the caller knows how much memory
+    /// the whole blob covers before handing it to [`DeviceTree::load`].
+    pub fn total_size(header: &[u8]) -> Result<usize> {
+        let mut reader = Reader::new(header);
+        let magic = reader.u32()?;
+        if magic != DEVICE_TREE_MAGIC {
+            return Err(Error::BadMagic(magic));
+        }
+        Ok(reader.u32()? as usize)
+    }
+
+    pub fn load(blob: &[u8]) -> Result<DeviceTree> {
+        let mut header = Reader::new(blob);
+        let magic = header.u32()?;
+        if magic != DEVICE_TREE_MAGIC {
+            return Err(Error::BadMagic(magic));
+        }
+        let total_size = header.u32()? as usize;
+        let off_dt_struct = header.u32()? as usize;
+        let off_dt_strings = header.u32()? as usize;
+        let off_mem_rsvmap = header.u32()? as usize;
+        let version = header.u32()?;
+        let last_comp_version = header.u32()?;
+        let boot_cpuid_phys = header.u32()?;
+        if version < 16 || last_comp_version > 17 {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let blob = blob.get(..total_size).ok_or(Error::Truncated)?;
+        let size_dt_strings = header.u32()? as usize;
+        let size_dt_struct = if version >= 17 {
+            header.u32()? as usize
+        } else {
+            total_size
+                .checked_sub(off_dt_struct)
+                .ok_or(Error::BadOffset)?
+        };
+
+        let strings = slice(blob, off_dt_strings, size_dt_strings)?;
+        let structure = slice(blob, off_dt_struct, size_dt_struct)?;
+        let reserved = parse_reserved(blob.get(off_mem_rsvmap..).ok_or(Error::BadOffset)?)?;
+        let root = parse_structure(structure, strings)?;
+        Ok(DeviceTree {
+            version,
+            boot_cpuid_phys,
+            reserved,
+            root,
+        })
+    }
+
+    /// Looks a node up by its absolute path, e.g. `/soc/uart@10000000`.
+    pub fn find(&self, path: &str) -> Option<&Node> {
+        self.root.find(path)
+    }
+
+    /// Calls `f` on every node in depth-first order, together with its parent.
+    pub fn walk<'a>(&'a self, mut f: impl FnMut(&'a Node, Option<&'a Node>)) {
+        fn walk<'a>(
+            node: &'a Node,
+            parent: Option<&'a Node>,
+            f: &mut impl FnMut(&'a Node, Option<&'a Node>),
+        ) {
+            f(node, parent);
+            for child in node.children.iter() {
+                walk(child, Some(node), f);
+            }
+        }
+        walk(&self.root, None, &mut f);
+    }
+}
+
+fn slice(blob: &[u8], offset: usize, size: usize) -> Result<&[u8]> {
+    let end = offset.checked_add(size).ok_or(Error::BadOffset)?;
+    blob.get(offset..end).ok_or(Error::BadOffset)
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Cursor over a big-endian byte buffer.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or(Error::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(Error::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.bytes(4).map(be_u32)
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let bytes = self.bytes(8)?;
+        Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Reads a NUL-terminated string and skips the padding up to the next cell.
+    fn cstr(&mut self) -> Result<&'a str> {
+        let rest = self.data.get(self.pos..).ok_or(Error::Truncated)?;
+        let len = rest.iter().position(|b| *b == 0).ok_or(Error::BadString)?;
+        let s = core::str::from_utf8(&rest[..len]).map_err(|_| Error::BadString)?;
+        self.pos += len + 1;
+        self.align();
+        Ok(s)
+    }
+
+    fn align(&mut self) {
+        self.pos = (self.pos + 3) & !3;
+    }
+}
+
+fn parse_reserved(block: &[u8]) -> Result<Vec<(u64, u64)>> {
+    let mut reader = Reader::new(block);
+    let mut reserved = Vec::new();
+    loop {
+        let address = reader.u64()?;
+        let size = reader.u64()?;
+        if address == 0 && size == 0 {
+            return Ok(reserved);
+        }
+        reserved.push((address, size));
+    }
+}
+
+fn string_at(strings: &[u8], offset: usize) -> Result<&str> {
+    let rest = strings.get(offset..).ok_or(Error::BadOffset)?;
+    let len = rest.iter().position(|b| *b == 0).ok_or(Error::BadString)?;
+    core::str::from_utf8(&rest[..len]).map_err(|_| Error::BadString)
+}
+
+fn parse_structure(structure: &[u8], strings: &[u8]) -> Result<Node> {
+    let mut reader = Reader::new(structure);
+    // Nodes still open, innermost last. Kept on the heap rather than the call
+    // stack so malicious nesting cannot overflow the kernel stack.
+    let mut stack: Vec<Node> = Vec::new();
+    let mut root = None;
+    loop {
+        match reader.u32()? {
+            FDT_BEGIN_NODE => {
+                if root.is_some() {
+                    return Err(Error::BadNesting);
+                }
+                if stack.len() >= MAX_DEPTH {
+                    return Err(Error::TooDeep);
+                }
+                let name = reader.cstr()?;
+                stack.push(Node {
+                    name: String::from(name),
+                    ..Node::default()
+                });
+            }
+            FDT_END_NODE => {
+                let node = stack.pop().ok_or(Error::BadNesting)?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => root = Some(node),
+                }
+            }
+            FDT_PROP => {
+                let len = reader.u32()? as usize;
+                let name_offset = reader.u32()? as usize;
+                let value = reader.bytes(len)?;
+                reader.align();
+                let name = string_at(strings, name_offset)?;
+                let node = stack.last_mut().ok_or(Error::BadNesting)?;
+                node.props.insert(String::from(name), Vec::from(value));
+            }
+            FDT_NOP => {}
+            FDT_END => {
+                return match (root, stack.is_empty()) {
+                    (Some(root), true) => Ok(root),
+                    _ => Err(Error::BadNesting),
+                };
+            }
+            token => return Err(Error::BadToken(token)),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! The parser must reject corrupt blobs with an error, never a panic.
+
+use dtb::{DeviceTree, Error, HEADER_SIZE, MAX_DEPTH};
+
+const SMP2_32M: &[u8] = include_bytes!("data/qemu-virt-smp2-32m.dtb");
+
+fn header_field(blob: &mut [u8], index: usize, value: u32) {
+    blob[index * 4..index * 4 + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Builds a blob whose structure block is exactly `structure`.
+fn blob_with_structure(structure: &[u8], strings: &[u8]) -> Vec<u8> {
+    let off_rsvmap = HEADER_SIZE;
+    let off_struct = off_rsvmap + 16;
+    let off_strings = off_struct + structure.len();
+    let total = off_strings + strings.len();
+    let mut blob = vec![0; total];
+    for (i, value) in [
+        0xd00d_feed,
+        total,
+        off_struct,
+        off_strings,
+        off_rsvmap,
+        17,
+        16,
+        0,
+        strings.len(),
+        structure.len(),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        header_field(&mut blob, i, value as u32);
+    }
+    blob[off_struct..off_strings].copy_from_slice(structure);
+    blob[off_strings..].copy_from_slice(strings);
+    blob
+}
+
+fn tokens(tokens: &[u32]) -> Vec<u8> {
+    tokens.iter().flat_map(|t| t.to_be_bytes()).collect()
+}
+
+#[test]
+fn empty_and_short() {
+    assert_eq!(DeviceTree::load(&[]), Err(Error::Truncated));
+    assert_eq!(DeviceTree::total_size(&[0xd0, 0x0d]), Err(Error::Truncated));
+    assert_eq!(
+        DeviceTree::load(&SMP2_32M[..HEADER_SIZE - 1]),
+        Err(Error::Truncated)
+    );
+}
+
+#[test]
+fn bad_magic() {
+    let mut blob = SMP2_32M.to_vec();
+    blob[0] = 0;
+    assert_eq!(DeviceTree::load(&blob), Err(Error::BadMagic(0x000d_feed)));
+    assert_eq!(
+        DeviceTree::total_size(&blob),
+        Err(Error::BadMagic(0x000d_feed))
+    );
+}
+
+#[test]
+fn every_truncation() {
+    for len in 0..SMP2_32M.len() {
+        assert!(DeviceTree::load(&SMP2_32M[..len]).is_err(), "len {}", len);
+    }
+}
+
+#[test]
+fn every_single_byte_corruption() {
+    for i in 0..SMP2_32M.len() {
+        for value in [0x00, 0x01, 0x7f, 0x80, 0xff] {
+            let mut blob = SMP2_32M.to_vec();
+            blob[i] = value;
+            let _ = DeviceTree::load(&blob);
+        }
+    }
+}
+
+#[test]
+fn offsets_out_of_bounds() {
+    for field in 1..=4 {
+        for value in [u32::MAX, u32::MAX - 3, SMP2_32M.len() as u32 + 1] {
+            let mut blob = SMP2_32M.to_vec();
+            header_field(&mut blob, field, value);
+            assert!(DeviceTree::load(&blob).is_err(), "field {}", field);
+        }
+    }
+}
+
+#[test]
+fn unsupported_version() {
+    let mut blob = SMP2_32M.to_vec();
+    header_field(&mut blob, 5, 15);
+    assert_eq!(DeviceTree::load(&blob), Err(Error::UnsupportedVersion(15)));
+}
+
+#[test]
+fn bad_tokens() {
+    let blob = blob_with_structure(&tokens(&[0x1, 0, 0x7]), &[]);
+    assert_eq!(DeviceTree::load(&blob), Err(Error::BadToken(0x7)));
+
+    let blob = blob_with_structure(&tokens(&[0x2, 0x9]), &[]);
+    assert_eq!(DeviceTree::load(&blob), Err(Error::BadNesting));
+
+    let blob = blob_with_structure(&tokens(&[0x1, 0, 0x9]), &[]);
+    assert_eq!(DeviceTree::load(&blob), Err(Error::BadNesting));
+
+    let blob = blob_with_structure(&tokens(&[0x1, 0, 0x2]), &[]);
+    assert_eq!(DeviceTree::load(&blob), Err(Error::Truncated));
+}
+
+#[test]
+fn property_outside_blocks() {
+    // A property whose length runs past the structure block.
+    let blob = blob_with_structure(&tokens(&[0x1, 0, 0x3, 0x100, 0, 0x2, 0x9]), b"a\0");
+    assert_eq!(DeviceTree::load(&blob), Err(Error::Truncated));
+
+    // A property whose name lies past the strings block.
+    let blob = blob_with_structure(&tokens(&[0x1, 0, 0x3, 0, 0x10, 0x2, 0x9]), b"a\0");
+    assert_eq!(DeviceTree::load(&blob), Err(Error::BadOffset));
+
+    // A property name without a terminating NUL.
+    let blob = blob_with_structure(&tokens(&[0x1, 0, 0x3, 0, 0, 0x2, 0x9]), b"ab");
+    assert_eq!(DeviceTree::load(&blob), Err(Error::BadString));
+}
+
+#[test]
+fn nesting_limit() {
+    let deep = |depth: usize| {
+        let mut structure = Vec::new();
+        structure.extend((0..depth).flat_map(|_| [0x1, 0]));
+        structure.extend((0..depth).map(|_| 0x2));
+        structure.push(0x9);
+        blob_with_structure(&tokens(&structure), &[])
+    };
+    assert!(DeviceTree::load(&deep(MAX_DEPTH)).is_ok());
+    assert_eq!(DeviceTree::load(&deep(MAX_DEPTH + 1)), Err(Error::TooDeep));
+    assert_eq!(DeviceTree::load(&deep(100_000)), Err(Error::TooDeep));
+}
@@ -0,0 +1,134 @@
+//! Tests against device trees of QEMU's `virt` machine, laid out the way
+//! `qemu-system-riscv64 -machine virt,dumpdtb=<file>` writes them. The blobs
+//! match the machines xtask starts: `-smp 2 -m 32m` for `cargo qemu`, and a
+//! single hart with `-m 1G -append test`.
+
+use dtb::{dts, DeviceTree};
+
+const SMP2_32M: &[u8] = include_bytes!("data/qemu-virt-smp2-32m.dtb");
+const SMP1_1G_TEST: &[u8] = include_bytes!("data/qemu-virt-smp1-1g-test.dtb");
+
+#[test]
+fn header() {
+    assert_eq!(DeviceTree::total_size(SMP2_32M), Ok(SMP2_32M.len()));
+    let tree = DeviceTree::load(SMP2_32M).unwrap();
+    assert_eq!(tree.version, 17);
+    assert_eq!(tree.boot_cpuid_phys, 0);
+    assert!(tree.reserved.is_empty());
+}
+
+#[test]
+fn root() {
+    let tree = DeviceTree::load(SMP2_32M).unwrap();
+    assert_eq!(tree.root.name, "");
+    assert_eq!(tree.root.prop_u32("#address-cells"), Some(2));
+    assert_eq!(tree.root.prop_u32("#size-cells"), Some(2));
+    assert_eq!(tree.root.prop_str("model"), Some("riscv-virtio,qemu"));
+    assert!(tree.root.is_compatible("riscv-virtio"));
+}
+
+#[test]
+fn memory() {
+    for (blob, size) in [(SMP2_32M, 32 << 20), (SMP1_1G_TEST, 1 << 30)] {
+        let tree = DeviceTree::load(blob).unwrap();
+        let memory = tree.find("/memory@80000000").unwrap();
+        assert_eq!(memory.prop_str("device_type"), Some("memory"));
+        assert_eq!(
+            memory.prop_cells("reg"),
+            Some(vec![0, 0x8000_0000, 0, size])
+        );
+    }
+}
+
+#[test]
+fn cpus() {
+    for (blob, harts) in [(SMP2_32M, 2), (SMP1_1G_TEST, 1)] {
+        let tree = DeviceTree::load(blob).unwrap();
+        let cpus = tree.find("/cpus").unwrap();
+        assert_eq!(cpus.prop_u32("timebase-frequency"), Some(10_000_000));
+        let harts_found: Vec<_> = cpus
+            .children
+            .iter()
+            .filter(|node| node.prop_str("device_type") == Some("cpu"))
+            .collect();
+        assert_eq!(harts_found.len(), harts);
+        for (i, cpu) in harts_found.iter().enumerate() {
+            assert_eq!(cpu.unit_address(), Some(format!("{:x}", i).as_str()));
+            assert_eq!(cpu.prop_u32("reg"), Some(i as u32));
+            assert!(cpu.prop_str("riscv,isa").unwrap().starts_with("rv64"));
+            let intc = cpu.child("interrupt-controller").unwrap();
+            assert!(intc.is_compatible("riscv,cpu-intc"));
+        }
+    }
+}
+
+#[test]
+fn soc_devices() {
+    let tree = DeviceTree::load(SMP2_32M).unwrap();
+    let uart = tree.find("/soc/uart@10000000").unwrap();
+    assert!(uart.is_compatible("ns16550a"));
+    assert_eq!(uart.prop_u32("clock-frequency"), Some(0x384000));
+    assert_eq!(uart.prop_u32("interrupts"), Some(10));
+
+    let plic = tree.find("/soc/plic@c000000").unwrap();
+    assert_eq!(
+        plic.prop_str_list("compatible"),
+        Some(vec!["sifive,plic-1.0.0", "riscv,plic0"])
+    );
+    assert!(plic.props.contains_key("interrupt-controller"));
+    assert_eq!(plic.prop_raw("interrupt-controller"), Some(&[][..]));
+    assert_eq!(uart.prop_u32("interrupt-parent"), plic.prop_u32("phandle"));
+
+    let mut virtio = Vec::new();
+    tree.walk(|node, _| {
+        if node.is_compatible("virtio,mmio") {
+            virtio.push(node.base_name().to_string());
+        }
+    });
+    assert_eq!(virtio.len(), 8);
+    assert!(virtio.iter().all(|name| name == "virtio_mmio"));
+}
+
+#[test]
+fn chosen() {
+    let tree = DeviceTree::load(SMP2_32M).unwrap();
+    let chosen = tree.find("/chosen").unwrap();
+    assert_eq!(chosen.prop_str("stdout-path"), Some("/soc/uart@10000000"));
+    assert_eq!(chosen.prop_str("bootargs"), None);
+
+    let tree = DeviceTree::load(SMP1_1G_TEST).unwrap();
+    let chosen = tree.find("/chosen").unwrap();
+    assert_eq!(chosen.prop_str("bootargs"), Some("test"));
+}
+
+#[test]
+fn walk_visits_parents() {
+    let tree = DeviceTree::load(SMP2_32M).unwrap();
+    let mut count = 0;
+    tree.walk(|node, parent| {
+        count += 1;
+        match parent {
+            Some(parent) => assert!(parent.children.iter().any(|c| std::ptr::eq(c, node))),
+            None => assert!(std::ptr::eq(node, &tree.root)),
+        }
+    });
+    assert!(count > 20);
+}
+
+#[test]
+fn render_dts() {
+    let tree = DeviceTree::load(SMP2_32M).unwrap();
+    let mut out = String::new();
+    dts::render(&tree, &mut out).unwrap();
+    assert!(out.starts_with("/dts-v1/;\n\n/ {\n"));
+    assert!(out.ends_with("};\n"));
+    assert!(out.contains("\tmodel = \"riscv-virtio,qemu\";\n"));
+    assert!(out.contains("\t\tcompatible = \"sifive,plic-1.0.0\", \"riscv,plic0\";\n"));
+    assert!(out.contains("\t\treg = <0x00 0x80000000 0x00 0x2000000>;\n"));
+    assert!(out.contains("\t\tplic_c000000: plic@c000000 {\n"));
+    assert!(out.contains("\t\t\tinterrupt-parent = <&plic_c000000>;\n"));
+    assert!(out.contains("\t\t\tinterrupt-controller;\n"));
+    assert!(out
+        .contains("interrupts-extended = <&interrupt_controller 0x0b &interrupt_controller 0x09"));
+    assert_eq!(out.matches('{').count(), out.matches("};").count());
+}
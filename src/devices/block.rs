@@ -0,0 +1,67 @@
+//! Block devices and the registry of those found at boot.
+
+use ::alloc::{sync::Arc, vec::Vec};
+use core::fmt;
+use spin::Mutex;
+
+/// Size of the blocks addressed by [`BlockDevice`].
+pub const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The block number is past the end of the device.
+    OutOfRange,
+    /// The buffer is not exactly [`BLOCK_SIZE`] bytes long.
+    BadBuffer,
+    /// The device rejects writes.
+    ReadOnly,
+    /// The device reported a failure.
+    Io,
+    /// The device does not support the request.
+    Unsupported,
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockError::OutOfRange => write!(f, "block out of range"),
+            BlockError::BadBuffer => write!(f, "buffer is not one block"),
+            BlockError::ReadOnly => write!(f, "device is read-only"),
+            BlockError::Io => write!(f, "I/O error"),
+            BlockError::Unsupported => write!(f, "request not supported"),
+        }
+    }
+}
+
+pub trait BlockDevice: Send + Sync {
+    /// Reads block `block_id` into `buf`, which must be [`BLOCK_SIZE`] bytes.
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Writes `buf`, which must be [`BLOCK_SIZE`] bytes, to block `block_id`.
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// Number of blocks on the device.
+    fn num_blocks(&self) -> usize;
+
+    /// Waits until all completed writes are on stable storage.
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref BLOCK_DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+}
+
+/// Makes `device` available to filesystems, returning its index.
+pub fn register(device: Arc<dyn BlockDevice>) -> usize {
+    let mut devices = BLOCK_DEVICES.lock();
+    devices.push(device);
+    devices.len() - 1
+}
+
+/// The `index`-th block device registered, in probe order.
+#[allow(unused)]
+pub fn get(index: usize) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES.lock().get(index).cloned()
+}
@@ -0,0 +1,288 @@
+//! Cache of recently used blocks in front of the [`BlockDevice`]s.
+//!
+//! Filesystems get blocks through [`get`], which hands out a shared
+//! [`BlockCache`] instead of reading the device on every access. Modified
+//! blocks are only written back when they are evicted or on [`sync_all`].
+//! Eviction picks the least recently used block nobody holds a reference to,
+//! and the cache grows past its size for as long as every block is held.
+//! The cache's own lock is only held to look blocks up; they are read and
+//! written back after it is released, with only the block itself locked.
+
+use super::block::{BlockDevice, BlockError, BLOCK_SIZE};
+use ::alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Number of blocks kept in memory.
+const CACHE_SIZE: usize = 16;
+
+/// One block of a device held in memory.
+pub struct BlockCache {
+    data: [u8; BLOCK_SIZE],
+    block_id: usize,
+    device: Arc<dyn BlockDevice>,
+    dirty: bool,
+    /// Whether `data` was read from the device yet.
+    loaded: bool,
+}
+
+impl BlockCache {
+    fn new(block_id: usize, device: Arc<dyn BlockDevice>) -> Self {
+        BlockCache {
+            data: [0; BLOCK_SIZE],
+            block_id,
+            device,
+            dirty: false,
+            loaded: false,
+        }
+    }
+
+    /// Reads the block from the device, unless that was done already.
+    fn load(&mut self) -> Result<(), BlockError> {
+        if !self.loaded {
+            self.device.read_block(self.block_id, &mut self.data)?;
+            self.loaded = true;
+        }
+        Ok(())
+    }
+
+    pub fn block_id(&self) -> usize {
+        self.block_id
+    }
+
+    pub fn data(&self) -> &[u8; BLOCK_SIZE] {
+        &self.data
+    }
+
+    /// The block contents for writing. Marks the block dirty.
+    pub fn data_mut(&mut self) -> &mut [u8; BLOCK_SIZE] {
+        self.dirty = true;
+        &mut self.data
+    }
+
+    /// Calls `f` with the `T` stored at `offset`.
+    pub fn read<T: Copy, V>(&self, offset: usize, f: impl FnOnce(&T) -> V) -> V {
+        assert!(offset + core::mem::size_of::<T>() <= BLOCK_SIZE);
+        let value = unsafe { (self.data.as_ptr().add(offset) as *const T).read_unaligned() };
+        f(&value)
+    }
+
+    /// Calls `f` with the `T` stored at `offset` and stores it back.
+    pub fn modify<T: Copy, V>(&mut self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
+        assert!(offset + core::mem::size_of::<T>() <= BLOCK_SIZE);
+        let ptr = unsafe { self.data.as_mut_ptr().add(offset) as *mut T };
+        let mut value = unsafe { ptr.read_unaligned() };
+        let result = f(&mut value);
+        unsafe { ptr.write_unaligned(value) };
+        self.dirty = true;
+        result
+    }
+
+    /// Writes the block back if it was modified.
+    pub fn sync(&mut self) -> Result<(), BlockError> {
+        if self.dirty {
+            self.device.write_block(self.block_id, &self.data)?;
+            self.dirty = false;
+            STATS.writebacks.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// Identifies a device by the address of its shared state.
+fn device_key(device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(device) as *const u8 as usize
+}
+
+struct Entry {
+    device: usize,
+    block_id: usize,
+    cache: Arc<Mutex<BlockCache>>,
+    /// Whether the block is known to be loaded, so that a lookup need not
+    /// lock it, which would deadlock a caller already holding it.
+    loaded: bool,
+}
+
+/// Cached blocks, least recently used first.
+struct BlockCacheManager {
+    entries: VecDeque<Entry>,
+}
+
+type Victims = Vec<Arc<Mutex<BlockCache>>>;
+
+impl BlockCacheManager {
+    /// The entry of block `block_id` of `device`, added but not yet loaded
+    /// on a miss, whether it is loaded, and the blocks to write back to make
+    /// room for it.
+    fn get(
+        &mut self,
+        block_id: usize,
+        device: &Arc<dyn BlockDevice>,
+    ) -> (Arc<Mutex<BlockCache>>, bool, Victims) {
+        let key = device_key(device);
+        if let Some(index) = self
+            .entries
+            .iter()
+            .position(|e| e.device == key && e.block_id == block_id)
+        {
+            STATS.hits.fetch_add(1, Ordering::Relaxed);
+            let entry = self.entries.remove(index).unwrap();
+            let found = (entry.cache.clone(), entry.loaded, Vec::new());
+            self.entries.push_back(entry);
+            return found;
+        }
+        STATS.misses.fetch_add(1, Ordering::Relaxed);
+        let victims = self.evict();
+        let cache = Arc::new(Mutex::new(BlockCache::new(block_id, device.clone())));
+        self.entries.push_back(Entry {
+            device: key,
+            block_id,
+            cache: cache.clone(),
+            loaded: false,
+        });
+        (cache, false, victims)
+    }
+
+    fn mark_loaded(&mut self, cache: &Arc<Mutex<BlockCache>>) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|e| Arc::ptr_eq(&e.cache, cache))
+        {
+            entry.loaded = true;
+        }
+    }
+
+    /// Makes room for one more block by dropping the least recently used
+    /// clean blocks nobody holds, and returns the dirty ones that have to be
+    /// written back before [`BlockCacheManager::remove_written`] drops them.
+    /// While every block is held the cache grows past [`CACHE_SIZE`]
+    /// instead, and shrinks back on later misses.
+    fn evict(&mut self) -> Victims {
+        let mut victims = Vec::new();
+        let mut index = 0;
+        while self.entries.len() >= CACHE_SIZE + victims.len() && index < self.entries.len() {
+            let cache = &self.entries[index].cache;
+            // Blocks still referenced elsewhere cannot be dropped, and
+            // nobody else can hold the lock of one that is not.
+            if Arc::strong_count(cache) > 1 {
+                index += 1;
+            } else if cache.lock().dirty {
+                victims.push(cache.clone());
+                index += 1;
+            } else {
+                self.entries.remove(index);
+                STATS.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        victims
+    }
+
+    /// Drops the `victims` that were written back, unless they were looked
+    /// up again in the meantime.
+    fn remove_written(&mut self, victims: &Victims) {
+        self.entries.retain(|entry| {
+            let written = victims.iter().any(|victim| {
+                Arc::ptr_eq(victim, &entry.cache)
+                    && Arc::strong_count(victim) == 2
+                    && !victim.lock().dirty
+            });
+            if written {
+                STATS.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+            !written
+        });
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref MANAGER: Mutex<BlockCacheManager> = Mutex::new(BlockCacheManager {
+        entries: VecDeque::new(),
+    });
+}
+
+/// Block `block_id` of `device`, read from the device unless it is cached.
+pub fn get(
+    block_id: usize,
+    device: &Arc<dyn BlockDevice>,
+) -> Result<Arc<Mutex<BlockCache>>, BlockError> {
+    let (cache, loaded, victims) = MANAGER.lock().get(block_id, device);
+    if !victims.is_empty() {
+        // A block that fails to write back stays cached, still dirty.
+        for victim in &victims {
+            victim.lock().sync()?;
+        }
+        MANAGER.lock().remove_written(&victims);
+    }
+    if !loaded {
+        // Whoever added the entry may not have read the block yet, or
+        // failed to.
+        cache.lock().load()?;
+        MANAGER.lock().mark_loaded(&cache);
+    }
+    Ok(cache)
+}
+
+/// Writes every dirty block back and flushes the devices they belong to.
+pub fn sync_all() -> Result<(), BlockError> {
+    let caches: Vec<_> = MANAGER
+        .lock()
+        .entries
+        .iter()
+        .map(|e| e.cache.clone())
+        .collect();
+    let mut flushed = Vec::new();
+    for cache in caches {
+        let mut cache = cache.lock();
+        cache.sync()?;
+        let key = device_key(&cache.device);
+        if !flushed.contains(&key) {
+            cache.device.flush()?;
+            flushed.push(key);
+        }
+    }
+    Ok(())
+}
+
+struct Counters {
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    evictions: AtomicUsize,
+    writebacks: AtomicUsize,
+}
+
+static STATS: Counters = Counters {
+    hits: AtomicUsize::new(0),
+    misses: AtomicUsize::new(0),
+    evictions: AtomicUsize::new(0),
+    writebacks: AtomicUsize::new(0),
+};
+
+/// Snapshot of the cache counters since boot.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+    pub writebacks: usize,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "block cache: {} hits, {} misses, {} evictions, {} writebacks",
+            self.hits, self.misses, self.evictions, self.writebacks
+        )
+    }
+}
+
+pub fn stats() -> CacheStats {
+    CacheStats {
+        hits: STATS.hits.load(Ordering::Relaxed),
+        misses: STATS.misses.load(Ordering::Relaxed),
+        evictions: STATS.evictions.load(Ordering::Relaxed),
+        writebacks: STATS.writebacks.load(Ordering::Relaxed),
+    }
+}
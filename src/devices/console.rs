@@ -1,21 +1,66 @@
+use super::ns16550a;
 use crate::sbi;
 use core::fmt::{self, Write};
 use spin::Mutex;
 
+/// Writes to the UART once it is probed, and through SBI before that.
 struct Stdout;
 
+impl Stdout {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        if let Some(uart) = ns16550a::get() {
+            uart.write(bytes);
+            return;
+        }
+        for byte in bytes {
+            sbi::console_putchar(*byte as usize);
+        }
+    }
+}
+
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        let mut buffer = [0u8; 4];
-        for c in s.chars() {
-            for code_point in c.encode_utf8(&mut buffer).as_bytes().iter() {
-                sbi::console_putchar(*code_point as usize);
-            }
-        }
+        self.write_bytes(s.as_bytes());
         Ok(())
     }
 }
 
+/// Reads one byte of console input without blocking.
+pub fn getchar() -> Option<u8> {
+    match ns16550a::get() {
+        Some(uart) => uart.read(),
+        None => match sbi::console_getchar() {
+            usize::MAX => None,
+            c => Some(c as u8),
+        },
+    }
+}
+
+/// Waits for one byte of console input.
+pub fn getchar_wait() -> u8 {
+    if let Some(uart) = ns16550a::get() {
+        return uart.read_wait();
+    }
+    loop {
+        if let Some(byte) = getchar() {
+            return byte;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Writes raw bytes, which need not be UTF-8, to the console.
+pub fn write(bytes: &[u8]) {
+    STDOUT.lock().write_bytes(bytes);
+}
+
+/// Sends everything written so far out of the UART.
+pub fn flush() {
+    if let Some(uart) = ns16550a::get() {
+        uart.flush();
+    }
+}
+
 #[allow(unused)]
 pub fn print(args: fmt::Arguments) {
     STDOUT.lock().write_fmt(args).unwrap();
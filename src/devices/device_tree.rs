@@ -1,79 +1,116 @@
-use device_tree::{DeviceTree, Node};
-
-const DEVICE_TREE_MAGIC: u32 = 0xD00DFEED;
-use ::alloc::{boxed::Box, string::String, vec::Vec, *};
-use lazy_static::lazy_static;
+use ::alloc::{boxed::Box, vec::Vec};
+use core::fmt::{self, Write};
+use core::ops::Range;
+use dtb::{DeviceTree, HEADER_SIZE};
 use once_cell::race::OnceBox;
 
-#[repr(C)]
-struct DtbHeader {
-    magic: u32,
-    size: u32,
-}
+/// Adapts the console so the renderer can write to it directly.
+struct Console;
 
-fn print(node: &Node, level: usize) {
-    let mut indent = String::new();
-    if level >= 2 {
-        for _ in 1..level {
-            indent.push_str("  ");
-        }
-        print!("{}", indent);
-        print!("+-");
-    }
-    println!("{}", node.name);
-    for (k, v) in &node.props {
-        print!("{}", indent);
-        print!("| {}: ", k);
-        if k == "reg" {
-            print!("{:?}", node.prop_cells(k));
-            // for (index, byte) in v.iter().enumerate() {
-            //     if index % 4 == 0 {
-            //         print!(" ");
-            //     }
-            //     print!("{:02X?} ", byte);
-            // }
-        } else if k == "device_type" {
-            print!("{:?}", node.prop_str(k));
-        } else if k == "compatible" {
-            print!("{:?}", node.prop_str_list(k));
-        } else {
-            print!("{:?}", v);
-        }
-        println!("");
-    }
-    // log!("{}{}", indent, node.name);
-    for child in node.children.iter() {
-        print(child, level + 1);
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print!("{}", s);
+        Ok(())
     }
 }
 
 static DT: OnceBox<DeviceTree> = OnceBox::new();
 
+/// The device tree passed in by the firmware.
+///
+/// Panics if called before [`print_tree`] has loaded it.
+pub fn tree() -> &'static DeviceTree {
+    DT.get().expect("device tree not loaded")
+}
+
+/// Start and size of the first RAM bank.
+pub fn memory_range() -> Option<(usize, usize)> {
+    let root = &tree().root;
+    let address_cells = root.prop_u32("#address-cells").unwrap_or(2);
+    let size_cells = root.prop_u32("#size-cells").unwrap_or(1);
+    let memory = root
+        .children
+        .iter()
+        .find(|node| node.prop_str("device_type") == Some("memory"))?;
+    let mut cells = memory.prop_cells("reg")?.into_iter();
+    let mut read = |count: u32| {
+        (0..count).try_fold(0usize, |value, _| {
+            Some((value << 32) | cells.next()? as usize)
+        })
+    };
+    Some((read(address_cells)?, read(size_cells)?))
+}
+
+/// Physical address range of the initrd the bootloader loaded, if any.
+pub fn initrd_range() -> Option<Range<usize>> {
+    let chosen = tree().root.child("chosen")?;
+    let start = chosen.prop_u64("linux,initrd-start")? as usize;
+    let end = chosen.prop_u64("linux,initrd-end")? as usize;
+    (start < end).then_some(start..end)
+}
+
+/// IDs of the harts the `/cpus` node lists, in its order.
+pub fn harts() -> Vec<usize> {
+    let Some(cpus) = tree().root.child("cpus") else {
+        return Vec::new();
+    };
+    cpus.children
+        .iter()
+        .filter(|node| node.prop_str("device_type") == Some("cpu"))
+        .filter_map(|cpu| cpu.prop_u32("reg"))
+        .map(|hart| hart as usize)
+        .collect()
+}
+
+/// The kernel command line the bootloader passed, or an empty one.
+pub fn bootargs() -> &'static str {
+    tree()
+        .root
+        .child("chosen")
+        .and_then(|chosen| chosen.prop_str("bootargs"))
+        .unwrap_or("")
+}
+
+/// The value of `name=value` on the kernel command line, if given.
+pub fn bootarg(name: &str) -> Option<&'static str> {
+    bootargs()
+        .split_whitespace()
+        .find_map(|arg| arg.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// Borrows the device tree blob the firmware left at `dtb_pa`.
+///
+/// # Safety
+///
+/// `dtb_pa` must point to readable memory holding at least a DTB header, and
+/// the whole blob it describes must stay mapped and unmodified.
+unsafe fn blob<'a>(dtb_pa: usize) -> &'a [u8] {
+    let header = core::slice::from_raw_parts(dtb_pa as *const u8, HEADER_SIZE);
+    match DeviceTree::total_size(header) {
+        Ok(size) => core::slice::from_raw_parts(dtb_pa as *const u8, size),
+        Err(err) => panic!("Failed to load device tree: {}", err),
+    }
+}
+
 pub unsafe fn print_tree(dtb_pa: usize) {
     log!("Tree addr: {:p}", dtb_pa as *const u8);
-    let header = &*(dtb_pa as *const DtbHeader);
-    let magic = u32::from_be(header.magic);
-    if magic == DEVICE_TREE_MAGIC {
-        log!("Found device tree!");
-        let size = u32::from_be(header.size);
-        log!("size: {:p}", size as *const u8);
-        // 拷贝数据，加载并遍历
-        let data = core::slice::from_raw_parts(dtb_pa as *const u8, size as usize);
-        if let Ok(dt) = DeviceTree::load(data) {
+    // 拷贝数据，加载并遍历
+    let data = blob(dtb_pa);
+    log!("Found device tree!");
+    log!("size: {:p}", data.len() as *const u8);
+    match DeviceTree::load(data) {
+        Ok(dt) => {
             DT.set(Box::new(dt)).unwrap();
-            print(&DT.get().unwrap().root, 0);
-            for node in &DT.get().unwrap().root.children {
-                if node.name.starts_with("memory") {
-                    let reg = node.prop_raw("reg").unwrap();
-                    let start: usize = usize::from_be_bytes(reg[0..8].try_into().unwrap());
-                    let size: usize = usize::from_be_bytes(reg[8..16].try_into().unwrap());
+            dtb::dts::render(DT.get().unwrap(), &mut Console).unwrap();
+            match memory_range() {
+                Some((start, size)) => {
                     log!("Memory start: {:X}, size: {:X}", start, size);
                 }
+                None => {
+                    log!("No memory in device tree");
+                }
             }
-        } else {
-            panic!("Failed to load device tree, maybe not a device tree");
         }
-        return;
+        Err(err) => panic!("Failed to load device tree: {}", err),
     }
-    panic!("Failed to load device tree");
 }
@@ -0,0 +1,288 @@
+//! Device driver model.
+//!
+//! Drivers are listed in [`DRIVERS`] and matched against device tree nodes by
+//! their `compatible` strings. Every node that a driver accepts becomes a bound
+//! [`Device`], which records where the hardware lives and which interrupts it
+//! raises.
+
+use ::alloc::{string::String, sync::Arc, vec::Vec};
+use core::fmt;
+use dtb::{DeviceTree, Node};
+use spin::Mutex;
+
+/// A physical MMIO window taken from a node's `reg` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmioRegion {
+    pub base: usize,
+    pub size: usize,
+}
+
+#[derive(Debug)]
+pub enum ProbeError {
+    /// The node lacks a property the driver needs.
+    MissingProperty(&'static str),
+    /// The hardware does not look like what the driver expects.
+    Unsupported,
+    /// The hardware is already in use.
+    Busy,
+    /// The node describes a slot with nothing attached.
+    Absent,
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeError::MissingProperty(name) => write!(f, "missing property `{}`", name),
+            ProbeError::Unsupported => write!(f, "unsupported device"),
+            ProbeError::Busy => write!(f, "device busy"),
+            ProbeError::Absent => write!(f, "no device present"),
+        }
+    }
+}
+
+pub trait Driver: Sync {
+    fn name(&self) -> &'static str;
+
+    /// `compatible` strings this driver can handle.
+    fn compatible(&self) -> &'static [&'static str];
+
+    /// Takes ownership of the hardware described by `device`. `node` gives
+    /// access to properties the generic model does not decode.
+    fn probe(&self, device: &Arc<Device>, node: &Node) -> Result<(), ProbeError>;
+
+    /// Releases the hardware. Called before the device is unbound.
+    fn remove(&self, _device: &Device) {}
+}
+
+/// A device tree node bound to a driver.
+pub struct Device {
+    /// Full path of the node, e.g. `/soc/uart@10000000`.
+    pub path: String,
+    /// The `compatible` entry the driver matched on.
+    pub compatible: &'static str,
+    pub regions: Vec<MmioRegion>,
+    /// Interrupt specifiers from the `interrupts` property.
+    pub irqs: Vec<u32>,
+    /// Phandle of the controller the interrupts are routed to.
+    pub interrupt_parent: Option<u32>,
+    pub driver: &'static dyn Driver,
+}
+
+impl Device {
+    /// The first MMIO region, which is the only one for most devices.
+    pub fn region(&self) -> Option<MmioRegion> {
+        self.regions.first().copied()
+    }
+
+    pub fn irq(&self) -> Option<u32> {
+        self.irqs.first().copied()
+    }
+}
+
+impl fmt::Debug for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Device")
+            .field("path", &self.path)
+            .field("compatible", &self.compatible)
+            .field("regions", &self.regions)
+            .field("irqs", &self.irqs)
+            .field("driver", &self.driver.name())
+            .finish()
+    }
+}
+
+/// All drivers built into the kernel. They are probed in this order, so
+/// interrupt controllers must come before the devices routed to them.
+static DRIVERS: &[&dyn Driver] = &[
+    &super::plic::DRIVER,
+    &super::ns16550a::DRIVER,
+    &super::virtio::DRIVER,
+];
+
+lazy_static::lazy_static! {
+    static ref DEVICES: Mutex<Vec<Arc<Device>>> = Mutex::new(Vec::new());
+}
+
+/// Properties inherited from the ancestors of a node.
+#[derive(Clone, Copy)]
+struct Context {
+    address_cells: u32,
+    size_cells: u32,
+    interrupt_parent: Option<u32>,
+}
+
+/// A node that has a `compatible` property, with its inherited context.
+struct Candidate<'a> {
+    node: &'a Node,
+    path: String,
+    context: Context,
+}
+
+fn collect<'a>(node: &'a Node, path: String, context: Context, out: &mut Vec<Candidate<'a>>) {
+    let context = Context {
+        interrupt_parent: node
+            .prop_u32("interrupt-parent")
+            .or(context.interrupt_parent),
+        ..context
+    };
+    let enabled = matches!(node.prop_str("status"), None | Some("okay") | Some("ok"));
+    if !enabled {
+        return;
+    }
+    if node.props.contains_key("compatible") {
+        out.push(Candidate {
+            node,
+            path: path.clone(),
+            context,
+        });
+    }
+    let child_context = Context {
+        address_cells: node.prop_u32("#address-cells").unwrap_or(2),
+        size_cells: node.prop_u32("#size-cells").unwrap_or(1),
+        ..context
+    };
+    for child in node.children.iter() {
+        let mut child_path = path.clone();
+        if !child_path.ends_with('/') {
+            child_path.push('/');
+        }
+        child_path.push_str(&child.name);
+        collect(child, child_path, child_context, out);
+    }
+}
+
+/// Reads a number spanning `cells` 32-bit cells.
+fn read_cells(cells: &mut impl Iterator<Item = u32>, count: u32) -> Option<usize> {
+    (0..count).try_fold(0usize, |value, _| {
+        Some(value.checked_shl(32).unwrap_or(0) | cells.next()? as usize)
+    })
+}
+
+fn regions(node: &Node, context: &Context) -> Vec<MmioRegion> {
+    let mut regions = Vec::new();
+    let (address_cells, size_cells) = (context.address_cells, context.size_cells);
+    if address_cells == 0 && size_cells == 0 {
+        return regions;
+    }
+    if let Some(reg) = node.prop_cells("reg") {
+        let mut cells = reg.into_iter();
+        while let (Some(base), Some(size)) = (
+            read_cells(&mut cells, address_cells),
+            read_cells(&mut cells, size_cells),
+        ) {
+            regions.push(MmioRegion { base, size });
+        }
+    }
+    regions
+}
+
+/// Probes `driver` on `candidate`, and returns whether it took the device.
+fn bind(driver: &'static dyn Driver, candidate: &Candidate, compatible: &'static str) -> bool {
+    let device = Arc::new(Device {
+        path: candidate.path.clone(),
+        compatible,
+        regions: regions(candidate.node, &candidate.context),
+        irqs: candidate.node.prop_cells("interrupts").unwrap_or_default(),
+        interrupt_parent: candidate.context.interrupt_parent,
+        driver,
+    });
+    match driver.probe(&device, candidate.node) {
+        Ok(()) => {
+            log!(
+                "driver: {} bound to {} {:x?} irqs {:?}",
+                driver.name(),
+                device.path,
+                device.regions,
+                device.irqs
+            );
+            DEVICES.lock().push(device);
+            true
+        }
+        Err(err) => {
+            log!(
+                "driver: {} failed on {}: {}",
+                driver.name(),
+                device.path,
+                err
+            );
+            false
+        }
+    }
+}
+
+/// Matches every enabled node of `tree` against the registered drivers.
+pub fn probe_all(tree: &DeviceTree) {
+    let root_context = Context {
+        address_cells: 2,
+        size_cells: 1,
+        interrupt_parent: None,
+    };
+    let mut candidates = Vec::new();
+    collect(&tree.root, String::from("/"), root_context, &mut candidates);
+    let mut choices: Vec<_> = candidates
+        .iter()
+        .map(|candidate| (candidate, drivers_for(candidate.node)))
+        .collect();
+    // Interrupt controllers come first in `DRIVERS`, so probing nodes in
+    // the order of the driver they prefer has them ready for the devices
+    // routed to them.
+    choices.sort_by_key(|(_, drivers)| drivers.first().map_or(usize::MAX, |&(index, _)| index));
+    for (candidate, drivers) in choices {
+        // Fall back to the drivers for less specific compatibles if those
+        // for the more specific ones fail to probe.
+        let bound = drivers
+            .iter()
+            .any(|&(index, matched)| bind(DRIVERS[index], candidate, matched));
+        if !bound {
+            let compatible = candidate
+                .node
+                .prop_str_list("compatible")
+                .unwrap_or_default();
+            log!("driver: no driver for {} {:?}", candidate.path, compatible);
+        }
+    }
+}
+
+/// The drivers that match `node`, by index in [`DRIVERS`], with the
+/// compatible string each matched: first those matching the most specific
+/// compatible, and then in the order they are listed.
+fn drivers_for(node: &Node) -> Vec<(usize, &'static str)> {
+    let compatible = node.prop_str_list("compatible").unwrap_or_default();
+    compatible
+        .iter()
+        .flat_map(|&c| {
+            DRIVERS
+                .iter()
+                .enumerate()
+                .filter_map(move |(index, driver)| {
+                    let matched = driver.compatible().iter().find(|&&d| d == c)?;
+                    Some((index, *matched))
+                })
+        })
+        .collect()
+}
+
+/// Unbinds the device at `path`, giving its driver a chance to release it.
+pub fn remove(path: &str) -> Option<Arc<Device>> {
+    let device = {
+        let mut devices = DEVICES.lock();
+        let index = devices.iter().position(|d| d.path == path)?;
+        devices.remove(index)
+    };
+    device.driver.remove(&device);
+    Some(device)
+}
+
+/// Devices currently bound to a driver.
+pub fn devices() -> Vec<Arc<Device>> {
+    DEVICES.lock().clone()
+}
+
+/// The first bound device whose driver is called `driver`.
+pub fn find_by_driver(driver: &str) -> Option<Arc<Device>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|d| d.driver.name() == driver)
+        .cloned()
+}
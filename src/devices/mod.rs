@@ -1,3 +1,27 @@
+pub mod block;
 #[macro_use]
 pub mod console;
+// Declared after `console`, whose macros it uses.
+pub mod block_cache;
 pub mod device_tree;
+pub mod driver;
+pub mod ns16550a;
+pub mod plic;
+pub mod virtio;
+
+use ::alloc::vec::Vec;
+use core::ops::Range;
+
+/// Binds drivers to the devices found in the device tree.
+pub fn init() {
+    driver::probe_all(device_tree::tree());
+}
+
+/// MMIO windows of the bound devices, which the kernel address space maps.
+pub fn mmio_ranges() -> Vec<Range<usize>> {
+    driver::devices()
+        .iter()
+        .flat_map(|device| device.regions.iter())
+        .map(|region| region.base..region.base + region.size)
+        .collect()
+}
@@ -0,0 +1,311 @@
+//! NS16550A UART.
+//!
+//! Received bytes are collected into a ring buffer by [`Uart::handle_irq`]
+//! and written bytes are queued in another one, so neither side has to wait
+//! for the line. Until interrupts are routed to the UART, transmission and
+//! reception fall back to polling the line status register.
+
+use super::driver::{Device, Driver, ProbeError};
+use super::plic;
+use crate::trap;
+use ::alloc::{boxed::Box, sync::Arc};
+use core::sync::atomic::{AtomicBool, Ordering};
+use dtb::Node;
+use once_cell::race::OnceBox;
+use spin::Mutex;
+
+const BAUD_RATE: u32 = 115200;
+
+/// Receive buffer / transmit holding register, or divisor latch low with DLAB set.
+const RBR_THR_DLL: usize = 0;
+/// Interrupt enable register, or divisor latch high with DLAB set.
+const IER_DLM: usize = 1;
+/// Interrupt identification register on read, FIFO control register on write.
+const IIR_FCR: usize = 2;
+const LCR: usize = 3;
+const MCR: usize = 4;
+const LSR: usize = 5;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_TX_EMPTY: u8 = 1 << 1;
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+const FCR_CLEAR_TX: u8 = 1 << 2;
+const LCR_8N1: u8 = 0x03;
+const LCR_DLAB: u8 = 1 << 7;
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+const MCR_OUT2: u8 = 1 << 3;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+/// Depth of the transmit FIFO once enabled.
+const TX_FIFO_SIZE: usize = 16;
+
+/// Fixed-capacity byte queue.
+pub struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        RingBuffer {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Appends `byte`, returning `false` if the buffer is full.
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.buf[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+/// Memory-mapped register block.
+struct Registers {
+    base: usize,
+    shift: usize,
+    /// Registers are 32 bits wide instead of 8.
+    wide: bool,
+}
+
+impl Registers {
+    fn read(&self, reg: usize) -> u8 {
+        let addr = self.base + (reg << self.shift);
+        unsafe {
+            if self.wide {
+                (addr as *const u32).read_volatile() as u8
+            } else {
+                (addr as *const u8).read_volatile()
+            }
+        }
+    }
+
+    fn write(&self, reg: usize, value: u8) {
+        let addr = self.base + (reg << self.shift);
+        unsafe {
+            if self.wide {
+                (addr as *mut u32).write_volatile(value as u32)
+            } else {
+                (addr as *mut u8).write_volatile(value)
+            }
+        }
+    }
+}
+
+struct Inner {
+    regs: Registers,
+    rx: RingBuffer<256>,
+    tx: RingBuffer<1024>,
+}
+
+impl Inner {
+    fn init(&mut self, clock: u32) {
+        let divisor = (clock / (16 * BAUD_RATE)).max(1) as u16;
+        self.regs.write(IER_DLM, 0);
+        self.regs.write(LCR, LCR_DLAB);
+        self.regs.write(RBR_THR_DLL, divisor as u8);
+        self.regs.write(IER_DLM, (divisor >> 8) as u8);
+        self.regs.write(LCR, LCR_8N1);
+        self.regs
+            .write(IIR_FCR, FCR_ENABLE | FCR_CLEAR_RX | FCR_CLEAR_TX);
+        self.regs.write(MCR, MCR_DTR | MCR_RTS | MCR_OUT2);
+        self.regs.write(IER_DLM, IER_RX_AVAILABLE);
+    }
+
+    /// Moves every byte waiting in the receive FIFO into the ring buffer.
+    fn drain_rx(&mut self) {
+        while self.regs.read(LSR) & LSR_DATA_READY != 0 {
+            let byte = self.regs.read(RBR_THR_DLL);
+            // Drop input nobody reads rather than stall the line.
+            self.rx.push(byte);
+        }
+    }
+
+    /// Feeds queued bytes into the transmit FIFO while it has room.
+    fn fill_tx(&mut self) {
+        if self.regs.read(LSR) & LSR_THR_EMPTY == 0 {
+            return;
+        }
+        for _ in 0..TX_FIFO_SIZE {
+            match self.tx.pop() {
+                Some(byte) => self.regs.write(RBR_THR_DLL, byte),
+                None => break,
+            }
+        }
+    }
+
+    fn set_tx_interrupt(&mut self, enabled: bool) {
+        let ier = if enabled {
+            IER_RX_AVAILABLE | IER_TX_EMPTY
+        } else {
+            IER_RX_AVAILABLE
+        };
+        self.regs.write(IER_DLM, ier);
+    }
+}
+
+pub struct Uart {
+    inner: Mutex<Inner>,
+    /// Set once the UART interrupt reaches [`Uart::handle_irq`].
+    irq_enabled: AtomicBool,
+}
+
+impl Uart {
+    /// Queues `bytes` for transmission.
+    ///
+    /// Once the UART interrupt is routed, the rest of the buffer is sent from
+    /// [`Uart::handle_irq`], which runs the next time the hart waits for an
+    /// interrupt or returns to user mode. Until then, this waits until every
+    /// byte has left the FIFO.
+    pub fn write(&self, bytes: &[u8]) {
+        let irq_enabled = self.irq_enabled.load(Ordering::Acquire);
+        let mut inner = self.inner.lock();
+        for byte in bytes {
+            while !inner.tx.push(*byte) {
+                inner.fill_tx();
+            }
+        }
+        inner.fill_tx();
+        if irq_enabled {
+            let pending = !inner.tx.is_empty();
+            inner.set_tx_interrupt(pending);
+        } else {
+            while !inner.tx.is_empty() {
+                inner.fill_tx();
+            }
+        }
+    }
+
+    /// Waits until every queued byte has left the FIFO, for output that must
+    /// not wait for the transmit interrupt, such as before shutdown.
+    pub fn flush(&self) {
+        let mut inner = self.inner.lock();
+        while !inner.tx.is_empty() {
+            inner.fill_tx();
+        }
+        inner.set_tx_interrupt(false);
+    }
+
+    /// Takes the next received byte, if any.
+    pub fn read(&self) -> Option<u8> {
+        let mut inner = self.inner.lock();
+        if !self.irq_enabled.load(Ordering::Acquire) {
+            inner.drain_rx();
+        }
+        inner.rx.pop()
+    }
+
+    /// Waits for the next received byte, sleeping until the UART interrupt
+    /// when it is routed and polling otherwise.
+    pub fn read_wait(&self) -> u8 {
+        loop {
+            if let Some(byte) = self.read() {
+                return byte;
+            }
+            if self.irq_enabled.load(Ordering::Acquire) {
+                trap::wait_for_interrupt();
+            } else {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Services a UART interrupt: buffers input and refills the transmit FIFO.
+    pub fn handle_irq(&self) {
+        let mut inner = self.inner.lock();
+        inner.drain_rx();
+        inner.fill_tx();
+        let pending = !inner.tx.is_empty();
+        inner.set_tx_interrupt(pending);
+    }
+
+    /// Switches from polling to interrupt-driven operation. Called once the
+    /// interrupt controller routes the UART interrupt to [`Uart::handle_irq`].
+    pub fn enable_irq(&self) {
+        self.irq_enabled.store(true, Ordering::Release);
+    }
+}
+
+static UART: OnceBox<Uart> = OnceBox::new();
+
+/// The console UART, once probed.
+pub fn get() -> Option<&'static Uart> {
+    UART.get()
+}
+
+pub struct Ns16550aDriver;
+
+pub static DRIVER: Ns16550aDriver = Ns16550aDriver;
+
+impl Driver for Ns16550aDriver {
+    fn name(&self) -> &'static str {
+        "ns16550a"
+    }
+
+    fn compatible(&self) -> &'static [&'static str] {
+        &["ns16550a", "ns16550"]
+    }
+
+    fn probe(&self, device: &Arc<Device>, node: &Node) -> Result<(), ProbeError> {
+        let region = device.region().ok_or(ProbeError::MissingProperty("reg"))?;
+        let clock = node
+            .prop_u32("clock-frequency")
+            .ok_or(ProbeError::MissingProperty("clock-frequency"))?;
+        let wide = match node.prop_u32("reg-io-width").unwrap_or(1) {
+            1 => false,
+            4 => true,
+            _ => return Err(ProbeError::Unsupported),
+        };
+        let mut inner = Inner {
+            regs: Registers {
+                base: region.base,
+                shift: node.prop_u32("reg-shift").unwrap_or(0) as usize,
+                wide,
+            },
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+        };
+        if UART.get().is_some() {
+            // Only the first UART becomes the console.
+            return Err(ProbeError::Busy);
+        }
+        inner.init(clock);
+        let uart = Uart {
+            inner: Mutex::new(inner),
+            irq_enabled: AtomicBool::new(false),
+        };
+        UART.set(Box::new(uart)).map_err(|_| ProbeError::Busy)?;
+        if let Some(irq) = device.irq() {
+            if plic::register_irq(irq, || UART.get().unwrap().handle_irq()) {
+                UART.get().unwrap().enable_irq();
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,270 @@
+//! RISC-V Platform-Level Interrupt Controller.
+//!
+//! Every hart has an S-mode context in the PLIC. A source is delivered to the
+//! harts whose context enables it; by default that is every hart that is
+//! up, so that a hart waiting for a device wakes up when it interrupts, and
+//! [`set_route`] changes it per hart.
+
+use super::device_tree;
+use super::driver::{Device, Driver, ProbeError};
+use crate::hart;
+use ::alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use dtb::Node;
+use once_cell::race::OnceBox;
+use riscv::register::sie;
+use spin::{Mutex, RwLock};
+
+const PRIORITY_BASE: usize = 0x0;
+const ENABLE_BASE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_BASE: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const CONTEXT_THRESHOLD: usize = 0x0;
+const CONTEXT_CLAIM: usize = 0x4;
+
+/// `interrupts-extended` cause routing a context to S-mode external interrupts.
+const CAUSE_SUPERVISOR_EXTERNAL: u32 = 9;
+
+/// Priority given to every registered source; 0 would mean disabled.
+const DEFAULT_PRIORITY: u32 = 1;
+
+pub type IrqHandler = Arc<dyn Fn() + Send + Sync>;
+
+pub struct Plic {
+    base: usize,
+    /// Number of interrupt sources, source 0 being reserved.
+    ndev: u32,
+    /// S-mode context of each hart.
+    contexts: BTreeMap<usize, usize>,
+    handlers: RwLock<BTreeMap<u32, IrqHandler>>,
+    /// Interrupts taken, by source and hart.
+    counts: Mutex<BTreeMap<(u32, usize), usize>>,
+    /// Serializes read-modify-write of the enable bits.
+    enable_lock: Mutex<()>,
+}
+
+impl Plic {
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { self.reg(offset).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { self.reg(offset).write_volatile(value) }
+    }
+
+    fn set_priority(&self, irq: u32, priority: u32) {
+        self.write(PRIORITY_BASE + irq as usize * 4, priority);
+    }
+
+    fn set_enable(&self, context: usize, irq: u32, enabled: bool) {
+        let offset = ENABLE_BASE + context * ENABLE_STRIDE + (irq as usize / 32) * 4;
+        let bit = 1 << (irq % 32);
+        let _guard = self.enable_lock.lock();
+        let value = self.read(offset);
+        let value = if enabled { value | bit } else { value & !bit };
+        self.write(offset, value);
+    }
+
+    fn context_reg(&self, context: usize, reg: usize) -> usize {
+        CONTEXT_BASE + context * CONTEXT_STRIDE + reg
+    }
+
+    /// Accepts interrupts of any priority on `hart` and enables S-mode
+    /// external interrupts there.
+    fn init_hart(&self, hart: usize) {
+        if let Some(&context) = self.contexts.get(&hart) {
+            self.write(self.context_reg(context, CONTEXT_THRESHOLD), 0);
+            unsafe { sie::set_sext() };
+        }
+    }
+
+    fn claim(&self, context: usize) -> u32 {
+        self.read(self.context_reg(context, CONTEXT_CLAIM))
+    }
+
+    fn complete(&self, context: usize, irq: u32) {
+        self.write(self.context_reg(context, CONTEXT_CLAIM), irq);
+    }
+}
+
+static PLIC: OnceBox<Plic> = OnceBox::new();
+
+/// Installs `handler` for source `irq` and routes it to the harts that are
+/// up.
+///
+/// Returns `false` if there is no PLIC or `irq` is out of range.
+pub fn register_irq(irq: u32, handler: impl Fn() + Send + Sync + 'static) -> bool {
+    let plic = match PLIC.get() {
+        Some(plic) if irq != 0 && irq <= plic.ndev => plic,
+        _ => return false,
+    };
+    plic.handlers.write().insert(irq, Arc::new(handler));
+    plic.set_priority(irq, DEFAULT_PRIORITY);
+    for hart in hart::online().chain([hart::id()]) {
+        set_route(irq, hart, true);
+    }
+    true
+}
+
+/// Removes the handler of `irq` and masks it on every hart.
+#[allow(unused)]
+pub fn unregister_irq(irq: u32) {
+    if let Some(plic) = PLIC.get() {
+        plic.set_priority(irq, 0);
+        for &context in plic.contexts.values() {
+            plic.set_enable(context, irq, false);
+        }
+        plic.handlers.write().remove(&irq);
+    }
+}
+
+/// Delivers (`enabled`) or stops delivering source `irq` to `hart`.
+pub fn set_route(irq: u32, hart: usize, enabled: bool) {
+    if let Some(plic) = PLIC.get() {
+        if let Some(&context) = plic.contexts.get(&hart) {
+            plic.set_enable(context, irq, enabled);
+        }
+    }
+}
+
+/// Prepares the PLIC context of a secondary hart, and routes the sources
+/// with a handler to it. Must run on that hart.
+pub fn init_hart() {
+    if let Some(plic) = PLIC.get() {
+        plic.init_hart(hart::id());
+        for &irq in plic.handlers.read().keys() {
+            set_route(irq, hart::id(), true);
+        }
+    }
+}
+
+/// Services every pending external interrupt of the calling hart.
+pub fn handle_external() {
+    let plic = match PLIC.get() {
+        Some(plic) => plic,
+        None => return,
+    };
+    let context = match plic.contexts.get(&hart::id()) {
+        Some(&context) => context,
+        None => return,
+    };
+    loop {
+        let irq = plic.claim(context);
+        if irq == 0 {
+            break;
+        }
+        *plic.counts.lock().entry((irq, hart::id())).or_insert(0) += 1;
+        let handler = plic.handlers.read().get(&irq).cloned();
+        match handler {
+            Some(handler) => handler(),
+            None => {
+                log!("plic: spurious interrupt {}", irq);
+            }
+        }
+        plic.complete(context, irq);
+    }
+}
+
+/// Harts with an S-mode context, in ascending order.
+pub fn harts() -> Vec<usize> {
+    PLIC.get()
+        .map(|plic| plic.contexts.keys().copied().collect())
+        .unwrap_or_default()
+}
+
+/// Interrupts taken so far by each source with a handler, listed per hart
+/// in the order of [`harts`].
+pub fn irq_counts() -> Vec<(u32, Vec<usize>)> {
+    let plic = match PLIC.get() {
+        Some(plic) => plic,
+        None => return Vec::new(),
+    };
+    let counts = plic.counts.lock();
+    plic.handlers
+        .read()
+        .keys()
+        .map(|&irq| {
+            let per_hart = plic
+                .contexts
+                .keys()
+                .map(|&hart| counts.get(&(irq, hart)).copied().unwrap_or(0))
+                .collect();
+            (irq, per_hart)
+        })
+        .collect()
+}
+
+/// Maps each hart to its S-mode context, following `interrupts-extended`
+/// back to the interrupt controller of each CPU node.
+fn find_contexts(node: &Node) -> BTreeMap<usize, usize> {
+    let mut intc_to_hart = BTreeMap::new();
+    if let Some(cpus) = device_tree::tree().find("/cpus") {
+        for cpu in cpus.children.iter() {
+            let hart = cpu.prop_u32("reg");
+            let intc = cpu
+                .child("interrupt-controller")
+                .and_then(|intc| intc.prop_u32("phandle"));
+            if let (Some(hart), Some(intc)) = (hart, intc) {
+                intc_to_hart.insert(intc, hart as usize);
+            }
+        }
+    }
+    let mut contexts = BTreeMap::new();
+    let cells = node.prop_cells("interrupts-extended").unwrap_or_default();
+    for (context, pair) in cells.chunks_exact(2).enumerate() {
+        if pair[1] == CAUSE_SUPERVISOR_EXTERNAL {
+            if let Some(&hart) = intc_to_hart.get(&pair[0]) {
+                contexts.insert(hart, context);
+            }
+        }
+    }
+    contexts
+}
+
+pub struct PlicDriver;
+
+pub static DRIVER: PlicDriver = PlicDriver;
+
+impl Driver for PlicDriver {
+    fn name(&self) -> &'static str {
+        "plic"
+    }
+
+    fn compatible(&self) -> &'static [&'static str] {
+        &["sifive,plic-1.0.0", "riscv,plic0"]
+    }
+
+    fn probe(&self, device: &Arc<Device>, node: &Node) -> Result<(), ProbeError> {
+        let region = device.region().ok_or(ProbeError::MissingProperty("reg"))?;
+        let ndev = node
+            .prop_u32("riscv,ndev")
+            .ok_or(ProbeError::MissingProperty("riscv,ndev"))?;
+        let contexts = find_contexts(node);
+        if contexts.is_empty() {
+            return Err(ProbeError::MissingProperty("interrupts-extended"));
+        }
+        if PLIC.get().is_some() {
+            return Err(ProbeError::Busy);
+        }
+        let plic = Plic {
+            base: region.base,
+            ndev,
+            contexts,
+            handlers: RwLock::new(BTreeMap::new()),
+            counts: Mutex::new(BTreeMap::new()),
+            enable_lock: Mutex::new(()),
+        };
+        for irq in 1..=ndev {
+            plic.set_priority(irq, 0);
+            for &context in plic.contexts.values() {
+                plic.set_enable(context, irq, false);
+            }
+        }
+        plic.init_hart(hart::id());
+        PLIC.set(Box::new(plic)).map_err(|_| ProbeError::Busy)
+    }
+}
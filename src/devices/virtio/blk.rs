@@ -0,0 +1,395 @@
+//! virtio-blk driver.
+//!
+//! Requests are futures, [`VirtioBlk::read`], [`VirtioBlk::write`] and
+//! [`VirtioBlk::flush`], woken by the device's interrupt, and several may be
+//! in flight at once. The filesystems do not use them that way yet: they
+//! go through [`BlockDevice`], which waits for each request in turn.
+
+use super::{Buffer, Completion, MmioTransport, VirtQueue, QUEUE_SIZE};
+use crate::devices::block::{self, BlockDevice, BlockError, BLOCK_SIZE};
+use crate::devices::driver::{Device, ProbeError};
+use crate::devices::plic;
+use crate::mm::{frame_alloc, FrameTracker, PAGE_SIZE};
+use crate::task;
+use ::alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::future::poll_fn;
+use core::task::{Context, Poll, Waker};
+use spin::{Mutex, MutexGuard};
+
+/// The device is read-only.
+const F_RO: u64 = 1 << 5;
+/// The device supports the flush command.
+const F_FLUSH: u64 = 1 << 9;
+
+/// Offset of `capacity`, in 512-byte sectors, in the configuration space.
+const CONFIG_CAPACITY: usize = 0;
+
+const REQ_IN: u32 = 0;
+const REQ_OUT: u32 = 1;
+const REQ_FLUSH: u32 = 4;
+
+const STATUS_OK: u8 = 0;
+const STATUS_UNSUPPORTED: u8 = 2;
+
+#[repr(C)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// Requests in flight at once, each taking at least two descriptors.
+const SLOTS: usize = QUEUE_SIZE / 2;
+
+/// Size of a request's slot of DMA memory, several of which share a frame.
+const SLOT_SIZE: usize = 1024;
+
+/// Offsets in a slot.
+const HEADER_OFFSET: usize = 0;
+const STATUS_OFFSET: usize = 16;
+const DATA_OFFSET: usize = 512;
+
+/// What a slot of DMA memory is used for.
+enum Slot {
+    Free,
+    /// The device is working on the slot's request; the waker is that of
+    /// the request waiting for it, once it has been polled.
+    InFlight(Option<Waker>),
+    /// The device has finished the request, which has yet to take the
+    /// results.
+    Done,
+    /// The device is working on a request that nobody waits for anymore,
+    /// and the slot is freed once it is done.
+    Abandoned,
+}
+
+struct Inner {
+    queue: VirtQueue,
+    /// Slots of the chains published to the device, by the chain's head.
+    heads: BTreeMap<u16, usize>,
+    slots: [Slot; SLOTS],
+    /// Wakers of requests waiting for a slot or room in the queue.
+    waiting: Vec<Waker>,
+}
+
+pub struct VirtioBlk {
+    transport: MmioTransport,
+    inner: Mutex<Inner>,
+    /// Memory for the slots: each holds a request's header, status byte and
+    /// a bounce buffer for its data, so the device never touches memory the
+    /// kernel may free or remap.
+    dma: Vec<FrameTracker>,
+    capacity: usize,
+    features: u64,
+    completion: Mutex<Completion>,
+}
+
+/// A request published to the device, which gives back its slot when
+/// dropped: right away if the device is done with it, and otherwise once it
+/// is.
+struct Submitted<'a> {
+    blk: &'a VirtioBlk,
+    slot: usize,
+}
+
+impl Drop for Submitted<'_> {
+    fn drop(&mut self) {
+        let waiting = {
+            let mut inner = self.blk.inner.lock();
+            let slot = &mut inner.slots[self.slot];
+            if !matches!(slot, Slot::Done) {
+                *slot = Slot::Abandoned;
+                return;
+            }
+            *slot = Slot::Free;
+            core::mem::take(&mut inner.waiting)
+        };
+        for waker in waiting {
+            waker.wake();
+        }
+    }
+}
+
+impl VirtioBlk {
+    fn slot_pa(&self, slot: usize, offset: usize) -> usize {
+        let frame = &self.dma[slot * SLOT_SIZE / PAGE_SIZE];
+        frame.pa().0 + slot * SLOT_SIZE % PAGE_SIZE + offset
+    }
+
+    /// Issues one request and waits for it. For reads and writes `data` is
+    /// the block to transfer.
+    async fn request(
+        &self,
+        kind: u32,
+        sector: usize,
+        data: Option<&mut [u8]>,
+    ) -> Result<(), BlockError> {
+        let slot = poll_fn(|cx| self.poll_submit(kind, sector, data.as_deref(), cx)).await;
+        let submitted = Submitted { blk: self, slot };
+        poll_fn(|cx| self.poll_done(slot, cx)).await;
+
+        let status = unsafe { (self.slot_pa(slot, STATUS_OFFSET) as *const u8).read_volatile() };
+        let result = match status {
+            STATUS_OK => {
+                if let (Some(data), REQ_IN) = (data, kind) {
+                    unsafe {
+                        core::ptr::copy_nonoverlapping(
+                            self.slot_pa(slot, DATA_OFFSET) as *const u8,
+                            data.as_mut_ptr(),
+                            BLOCK_SIZE,
+                        )
+                    };
+                }
+                Ok(())
+            }
+            STATUS_UNSUPPORTED => Err(BlockError::Unsupported),
+            _ => Err(BlockError::Io),
+        };
+        drop(submitted);
+        result
+    }
+
+    /// Fills a free slot with a request and publishes it to the device, or
+    /// waits for a slot and room in the queue. Returns the slot.
+    fn poll_submit(
+        &self,
+        kind: u32,
+        sector: usize,
+        data: Option<&[u8]>,
+        cx: &mut Context,
+    ) -> Poll<usize> {
+        let polling = *self.completion.lock() == Completion::Polling;
+        if polling {
+            self.collect();
+        }
+        let mut inner = self.inner.lock();
+        let Some(slot) = inner
+            .slots
+            .iter()
+            .position(|slot| matches!(slot, Slot::Free))
+        else {
+            return self.wait_for_room(inner, polling, cx);
+        };
+        let header = self.slot_pa(slot, HEADER_OFFSET);
+        let status = self.slot_pa(slot, STATUS_OFFSET);
+        let bounce = self.slot_pa(slot, DATA_OFFSET);
+        unsafe {
+            (header as *mut RequestHeader).write_volatile(RequestHeader {
+                kind,
+                reserved: 0,
+                sector: sector as u64,
+            });
+            (status as *mut u8).write_volatile(0xff);
+        }
+        let header = Buffer {
+            pa: header,
+            len: core::mem::size_of::<RequestHeader>() as u32,
+            device_writable: false,
+        };
+        let status_buffer = Buffer {
+            pa: status,
+            len: 1,
+            device_writable: true,
+        };
+        let added = match data {
+            Some(data) => {
+                if kind == REQ_OUT {
+                    unsafe {
+                        core::ptr::copy_nonoverlapping(data.as_ptr(), bounce as *mut u8, BLOCK_SIZE)
+                    };
+                }
+                let data_buffer = Buffer {
+                    pa: bounce,
+                    len: BLOCK_SIZE as u32,
+                    device_writable: kind == REQ_IN,
+                };
+                inner.queue.add(&[header, data_buffer, status_buffer])
+            }
+            None => inner.queue.add(&[header, status_buffer]),
+        };
+        let Some(head) = added else {
+            return self.wait_for_room(inner, polling, cx);
+        };
+        inner.slots[slot] = Slot::InFlight(None);
+        inner.heads.insert(head, slot);
+        drop(inner);
+        self.transport.notify(0);
+        Poll::Ready(slot)
+    }
+
+    fn wait_for_room<T>(
+        &self,
+        mut inner: MutexGuard<Inner>,
+        polling: bool,
+        cx: &mut Context,
+    ) -> Poll<T> {
+        inner.waiting.push(cx.waker().clone());
+        drop(inner);
+        // Without an interrupt, nothing else checks the used ring.
+        if polling {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+
+    /// Whether the device has finished the request in `slot`.
+    fn poll_done(&self, slot: usize, cx: &mut Context) -> Poll<()> {
+        let polling = *self.completion.lock() == Completion::Polling;
+        if polling {
+            self.collect();
+        }
+        let mut inner = self.inner.lock();
+        let Slot::InFlight(waker) = &mut inner.slots[slot] else {
+            return Poll::Ready(());
+        };
+        *waker = Some(cx.waker().clone());
+        drop(inner);
+        if polling {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+
+    /// Marks the requests the device has finished as done and wakes them,
+    /// frees the slots of those nobody waits for, and wakes the requests
+    /// waiting for the room they leave.
+    fn collect(&self) {
+        let mut wakers = Vec::new();
+        {
+            let mut inner = self.inner.lock();
+            let mut freed = false;
+            while let Some((head, _)) = inner.queue.pop_used() {
+                freed = true;
+                let Some(index) = inner.heads.remove(&head) else {
+                    continue;
+                };
+                let slot = &mut inner.slots[index];
+                match core::mem::replace(slot, Slot::Done) {
+                    Slot::InFlight(waker) => wakers.extend(waker),
+                    Slot::Abandoned => *slot = Slot::Free,
+                    other => *slot = other,
+                }
+            }
+            if freed {
+                wakers.append(&mut inner.waiting);
+            }
+        }
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    fn check(&self, block_id: usize, len: usize) -> Result<(), BlockError> {
+        if len != BLOCK_SIZE {
+            return Err(BlockError::BadBuffer);
+        }
+        if block_id >= self.capacity {
+            return Err(BlockError::OutOfRange);
+        }
+        Ok(())
+    }
+
+    /// Switches between polled and interrupt-driven completion.
+    pub fn set_completion(&self, completion: Completion) {
+        *self.completion.lock() = completion;
+    }
+
+    fn handle_irq(&self) {
+        self.transport.ack_interrupt();
+        self.collect();
+    }
+
+    /// Reads block `block_id` into `buf`.
+    pub async fn read(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check(block_id, buf.len())?;
+        self.request(REQ_IN, block_id, Some(buf)).await
+    }
+
+    /// Writes `buf` to block `block_id`.
+    pub async fn write(&self, block_id: usize, buf: &[u8]) -> Result<(), BlockError> {
+        self.check(block_id, buf.len())?;
+        if self.features & F_RO != 0 {
+            return Err(BlockError::ReadOnly);
+        }
+        // The request only reads from `data` for writes.
+        let mut data = [0u8; BLOCK_SIZE];
+        data.copy_from_slice(buf);
+        self.request(REQ_OUT, block_id, Some(&mut data)).await
+    }
+
+    /// Waits for the blocks written so far to reach the disk.
+    pub async fn flush(&self) -> Result<(), BlockError> {
+        if self.features & F_FLUSH == 0 {
+            return Ok(());
+        }
+        self.request(REQ_FLUSH, 0, None).await
+    }
+}
+
+// Filesystems call these holding spin locks, so each request is waited for
+// with the hart kept, rather than with the task switched out, and nothing
+// else runs on the hart meanwhile.
+impl BlockDevice for VirtioBlk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlockError> {
+        task::poll_in_place(self.read(block_id, buf))
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), BlockError> {
+        task::poll_in_place(self.write(block_id, buf))
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.capacity
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        task::poll_in_place(VirtioBlk::flush(self))
+    }
+}
+
+pub fn probe(transport: MmioTransport, device: &Arc<Device>) -> Result<(), ProbeError> {
+    let features = transport.begin_init(F_RO | F_FLUSH)?;
+    let queue = VirtQueue::new().ok_or(ProbeError::Busy)?;
+    let dma = (0..(SLOTS * SLOT_SIZE).div_ceil(PAGE_SIZE))
+        .map(|_| frame_alloc())
+        .collect::<Option<Vec<_>>>()
+        .ok_or(ProbeError::Busy)?;
+    if let Err(err) = transport.setup_queue(0, &queue) {
+        transport.fail();
+        return Err(err);
+    }
+    transport.finish_init();
+    let capacity = transport.config_u64(CONFIG_CAPACITY) as usize;
+    let blk = Arc::new(VirtioBlk {
+        transport,
+        inner: Mutex::new(Inner {
+            queue,
+            heads: BTreeMap::new(),
+            slots: core::array::from_fn(|_| Slot::Free),
+            waiting: Vec::new(),
+        }),
+        dma,
+        capacity,
+        features,
+        completion: Mutex::new(Completion::Polling),
+    });
+    if let Some(irq) = device.irq() {
+        let handler = blk.clone();
+        if plic::register_irq(irq, move || handler.handle_irq()) {
+            blk.set_completion(Completion::Interrupt);
+        }
+    }
+    let index = block::register(blk);
+    log!(
+        "virtio-blk: {} at vd{}, {} blocks{}",
+        device.path,
+        (b'a' + index as u8) as char,
+        capacity,
+        if features & F_RO != 0 {
+            ", read-only"
+        } else {
+            ""
+        }
+    );
+    Ok(())
+}
@@ -0,0 +1,215 @@
+//! VirtIO over memory-mapped I/O.
+//!
+//! Both the legacy (version 1) interface QEMU uses by default and the
+//! version 2 interface are supported.
+
+mod blk;
+mod queue;
+
+pub use queue::{Buffer, VirtQueue, QUEUE_SIZE};
+
+use super::driver::{Device, Driver, ProbeError};
+use crate::mm::PAGE_SIZE;
+use ::alloc::sync::Arc;
+use dtb::Node;
+
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_ALIGN: usize = 0x03c;
+const QUEUE_PFN: usize = 0x040;
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const CONFIG: usize = 0x100;
+
+/// "virt" in little endian.
+const MAGIC: u32 = 0x7472_6976;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_FAILED: u32 = 128;
+
+/// Set by devices conforming to version 1.0 of the specification.
+pub const F_VERSION_1: u64 = 1 << 32;
+
+const DEVICE_ID_BLOCK: u32 = 2;
+
+/// How a driver learns that the device finished a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Completion {
+    /// Check the used ring each time the request is polled.
+    Polling,
+    /// Wait for the device's interrupt to wake the request.
+    Interrupt,
+}
+
+/// Register interface of one virtio-mmio slot.
+pub struct MmioTransport {
+    base: usize,
+    version: u32,
+}
+
+impl MmioTransport {
+    /// Checks the slot at `base` and returns it with its device ID, which is
+    /// 0 for an empty slot.
+    pub fn new(base: usize) -> Result<(Self, u32), ProbeError> {
+        let transport = MmioTransport { base, version: 0 };
+        if transport.read(MAGIC_VALUE) != MAGIC {
+            return Err(ProbeError::Unsupported);
+        }
+        let version = transport.read(VERSION);
+        if version != 1 && version != 2 {
+            return Err(ProbeError::Unsupported);
+        }
+        let device_id = transport.read(DEVICE_ID);
+        Ok((MmioTransport { base, version }, device_id))
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+
+    fn is_legacy(&self) -> bool {
+        self.version == 1
+    }
+
+    /// Resets the device and negotiates features, returning those accepted
+    /// by both sides.
+    pub fn begin_init(&self, supported: u64) -> Result<u64, ProbeError> {
+        self.write(STATUS, 0);
+        self.write(STATUS, STATUS_ACKNOWLEDGE);
+        self.write(STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        self.write(DEVICE_FEATURES_SEL, 0);
+        let mut device_features = self.read(DEVICE_FEATURES) as u64;
+        self.write(DEVICE_FEATURES_SEL, 1);
+        device_features |= (self.read(DEVICE_FEATURES) as u64) << 32;
+
+        let supported = if self.is_legacy() {
+            supported & !F_VERSION_1
+        } else {
+            supported | F_VERSION_1
+        };
+        let features = device_features & supported;
+        self.write(DRIVER_FEATURES_SEL, 0);
+        self.write(DRIVER_FEATURES, features as u32);
+        self.write(DRIVER_FEATURES_SEL, 1);
+        self.write(DRIVER_FEATURES, (features >> 32) as u32);
+
+        if self.is_legacy() {
+            self.write(GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        } else {
+            let status = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK;
+            self.write(STATUS, status);
+            if self.read(STATUS) & STATUS_FEATURES_OK == 0 {
+                self.fail();
+                return Err(ProbeError::Unsupported);
+            }
+        }
+        Ok(features)
+    }
+
+    /// Tells the device the driver is ready.
+    pub fn finish_init(&self) {
+        let status = self.read(STATUS);
+        self.write(STATUS, status | STATUS_DRIVER_OK);
+    }
+
+    /// Gives up on the device.
+    pub fn fail(&self) {
+        let status = self.read(STATUS);
+        self.write(STATUS, status | STATUS_FAILED);
+    }
+
+    /// Hands `queue` to the device as queue number `index`.
+    pub fn setup_queue(&self, index: u32, queue: &VirtQueue) -> Result<(), ProbeError> {
+        self.write(QUEUE_SEL, index);
+        let max = self.read(QUEUE_NUM_MAX) as usize;
+        if max < QUEUE_SIZE {
+            return Err(ProbeError::Unsupported);
+        }
+        self.write(QUEUE_NUM, QUEUE_SIZE as u32);
+        if self.is_legacy() {
+            self.write(QUEUE_ALIGN, PAGE_SIZE as u32);
+            self.write(QUEUE_PFN, (queue.desc_pa() / PAGE_SIZE) as u32);
+        } else {
+            let write64 = |low, high, value: usize| {
+                self.write(low, value as u32);
+                self.write(high, (value >> 32) as u32);
+            };
+            write64(QUEUE_DESC_LOW, QUEUE_DESC_HIGH, queue.desc_pa());
+            write64(QUEUE_DRIVER_LOW, QUEUE_DRIVER_HIGH, queue.avail_pa());
+            write64(QUEUE_DEVICE_LOW, QUEUE_DEVICE_HIGH, queue.used_pa());
+            self.write(QUEUE_READY, 1);
+        }
+        Ok(())
+    }
+
+    pub fn notify(&self, index: u32) {
+        self.write(QUEUE_NOTIFY, index);
+    }
+
+    /// Acknowledges a pending interrupt, returning its cause bits.
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.read(INTERRUPT_STATUS);
+        if status != 0 {
+            self.write(INTERRUPT_ACK, status);
+        }
+        status
+    }
+
+    pub fn config_u32(&self, offset: usize) -> u32 {
+        self.read(CONFIG + offset)
+    }
+
+    pub fn config_u64(&self, offset: usize) -> u64 {
+        self.config_u32(offset) as u64 | (self.config_u32(offset + 4) as u64) << 32
+    }
+}
+
+pub struct VirtioMmioDriver;
+
+pub static DRIVER: VirtioMmioDriver = VirtioMmioDriver;
+
+impl Driver for VirtioMmioDriver {
+    fn name(&self) -> &'static str {
+        "virtio-mmio"
+    }
+
+    fn compatible(&self) -> &'static [&'static str] {
+        &["virtio,mmio"]
+    }
+
+    fn probe(&self, device: &Arc<Device>, _node: &Node) -> Result<(), ProbeError> {
+        let region = device.region().ok_or(ProbeError::MissingProperty("reg"))?;
+        let (transport, device_id) = MmioTransport::new(region.base)?;
+        match device_id {
+            0 => Err(ProbeError::Absent),
+            DEVICE_ID_BLOCK => blk::probe(transport, device),
+            _ => Err(ProbeError::Unsupported),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Split virtqueues.
+
+use crate::mm::{frame_alloc_contiguous, FrameTracker, PAGE_SIZE};
+use core::sync::atomic::{fence, Ordering};
+
+/// Number of descriptors in every queue.
+pub const QUEUE_SIZE: usize = 16;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct AvailRing {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+    used_event: u16,
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C)]
+struct UsedRing {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; QUEUE_SIZE],
+    avail_event: u16,
+}
+
+/// Offset of the available ring, right after the descriptor table.
+const AVAIL_OFFSET: usize = core::mem::size_of::<Descriptor>() * QUEUE_SIZE;
+/// Offset of the used ring. Legacy devices require it on its own page.
+const USED_OFFSET: usize = PAGE_SIZE;
+
+/// A buffer handed to the device, by physical address.
+#[derive(Clone, Copy)]
+pub struct Buffer {
+    pub pa: usize,
+    pub len: u32,
+    /// Whether the device writes to the buffer rather than reads it.
+    pub device_writable: bool,
+}
+
+pub struct VirtQueue {
+    /// Two contiguous frames: descriptors and available ring in the first,
+    /// used ring in the second, matching the legacy layout.
+    frames: FrameTracker,
+    free_head: u16,
+    num_free: usize,
+    /// Next available index to publish.
+    avail_idx: u16,
+    /// Used index up to which completions have been collected.
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    pub fn new() -> Option<Self> {
+        let frames = frame_alloc_contiguous(2)?;
+        let queue = VirtQueue {
+            frames,
+            free_head: 0,
+            num_free: QUEUE_SIZE,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..QUEUE_SIZE {
+            unsafe { (*queue.desc(i)).next = (i + 1) as u16 };
+        }
+        Some(queue)
+    }
+
+    pub fn desc_pa(&self) -> usize {
+        self.frames.pa().0
+    }
+
+    pub fn avail_pa(&self) -> usize {
+        self.desc_pa() + AVAIL_OFFSET
+    }
+
+    pub fn used_pa(&self) -> usize {
+        self.desc_pa() + USED_OFFSET
+    }
+
+    fn desc(&self, i: usize) -> *mut Descriptor {
+        (self.desc_pa() as *mut Descriptor).wrapping_add(i)
+    }
+
+    fn avail(&self) -> *mut AvailRing {
+        self.avail_pa() as *mut AvailRing
+    }
+
+    fn used(&self) -> *mut UsedRing {
+        self.used_pa() as *mut UsedRing
+    }
+
+    /// Chains `buffers` into descriptors and publishes them to the device.
+    /// Returns the head descriptor, or `None` if the queue is too full.
+    pub fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.num_free {
+            return None;
+        }
+        let head = self.free_head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let index = self.free_head;
+            let desc = self.desc(index as usize);
+            unsafe {
+                (*desc).addr = buffer.pa as u64;
+                (*desc).len = buffer.len;
+                (*desc).flags = if buffer.device_writable {
+                    DESC_F_WRITE
+                } else {
+                    0
+                };
+                if i + 1 < buffers.len() {
+                    (*desc).flags |= DESC_F_NEXT;
+                }
+                self.free_head = (*desc).next;
+            }
+        }
+        self.num_free -= buffers.len();
+        unsafe {
+            let avail = self.avail();
+            let slot = self.avail_idx as usize % QUEUE_SIZE;
+            core::ptr::addr_of_mut!((*avail).ring[slot]).write_volatile(head);
+            // The descriptors must be visible before the index moves.
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            core::ptr::addr_of_mut!((*avail).idx).write_volatile(self.avail_idx);
+            fence(Ordering::SeqCst);
+        }
+        Some(head)
+    }
+
+    /// Whether the device has completed a chain not yet collected.
+    pub fn can_pop(&self) -> bool {
+        fence(Ordering::SeqCst);
+        let used_idx = unsafe { core::ptr::addr_of!((*self.used()).idx).read_volatile() };
+        used_idx != self.last_used_idx
+    }
+
+    /// Collects the next completed chain, returning its head and the number
+    /// of bytes the device wrote.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.can_pop() {
+            return None;
+        }
+        let slot = self.last_used_idx as usize % QUEUE_SIZE;
+        let (id, len) = unsafe {
+            let elem = core::ptr::addr_of!((*self.used()).ring[slot]);
+            (
+                core::ptr::addr_of!((*elem).id).read_volatile() as u16,
+                core::ptr::addr_of!((*elem).len).read_volatile(),
+            )
+        };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        self.free_chain(id);
+        Some((id, len))
+    }
+
+    fn free_chain(&mut self, head: u16) {
+        let mut index = head;
+        loop {
+            let desc = self.desc(index as usize);
+            self.num_free += 1;
+            let (flags, next) = unsafe { ((*desc).flags, (*desc).next) };
+            if flags & DESC_F_NEXT == 0 {
+                unsafe { (*desc).next = self.free_head };
+                break;
+            }
+            index = next;
+        }
+        self.free_head = head;
+    }
+}
+
+// The queue memory is only reached through `&mut self` or the device.
+unsafe impl Send for VirtQueue {}
@@ -0,0 +1,271 @@
+//! Device files, mounted at `/dev`.
+//!
+//! The directory has no state of its own: every lookup lists the devices the
+//! drivers have registered, so nodes for newly probed hardware appear
+//! without being created. Opening a node gives a file whose reads, writes
+//! and ioctls go to the driver.
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Metadata, Result};
+use crate::devices::block::{self, BlockDevice, BLOCK_SIZE};
+use crate::devices::{block_cache, console, ns16550a};
+use ::alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+const ROOT_INO: u64 = 1;
+/// Block devices are numbered from here, in registration order.
+const BLOCK_INO_BASE: u64 = 16;
+
+// ioctl requests, with the values Linux uses.
+const TIOCGWINSZ: u32 = 0x5413;
+const BLKFLSBUF: u32 = 0x1261;
+const BLKSSZGET: u32 = 0x1268;
+const BLKGETSIZE64: u32 = 0x8008_1272;
+
+enum Device {
+    /// The kernel console, wherever it currently goes.
+    Console,
+    Serial(&'static ns16550a::Uart),
+    Null,
+    Zero,
+    Random,
+    Block(Arc<dyn BlockDevice>),
+}
+
+struct DevNode {
+    ino: u64,
+    device: Device,
+}
+
+/// Every device node, by name.
+fn nodes() -> Vec<(String, DevNode)> {
+    let mut nodes = Vec::new();
+    let mut add = |name: &str, ino: u64, device: Device| {
+        nodes.push((String::from(name), DevNode { ino, device }))
+    };
+    add("console", 2, Device::Console);
+    if let Some(uart) = ns16550a::get() {
+        add("ttyS0", 3, Device::Serial(uart));
+    }
+    add("null", 4, Device::Null);
+    add("zero", 5, Device::Zero);
+    add("urandom", 6, Device::Random);
+    let mut index = 0;
+    while let Some(device) = block::get(index) {
+        // vda to vdz, as Linux names virtio disks.
+        if index < 26 {
+            let name = format!("vd{}", (b'a' + index as u8) as char);
+            add(&name, BLOCK_INO_BASE + index as u64, Device::Block(device));
+        }
+        index += 1;
+    }
+    nodes
+}
+
+/// Source of `/dev/urandom`: splitmix64, seeded from the time counter on
+/// first use. It is not suitable for cryptography.
+pub fn random_u64() -> u64 {
+    static STATE: AtomicU64 = AtomicU64::new(0);
+    const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+    let _ = STATE.compare_exchange(
+        0,
+        riscv::register::time::read() as u64 | 1,
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+    );
+    let mut z = STATE
+        .fetch_add(GAMMA, Ordering::Relaxed)
+        .wrapping_add(GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Reads from a terminal: waits for the first byte, then takes whatever
+/// else has already arrived.
+fn read_tty(buf: &mut [u8], wait: impl Fn() -> u8, poll: impl Fn() -> Option<u8>) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+    buf[0] = wait();
+    let mut len = 1;
+    while len < buf.len() {
+        match poll() {
+            Some(byte) => buf[len] = byte,
+            None => break,
+        }
+        len += 1;
+    }
+    len
+}
+
+impl DevNode {
+    fn kind(&self) -> FileType {
+        match self.device {
+            Device::Block(_) => FileType::BlockDevice,
+            _ => FileType::CharDevice,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match &self.device {
+            Device::Block(device) => device.num_blocks() * BLOCK_SIZE,
+            _ => 0,
+        }
+    }
+}
+
+impl Inode for DevNode {
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            ino: self.ino,
+            kind: self.kind(),
+            size: self.size() as u64,
+            nlink: 1,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        match &self.device {
+            Device::Console => Ok(read_tty(buf, console::getchar_wait, console::getchar)),
+            Device::Serial(uart) => Ok(read_tty(buf, || uart.read_wait(), || uart.read())),
+            Device::Null => Ok(0),
+            Device::Zero => {
+                buf.fill(0);
+                Ok(buf.len())
+            }
+            Device::Random => {
+                for chunk in buf.chunks_mut(8) {
+                    chunk.copy_from_slice(&random_u64().to_ne_bytes()[..chunk.len()]);
+                }
+                Ok(buf.len())
+            }
+            Device::Block(device) => {
+                // Through the cache, so mounted filesystems see the same data.
+                let size = self.size();
+                if offset >= size {
+                    return Ok(0);
+                }
+                let len = buf.len().min(size - offset);
+                let mut done = 0;
+                while done < len {
+                    let pos = offset + done;
+                    let in_block = pos % BLOCK_SIZE;
+                    let n = (BLOCK_SIZE - in_block).min(len - done);
+                    let cache = block_cache::get(pos / BLOCK_SIZE, device)?;
+                    buf[done..done + n]
+                        .copy_from_slice(&cache.lock().data()[in_block..in_block + n]);
+                    done += n;
+                }
+                Ok(len)
+            }
+        }
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        match &self.device {
+            Device::Console => console::write(buf),
+            Device::Serial(uart) => uart.write(buf),
+            Device::Null | Device::Zero | Device::Random => {}
+            Device::Block(device) => {
+                let size = self.size();
+                if buf.is_empty() {
+                    return Ok(0);
+                }
+                if offset >= size {
+                    return Err(FsError::NoSpace);
+                }
+                let len = buf.len().min(size - offset);
+                let mut done = 0;
+                while done < len {
+                    let pos = offset + done;
+                    let in_block = pos % BLOCK_SIZE;
+                    let n = (BLOCK_SIZE - in_block).min(len - done);
+                    let cache = block_cache::get(pos / BLOCK_SIZE, device)?;
+                    cache.lock().data_mut()[in_block..in_block + n]
+                        .copy_from_slice(&buf[done..done + n]);
+                    done += n;
+                }
+                return Ok(len);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn ioctl(&self, request: u32) -> Result<Vec<u8>> {
+        match (&self.device, request) {
+            (Device::Console | Device::Serial(_), TIOCGWINSZ) => {
+                // struct winsize: rows, columns, and pixel sizes left unknown.
+                Ok([24u16, 80, 0, 0]
+                    .iter()
+                    .flat_map(|field| field.to_ne_bytes())
+                    .collect())
+            }
+            (Device::Block(_), BLKGETSIZE64) => Ok((self.size() as u64).to_ne_bytes().to_vec()),
+            (Device::Block(_), BLKSSZGET) => Ok((BLOCK_SIZE as i32).to_ne_bytes().to_vec()),
+            (Device::Block(device), BLKFLSBUF) => {
+                block_cache::sync_all()?;
+                device.flush()?;
+                Ok(Vec::new())
+            }
+            _ => Err(FsError::NotTty),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+struct DevDir;
+
+impl Inode for DevDir {
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            ino: ROOT_INO,
+            kind: FileType::Directory,
+            size: nodes().len() as u64,
+            nlink: 2,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        nodes()
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, node)| Arc::new(node) as Arc<dyn Inode>)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>> {
+        Err(FsError::NotSupported)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn read_dir(&self, index: usize) -> Result<Option<DirEntry>> {
+        Ok(nodes().into_iter().nth(index).map(|(name, node)| DirEntry {
+            name,
+            ino: node.ino,
+            kind: node.kind(),
+        }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct DevFs;
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(DevDir)
+    }
+}
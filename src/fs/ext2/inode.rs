@@ -0,0 +1,294 @@
+//! ext2 inodes, block maps and directories.
+
+use super::{le_u16, le_u32, Ext2};
+use crate::fs::{DirEntry, FileType, FsError, Inode, Metadata, Result};
+use ::alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::any::Any;
+
+/// Bytes of the on-disk inode this driver reads.
+const INODE_READ_SIZE: usize = 128;
+
+const DIRECT_BLOCKS: usize = 12;
+const INDIRECT: usize = 12;
+const DOUBLE_INDIRECT: usize = 13;
+const TRIPLE_INDIRECT: usize = 14;
+
+const S_IFMT: u16 = 0xf000;
+const S_IFCHR: u16 = 0x2000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFBLK: u16 = 0x6000;
+const S_IFREG: u16 = 0x8000;
+const S_IFLNK: u16 = 0xa000;
+
+/// The inode maps its data with extents instead of blocks.
+const EXTENTS_FL: u32 = 0x0008_0000;
+
+/// Targets shorter than this are stored in the block map itself.
+const FAST_SYMLINK_MAX: usize = 60;
+
+/// Directory entry file types, used when the `filetype` feature is on.
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_CHRDEV: u8 = 3;
+const FT_BLKDEV: u8 = 4;
+const FT_SYMLINK: u8 = 7;
+
+/// Fields of an on-disk inode.
+pub struct DiskInode {
+    mode: u16,
+    size: u64,
+    links_count: u16,
+    /// Size in 512-byte units, including metadata blocks.
+    sectors: u32,
+    flags: u32,
+    block: [u32; 15],
+    file_acl: u32,
+}
+
+impl DiskInode {
+    fn parse(raw: &[u8; INODE_READ_SIZE]) -> Self {
+        let mode = le_u16(raw, 0);
+        let size_high = if mode & S_IFMT == S_IFREG {
+            le_u32(raw, 108)
+        } else {
+            0
+        };
+        let mut block = [0u32; 15];
+        for (i, b) in block.iter_mut().enumerate() {
+            *b = le_u32(raw, 40 + i * 4);
+        }
+        DiskInode {
+            mode,
+            size: le_u32(raw, 4) as u64 | (size_high as u64) << 32,
+            links_count: le_u16(raw, 26),
+            sectors: le_u32(raw, 28),
+            flags: le_u32(raw, 32),
+            block,
+            file_acl: le_u32(raw, 104),
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    fn kind(&self) -> FileType {
+        match self.mode & S_IFMT {
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            // FIFOs and sockets read as empty regular files.
+            _ => FileType::Regular,
+        }
+    }
+}
+
+pub struct Ext2Inode {
+    fs: Arc<Ext2>,
+    ino: u32,
+}
+
+impl Ext2Inode {
+    pub fn new(fs: Arc<Ext2>, ino: u32) -> Self {
+        Ext2Inode { fs, ino }
+    }
+
+    pub fn disk(&self) -> Result<DiskInode> {
+        let mut raw = [0u8; INODE_READ_SIZE];
+        self.fs.read_inode(self.ino, &mut raw)?;
+        Ok(DiskInode::parse(&raw))
+    }
+
+    /// The filesystem block holding block `index` of the file, 0 for a hole.
+    fn map_block(&self, disk: &DiskInode, index: usize) -> Result<u32> {
+        if disk.flags & EXTENTS_FL != 0 {
+            return Err(FsError::Incompatible);
+        }
+        if index < DIRECT_BLOCKS {
+            return Ok(disk.block[index]);
+        }
+        let per_block = self.fs.sb.block_size / 4;
+        let mut index = index - DIRECT_BLOCKS;
+        // Number of indirections and the span of each entry at the top level.
+        let mut levels = None;
+        let mut span = 1;
+        for (level, slot) in [INDIRECT, DOUBLE_INDIRECT, TRIPLE_INDIRECT]
+            .into_iter()
+            .enumerate()
+        {
+            span *= per_block;
+            if index < span {
+                levels = Some((level + 1, slot));
+                break;
+            }
+            index -= span;
+        }
+        let (levels, slot) = levels.ok_or(FsError::InvalidArgument)?;
+        let mut block = disk.block[slot];
+        let mut span = span / per_block;
+        for _ in 0..levels {
+            if block == 0 {
+                return Ok(0);
+            }
+            let mut entry = [0u8; 4];
+            self.fs.read_block(block, (index / span) * 4, &mut entry)?;
+            block = u32::from_le_bytes(entry);
+            index %= span;
+            span = (span / per_block).max(1);
+        }
+        Ok(block)
+    }
+
+    /// Reads file data at `offset`, stopping at the end of the file.
+    fn read_data(&self, disk: &DiskInode, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let size = disk.size as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        let block_size = self.fs.sb.block_size;
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let in_block = pos % block_size;
+            let n = (block_size - in_block).min(len - done);
+            let out = &mut buf[done..done + n];
+            match self.map_block(disk, pos / block_size)? {
+                0 => out.fill(0),
+                block => self.fs.read_block(block, in_block, out)?,
+            }
+            done += n;
+        }
+        Ok(len)
+    }
+
+    /// Every entry of the directory, except `.` and `..`.
+    fn entries(&self) -> Result<Vec<DirEntry>> {
+        let disk = self.disk()?;
+        if !disk.is_dir() {
+            return Err(FsError::NotDir);
+        }
+        let mut data = vec![0u8; disk.size as usize];
+        self.read_data(&disk, 0, &mut data)?;
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos + 8 <= data.len() {
+            let ino = le_u32(&data, pos);
+            let rec_len = le_u16(&data, pos + 4) as usize;
+            let (name_len, file_type) = if self.fs.has_filetype() {
+                (data[pos + 6] as usize, Some(data[pos + 7]))
+            } else {
+                (le_u16(&data, pos + 6) as usize, None)
+            };
+            if rec_len < 8 || pos + rec_len > data.len() || 8 + name_len > rec_len {
+                return Err(FsError::Corrupted);
+            }
+            let name = &data[pos + 8..pos + 8 + name_len];
+            if ino != 0 && name != b"." && name != b".." {
+                let kind = match file_type {
+                    Some(FT_REG_FILE) => FileType::Regular,
+                    Some(FT_DIR) => FileType::Directory,
+                    Some(FT_CHRDEV) => FileType::CharDevice,
+                    Some(FT_BLKDEV) => FileType::BlockDevice,
+                    Some(FT_SYMLINK) => FileType::Symlink,
+                    // The entry does not say; ask the inode.
+                    _ => Ext2Inode::new(self.fs.clone(), ino).disk()?.kind(),
+                };
+                entries.push(DirEntry {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    ino: ino as u64,
+                    kind,
+                });
+            }
+            pos += rec_len;
+        }
+        Ok(entries)
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> Result<Metadata> {
+        let disk = self.disk()?;
+        Ok(Metadata {
+            ino: self.ino as u64,
+            kind: disk.kind(),
+            size: disk.size,
+            nlink: disk.links_count as u32,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let disk = self.disk()?;
+        if disk.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        self.read_data(&disk, offset, buf)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&self, _size: usize) -> Result<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let entry = self
+            .entries()?
+            .into_iter()
+            .find(|e| e.name == name)
+            .ok_or(FsError::NotFound)?;
+        Ok(Arc::new(Ext2Inode::new(self.fs.clone(), entry.ino as u32)))
+    }
+
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> Result<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn read_link(&self) -> Result<String> {
+        let disk = self.disk()?;
+        if disk.kind() != FileType::Symlink {
+            return Err(FsError::InvalidArgument);
+        }
+        let size = disk.size as usize;
+        let acl_sectors = if disk.file_acl != 0 {
+            (self.fs.sb.block_size / 512) as u32
+        } else {
+            0
+        };
+        let target = if size < FAST_SYMLINK_MAX && disk.sectors == acl_sectors {
+            disk.block
+                .iter()
+                .flat_map(|b| b.to_le_bytes())
+                .take(size)
+                .collect()
+        } else {
+            let mut data = vec![0u8; size];
+            self.read_data(&disk, 0, &mut data)?;
+            data
+        };
+        String::from_utf8(target).map_err(|_| FsError::Corrupted)
+    }
+
+    fn read_dir(&self, index: usize) -> Result<Option<DirEntry>> {
+        Ok(self.entries()?.into_iter().nth(index))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
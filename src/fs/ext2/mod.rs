@@ -0,0 +1,181 @@
+//! Read-only ext2 filesystem.
+//!
+//! Volumes made by `mke2fs -t ext2` are supported, including the optional
+//! features that only change how data is laid out on the disk in ways
+//! this reader does not rely on. Volumes using extents, 64-bit block
+//! numbers or other incompatible features are refused with
+//! [`FsError::Incompatible`].
+
+mod inode;
+
+use super::{FileSystem, FsError, Inode, Result};
+use crate::devices::block::{BlockDevice, BLOCK_SIZE};
+use crate::devices::block_cache;
+use ::alloc::sync::{Arc, Weak};
+use inode::Ext2Inode;
+
+const SUPERBLOCK_OFFSET: usize = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const EXT2_MAGIC: u16 = 0xef53;
+const ROOT_INO: u32 = 2;
+const GOOD_OLD_REV: u32 = 0;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const GROUP_DESC_SIZE: usize = 32;
+
+/// Directory entries record the file type.
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Block groups are packed together; only the allocator cares.
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE | INCOMPAT_FLEX_BG;
+
+/// Names of the incompatible features, for the mount error message.
+const INCOMPAT_NAMES: &[(u32, &str)] = &[
+    (0x0001, "compression"),
+    (0x0004, "needs_recovery"),
+    (0x0008, "journal_dev"),
+    (0x0010, "meta_bg"),
+    (0x0040, "extent"),
+    (0x0080, "64bit"),
+    (0x0100, "mmp"),
+    (0x0400, "ea_inode"),
+    (0x1000, "dirdata"),
+    (0x2000, "metadata_csum_seed"),
+    (0x4000, "large_dir"),
+    (0x8000, "inline_data"),
+    (0x10000, "encrypt"),
+];
+
+/// Superblock fields this driver uses.
+struct Superblock {
+    inodes_count: u32,
+    first_data_block: u32,
+    block_size: usize,
+    inodes_per_group: u32,
+    inode_size: usize,
+    feature_incompat: u32,
+}
+
+pub struct Ext2 {
+    this: Weak<Ext2>,
+    device: Arc<dyn BlockDevice>,
+    sb: Superblock,
+}
+
+fn le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads `buf.len()` bytes at byte `pos` of `device`.
+fn read_device(device: &Arc<dyn BlockDevice>, pos: usize, buf: &mut [u8]) -> Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        let at = pos + done;
+        let offset = at % BLOCK_SIZE;
+        let n = (BLOCK_SIZE - offset).min(buf.len() - done);
+        let cache = block_cache::get(at / BLOCK_SIZE, device)?;
+        buf[done..done + n].copy_from_slice(&cache.lock().data()[offset..offset + n]);
+        done += n;
+    }
+    Ok(())
+}
+
+impl Ext2 {
+    /// Opens the ext2 volume on `device`.
+    pub fn open(device: Arc<dyn BlockDevice>) -> Result<Arc<Ext2>> {
+        let mut raw = [0u8; SUPERBLOCK_SIZE];
+        read_device(&device, SUPERBLOCK_OFFSET, &mut raw)?;
+        if le_u16(&raw, 56) != EXT2_MAGIC {
+            return Err(FsError::NotSupported);
+        }
+        let log_block_size = le_u32(&raw, 24);
+        if log_block_size > 6 {
+            return Err(FsError::Corrupted);
+        }
+        let rev_level = le_u32(&raw, 76);
+        let (inode_size, feature_incompat) = if rev_level == GOOD_OLD_REV {
+            (GOOD_OLD_INODE_SIZE, 0)
+        } else {
+            (le_u16(&raw, 88) as usize, le_u32(&raw, 96))
+        };
+        let unsupported = feature_incompat & !INCOMPAT_SUPPORTED;
+        if unsupported != 0 {
+            log!(
+                "ext2: unsupported incompatible features {:#x}:",
+                unsupported
+            );
+            for (flag, name) in INCOMPAT_NAMES {
+                if unsupported & flag != 0 {
+                    log!("ext2:     {}", name);
+                }
+            }
+            return Err(FsError::Incompatible);
+        }
+        let sb = Superblock {
+            inodes_count: le_u32(&raw, 0),
+            first_data_block: le_u32(&raw, 20),
+            block_size: 1024 << log_block_size,
+            inodes_per_group: le_u32(&raw, 40),
+            inode_size,
+            feature_incompat,
+        };
+        if sb.inodes_per_group == 0
+            || sb.inode_size < GOOD_OLD_INODE_SIZE
+            || !sb.inode_size.is_power_of_two()
+            || sb.inode_size > sb.block_size
+        {
+            return Err(FsError::Corrupted);
+        }
+        let fs = Arc::new_cyclic(|this| Ext2 {
+            this: this.clone(),
+            device,
+            sb,
+        });
+        if !Ext2Inode::new(fs.clone(), ROOT_INO).disk()?.is_dir() {
+            return Err(FsError::Corrupted);
+        }
+        Ok(fs)
+    }
+
+    /// Reads `buf.len()` bytes at byte `offset` of filesystem block `block`.
+    fn read_block(&self, block: u32, offset: usize, buf: &mut [u8]) -> Result<()> {
+        read_device(
+            &self.device,
+            block as usize * self.sb.block_size + offset,
+            buf,
+        )
+    }
+
+    /// Reads the on-disk inode `ino` into `buf`.
+    fn read_inode(&self, ino: u32, buf: &mut [u8]) -> Result<()> {
+        if ino == 0 || ino > self.sb.inodes_count {
+            return Err(FsError::Corrupted);
+        }
+        let group = (ino - 1) / self.sb.inodes_per_group;
+        let index = ((ino - 1) % self.sb.inodes_per_group) as usize;
+        // The descriptor table starts in the block after the superblock.
+        let table_block = self.sb.first_data_block + 1;
+        let desc_offset = group as usize * GROUP_DESC_SIZE;
+        let mut desc = [0u8; GROUP_DESC_SIZE];
+        self.read_block(table_block, desc_offset, &mut desc)?;
+        let inode_table = le_u32(&desc, 8);
+        self.read_block(inode_table, index * self.sb.inode_size, buf)
+    }
+
+    fn has_filetype(&self) -> bool {
+        self.sb.feature_incompat & INCOMPAT_FILETYPE != 0
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(Ext2Inode::new(self.this.upgrade().unwrap(), ROOT_INO))
+    }
+}
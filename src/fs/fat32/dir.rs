@@ -0,0 +1,305 @@
+//! Directory entries, including long file names (VFAT).
+//!
+//! A long name is stored in a run of extra entries just before the 8.3 entry
+//! it belongs to, last part first, each carrying a checksum of the 8.3 name.
+
+use ::alloc::{format, string::String, vec::Vec};
+
+pub const ENTRY_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+pub const ATTR_LONG_NAME: u8 = 0x0f;
+
+/// First name byte of a deleted entry.
+pub const DELETED: u8 = 0xe5;
+/// First name byte of the entry after the last one in use.
+pub const END: u8 = 0x00;
+
+/// Set in `nt_res` when the base name, or the extension, is lower case.
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+/// Offsets of the 13 UTF-16 characters within a long name entry.
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// Long names are limited to 255 UTF-16 units, hence 20 entries.
+const LFN_MAX_ENTRIES: usize = 20;
+
+/// Characters that may not appear in any file name.
+const INVALID_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// 1 January 2000 in FAT date format; there is no clock to take the time from.
+const DEFAULT_DATE: u16 = (20 << 9) | (1 << 5) | 1;
+
+/// The 8.3 entry describing a file.
+#[derive(Debug, Clone, Copy)]
+pub struct ShortEntry {
+    pub name: [u8; 11],
+    pub attr: u8,
+    pub nt_res: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+}
+
+impl ShortEntry {
+    pub fn new(name: [u8; 11], attr: u8, first_cluster: u32) -> Self {
+        ShortEntry {
+            name,
+            attr,
+            nt_res: 0,
+            first_cluster,
+            size: 0,
+        }
+    }
+
+    pub fn parse(raw: &[u8; ENTRY_SIZE]) -> Self {
+        let hi = u16::from_le_bytes([raw[20], raw[21]]) as u32;
+        let lo = u16::from_le_bytes([raw[26], raw[27]]) as u32;
+        ShortEntry {
+            name: raw[..11].try_into().unwrap(),
+            attr: raw[11],
+            nt_res: raw[12],
+            first_cluster: (hi << 16) | lo,
+            size: u32::from_le_bytes(raw[28..32].try_into().unwrap()),
+        }
+    }
+
+    /// Stores the entry in `raw`, keeping the timestamps already there.
+    pub fn store(&self, raw: &mut [u8; ENTRY_SIZE]) {
+        raw[..11].copy_from_slice(&self.name);
+        raw[11] = self.attr;
+        raw[12] = self.nt_res;
+        raw[20..22].copy_from_slice(&((self.first_cluster >> 16) as u16).to_le_bytes());
+        raw[26..28].copy_from_slice(&(self.first_cluster as u16).to_le_bytes());
+        raw[28..32].copy_from_slice(&self.size.to_le_bytes());
+    }
+
+    /// A fresh entry with creation and modification dates filled in.
+    pub fn to_raw(self) -> [u8; ENTRY_SIZE] {
+        let mut raw = [0u8; ENTRY_SIZE];
+        for offset in [16, 18, 24] {
+            raw[offset..offset + 2].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+        }
+        self.store(&mut raw);
+        raw
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    pub fn is_volume_label(&self) -> bool {
+        self.attr & ATTR_VOLUME_ID != 0
+    }
+
+    pub fn is_dot(&self) -> bool {
+        self.name == *b".          " || self.name == *b"..         "
+    }
+
+    /// The 8.3 name as shown when there is no long name.
+    pub fn display_name(&self) -> String {
+        let mut name = String::new();
+        let base = trim_padding(&self.name[..8]);
+        let ext = trim_padding(&self.name[8..]);
+        let case = |bytes: &[u8], lower: bool, out: &mut String| {
+            for (i, &b) in bytes.iter().enumerate() {
+                // 0x05 stands for a leading 0xe5, which marks deleted entries.
+                let b = if i == 0 && b == 0x05 { 0xe5 } else { b };
+                let c = b as char;
+                out.push(if lower { c.to_ascii_lowercase() } else { c });
+            }
+        };
+        case(base, self.nt_res & NT_LOWER_BASE != 0, &mut name);
+        if !ext.is_empty() {
+            name.push('.');
+            case(ext, self.nt_res & NT_LOWER_EXT != 0, &mut name);
+        }
+        name
+    }
+}
+
+fn trim_padding(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+    &bytes[..len]
+}
+
+/// Checksum of an 8.3 name, stored in each of its long name entries.
+pub fn checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// One long name entry: its sequence number, checksum and characters.
+pub struct LongEntry {
+    pub order: u8,
+    pub is_last: bool,
+    pub checksum: u8,
+    pub chars: [u16; LFN_CHARS],
+}
+
+impl LongEntry {
+    pub fn parse(raw: &[u8; ENTRY_SIZE]) -> Self {
+        let mut chars = [0u16; LFN_CHARS];
+        for (c, &offset) in chars.iter_mut().zip(LFN_CHAR_OFFSETS.iter()) {
+            *c = u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        }
+        LongEntry {
+            order: raw[0] & !LFN_LAST,
+            is_last: raw[0] & LFN_LAST != 0,
+            checksum: raw[13],
+            chars,
+        }
+    }
+}
+
+/// Collects the parts of a long name while scanning a directory.
+#[derive(Default)]
+pub struct LongNameBuilder {
+    /// Parts seen so far, in on-disk order (last part first).
+    parts: Vec<[u16; LFN_CHARS]>,
+    checksum: u8,
+    /// Sequence number expected next; 0 when not inside a long name.
+    expected: u8,
+}
+
+impl LongNameBuilder {
+    pub fn push(&mut self, entry: &LongEntry) {
+        if entry.is_last {
+            self.parts.clear();
+            self.checksum = entry.checksum;
+            self.expected = entry.order;
+        }
+        if self.expected == 0 || entry.order != self.expected || entry.checksum != self.checksum {
+            self.reset();
+            return;
+        }
+        self.parts.push(entry.chars);
+        self.expected -= 1;
+    }
+
+    pub fn reset(&mut self) {
+        self.parts.clear();
+        self.expected = 0;
+    }
+
+    /// The long name belonging to the 8.3 entry `short`, if a complete one
+    /// was collected.
+    pub fn finish(&mut self, short: &ShortEntry) -> Option<String> {
+        let complete =
+            !self.parts.is_empty() && self.expected == 0 && self.checksum == checksum(&short.name);
+        let name = if complete {
+            let units = self
+                .parts
+                .iter()
+                .rev()
+                .flat_map(|part| part.iter().copied())
+                .take_while(|&c| c != 0);
+            Some(
+                char::decode_utf16(units)
+                    .map(|c| c.unwrap_or('\u{fffd}'))
+                    .collect(),
+            )
+        } else {
+            None
+        };
+        self.reset();
+        name
+    }
+}
+
+/// Rejects names FAT cannot store.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.encode_utf16().count() <= LFN_MAX_ENTRIES * LFN_CHARS - 5
+        && !name.ends_with(' ')
+        && !name.ends_with('.')
+        && !name.chars().any(|c| c < ' ' || INVALID_CHARS.contains(&c))
+}
+
+fn is_short_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || "!#$%&'()-@^_`{}~".contains(c)
+}
+
+/// The 8.3 form of `name` if it is a valid upper-case 8.3 name, in which case
+/// no long name is needed.
+pub fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    if base.is_empty()
+        || base.len() > 8
+        || ext.len() > 3
+        || !base.chars().chain(ext.chars()).all(is_short_char)
+    {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short)
+}
+
+/// The `n`-th numbered 8.3 alias for `name`, like `LONGFI~1.TXT`.
+pub fn numbered_short_name(name: &str, n: usize) -> [u8; 11] {
+    let convert = |part: &str, max: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let c = c.to_ascii_uppercase();
+                if is_short_char(c) {
+                    c as u8
+                } else {
+                    b'_'
+                }
+            })
+            .take(max)
+            .collect()
+    };
+    let (base, ext) = match name.rfind('.') {
+        Some(index) if index > 0 => (&name[..index], &name[index + 1..]),
+        _ => (name, ""),
+    };
+    let mut base = convert(base, 8);
+    let ext = convert(ext, 3);
+    let tail = format!("~{}", n).into_bytes();
+    base.truncate(8 - tail.len());
+    if base.is_empty() {
+        base.push(b'_');
+    }
+    base.extend_from_slice(&tail);
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(&base);
+    short[8..8 + ext.len()].copy_from_slice(&ext);
+    short
+}
+
+/// The long name entries for `name`, in on-disk order.
+pub fn long_entries(name: &str, short: &[u8; 11]) -> Vec<[u8; ENTRY_SIZE]> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LFN_CHARS);
+    let sum = checksum(short);
+    (1..=count)
+        .rev()
+        .map(|order| {
+            let mut raw = [0u8; ENTRY_SIZE];
+            raw[0] = order as u8 | if order == count { LFN_LAST } else { 0 };
+            raw[11] = ATTR_LONG_NAME;
+            raw[13] = sum;
+            for (i, &offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                let index = (order - 1) * LFN_CHARS + i;
+                // The name is NUL terminated if it does not fill the entry,
+                // and padded with 0xffff after that.
+                let c = match index.cmp(&units.len()) {
+                    core::cmp::Ordering::Less => units[index],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xffff,
+                };
+                raw[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+            }
+            raw
+        })
+        .collect()
+}
@@ -0,0 +1,491 @@
+//! Files and directories of a FAT32 volume.
+//!
+//! FAT has no inodes: a file is described by its 8.3 directory entry, so an
+//! inode here is the position of that entry, which never moves while the
+//! file exists. The entry is read again on every access, so all inodes for a
+//! file stay consistent.
+
+use super::dir::{
+    self, LongEntry, LongNameBuilder, ShortEntry, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_LONG_NAME,
+    ATTR_READ_ONLY, DELETED, END, ENTRY_SIZE,
+};
+use super::{Fat32, FAT_EOC};
+use crate::devices::block::BLOCK_SIZE;
+use crate::fs::{DirEntry, FileType, FsError, Inode, Metadata, Result};
+use ::alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+const ENTRIES_PER_SECTOR: usize = BLOCK_SIZE / ENTRY_SIZE;
+
+/// Inode number of the root directory, which has no entry of its own.
+const ROOT_INO: u64 = 1;
+
+/// Position of a directory entry on the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    sector: usize,
+    offset: usize,
+}
+
+impl Slot {
+    fn ino(&self) -> u64 {
+        (self.sector * ENTRIES_PER_SECTOR + self.offset / ENTRY_SIZE) as u64
+    }
+}
+
+/// An entry in use, as found by scanning a directory.
+struct Found {
+    name: String,
+    entry: ShortEntry,
+    slot: Slot,
+    /// Entries holding the long name, if any.
+    long_slots: Vec<Slot>,
+}
+
+impl Found {
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.entry.display_name().eq_ignore_ascii_case(name)
+    }
+}
+
+impl Fat32 {
+    fn read_slot(&self, slot: Slot) -> Result<[u8; ENTRY_SIZE]> {
+        self.with_sector(slot.sector, |b| {
+            b.data()[slot.offset..slot.offset + ENTRY_SIZE]
+                .try_into()
+                .unwrap()
+        })
+    }
+
+    fn write_slot(&self, slot: Slot, raw: &[u8; ENTRY_SIZE]) -> Result<()> {
+        self.with_sector_mut(slot.sector, |b| {
+            b.data_mut()[slot.offset..slot.offset + ENTRY_SIZE].copy_from_slice(raw)
+        })
+    }
+
+    /// Every sector of the cluster chain starting at `first`, in order.
+    fn chain_sectors(&self, first: u32) -> Result<Vec<usize>> {
+        let per_cluster = self.layout.sectors_per_cluster;
+        Ok(self
+            .chain(first)?
+            .into_iter()
+            .flat_map(|c| {
+                let start = self.cluster_sector(c);
+                start..start + per_cluster
+            })
+            .collect())
+    }
+
+    /// The entries in use in the directory starting at `dir`, without `.`,
+    /// `..` and the volume label.
+    fn scan(&self, dir: u32) -> Result<Vec<Found>> {
+        let mut found = Vec::new();
+        let mut long_name = LongNameBuilder::default();
+        let mut long_slots = Vec::new();
+        for sector in self.chain_sectors(dir)? {
+            let data = self.with_sector(sector, |b| *b.data())?;
+            for (index, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+                let raw: &[u8; ENTRY_SIZE] = raw.try_into().unwrap();
+                let slot = Slot {
+                    sector,
+                    offset: index * ENTRY_SIZE,
+                };
+                match raw[0] {
+                    END => return Ok(found),
+                    DELETED => {
+                        long_name.reset();
+                        long_slots.clear();
+                        continue;
+                    }
+                    _ => {}
+                }
+                if raw[11] & 0x3f == ATTR_LONG_NAME {
+                    let entry = LongEntry::parse(raw);
+                    if entry.is_last {
+                        long_slots.clear();
+                    }
+                    long_slots.push(slot);
+                    long_name.push(&entry);
+                    continue;
+                }
+                let entry = ShortEntry::parse(raw);
+                let name = long_name.finish(&entry);
+                if entry.is_volume_label() || entry.is_dot() {
+                    long_slots.clear();
+                    continue;
+                }
+                if name.is_none() {
+                    long_slots.clear();
+                }
+                found.push(Found {
+                    name: name.unwrap_or_else(|| entry.display_name()),
+                    entry,
+                    slot,
+                    long_slots: core::mem::take(&mut long_slots),
+                });
+            }
+        }
+        Ok(found)
+    }
+
+    fn find(&self, dir: u32, name: &str) -> Result<Found> {
+        self.scan(dir)?
+            .into_iter()
+            .find(|f| f.matches(name))
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Finds `count` consecutive free entries in `dir`, growing it if needed.
+    fn alloc_slots(&self, dir: u32, count: usize) -> Result<Vec<Slot>> {
+        let mut run = Vec::new();
+        for sector in self.chain_sectors(dir)? {
+            let data = self.with_sector(sector, |b| *b.data())?;
+            for index in 0..ENTRIES_PER_SECTOR {
+                if matches!(data[index * ENTRY_SIZE], END | DELETED) {
+                    run.push(Slot {
+                        sector,
+                        offset: index * ENTRY_SIZE,
+                    });
+                    if run.len() == count {
+                        return Ok(run);
+                    }
+                } else {
+                    run.clear();
+                }
+            }
+        }
+        // New clusters are zeroed, which makes every entry in them free.
+        let mut last = self.chain(dir)?.last().copied();
+        loop {
+            let cluster = self.alloc_cluster(last)?;
+            let start = self.cluster_sector(cluster);
+            for sector in start..start + self.layout.sectors_per_cluster {
+                for index in 0..ENTRIES_PER_SECTOR {
+                    run.push(Slot {
+                        sector,
+                        offset: index * ENTRY_SIZE,
+                    });
+                    if run.len() == count {
+                        return Ok(run);
+                    }
+                }
+            }
+            last = Some(cluster);
+        }
+    }
+
+    /// Adds an entry called `name` to `dir`, with a long name if it is not a
+    /// plain 8.3 name.
+    fn add_entry(&self, dir: u32, name: &str, attr: u8, first_cluster: u32) -> Result<Slot> {
+        let existing = self.scan(dir)?;
+        if existing.iter().any(|f| f.matches(name)) {
+            return Err(FsError::Exists);
+        }
+        let (short, long) = match dir::exact_short_name(name) {
+            Some(short) => (short, Vec::new()),
+            None => {
+                let short = (1..=existing.len() + 1)
+                    .map(|n| dir::numbered_short_name(name, n))
+                    .find(|short| !existing.iter().any(|f| f.entry.name == *short))
+                    .unwrap();
+                (short, dir::long_entries(name, &short))
+            }
+        };
+        let slots = self.alloc_slots(dir, long.len() + 1)?;
+        for (slot, raw) in slots.iter().zip(long.iter()) {
+            self.write_slot(*slot, raw)?;
+        }
+        let slot = *slots.last().unwrap();
+        self.write_slot(slot, &ShortEntry::new(short, attr, first_cluster).to_raw())?;
+        Ok(slot)
+    }
+
+    /// Calls `f` on each sector-sized span of the `len` bytes at `offset` in
+    /// the file made of `chain`, with the sector, the offset in the sector
+    /// and the offset in the span.
+    fn for_each_span(
+        &self,
+        chain: &[u32],
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(usize, usize, core::ops::Range<usize>) -> Result<()>,
+    ) -> Result<()> {
+        let cluster_size = self.cluster_size();
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let cluster = *chain.get(pos / cluster_size).ok_or(FsError::Corrupted)?;
+            let (sector, in_sector) = self.cluster_position(cluster, pos % cluster_size);
+            let n = (BLOCK_SIZE - in_sector).min(len - done);
+            f(sector, in_sector, done..done + n)?;
+            done += n;
+        }
+        Ok(())
+    }
+}
+
+pub struct FatInode {
+    fs: Arc<Fat32>,
+    /// The entry describing the file, or `None` for the root directory.
+    slot: Option<Slot>,
+}
+
+impl FatInode {
+    pub fn root(fs: Arc<Fat32>) -> Self {
+        FatInode { fs, slot: None }
+    }
+
+    /// The directory entry, or `None` for the root directory.
+    fn entry(&self) -> Result<Option<ShortEntry>> {
+        let slot = match self.slot {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+        let raw = self.fs.read_slot(slot)?;
+        // The file was removed while this inode was held.
+        if matches!(raw[0], END | DELETED) {
+            return Err(FsError::NotFound);
+        }
+        Ok(Some(ShortEntry::parse(&raw)))
+    }
+
+    fn update_entry(&self, f: impl FnOnce(&mut ShortEntry)) -> Result<()> {
+        let slot = self.slot.expect("root directory has no entry");
+        let mut raw = self.fs.read_slot(slot)?;
+        let mut entry = ShortEntry::parse(&raw);
+        f(&mut entry);
+        entry.store(&mut raw);
+        self.fs.write_slot(slot, &raw)
+    }
+
+    fn first_cluster(&self) -> Result<u32> {
+        Ok(match self.entry()? {
+            Some(entry) => entry.first_cluster,
+            None => self.fs.layout.root_cluster,
+        })
+    }
+
+    fn is_dir(&self) -> Result<bool> {
+        Ok(self.entry()?.is_none_or(|e| e.is_dir()))
+    }
+
+    /// The directory's first cluster, or [`FsError::NotDir`].
+    fn dir_cluster(&self) -> Result<u32> {
+        if !self.is_dir()? {
+            return Err(FsError::NotDir);
+        }
+        self.first_cluster()
+    }
+
+    /// The entry of a regular file, or [`FsError::IsDir`].
+    fn file_entry(&self) -> Result<ShortEntry> {
+        match self.entry()? {
+            Some(entry) if !entry.is_dir() => Ok(entry),
+            _ => Err(FsError::IsDir),
+        }
+    }
+
+    fn child(&self, found: &Found) -> Arc<dyn Inode> {
+        Arc::new(FatInode {
+            fs: self.fs.clone(),
+            slot: Some(found.slot),
+        })
+    }
+
+    /// Changes the size of the file, freeing or zero-filling clusters.
+    fn resize(&self, entry: &ShortEntry, size: usize) -> Result<()> {
+        if size > u32::MAX as usize {
+            return Err(FsError::NoSpace);
+        }
+        if entry.attr & ATTR_READ_ONLY != 0 {
+            return Err(FsError::ReadOnly);
+        }
+        let fs = &self.fs;
+        let old_size = entry.size as usize;
+        let mut chain = fs.chain(entry.first_cluster)?;
+        let needed = size.div_ceil(fs.cluster_size());
+        while chain.len() < needed {
+            let cluster = fs.alloc_cluster(chain.last().copied())?;
+            if chain.is_empty() {
+                self.update_entry(|e| e.first_cluster = cluster)?;
+            }
+            chain.push(cluster);
+        }
+        if needed < chain.len() {
+            if needed == 0 {
+                self.update_entry(|e| e.first_cluster = 0)?;
+            } else {
+                fs.set_fat_entry(chain[needed - 1], FAT_EOC)?;
+            }
+            fs.free_chain(chain[needed])?;
+        }
+        if size > old_size {
+            // Clear what an earlier truncate may have left after the old end.
+            fs.for_each_span(&chain, old_size, size - old_size, |sector, offset, span| {
+                fs.with_sector_mut(sector, |b| {
+                    b.data_mut()[offset..offset + span.len()].fill(0)
+                })
+            })?;
+        }
+        self.update_entry(|e| e.size = size as u32)
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Result<Metadata> {
+        let _guard = self.fs.lock.lock();
+        let entry = self.entry()?;
+        let (kind, size) = match entry {
+            Some(entry) if !entry.is_dir() => (FileType::Regular, entry.size as u64),
+            _ => {
+                let clusters = self.fs.chain(self.first_cluster()?)?.len();
+                (
+                    FileType::Directory,
+                    (clusters * self.fs.cluster_size()) as u64,
+                )
+            }
+        };
+        Ok(Metadata {
+            ino: self.slot.map_or(ROOT_INO, |slot| slot.ino()),
+            kind,
+            size,
+            nlink: 1,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let _guard = self.fs.lock.lock();
+        let entry = self.file_entry()?;
+        let size = entry.size as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        let chain = self.fs.chain(entry.first_cluster)?;
+        self.fs
+            .for_each_span(&chain, offset, len, |sector, in_sector, span| {
+                self.fs.with_sector(sector, |b| {
+                    buf[span.clone()].copy_from_slice(&b.data()[in_sector..in_sector + span.len()])
+                })
+            })?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let _guard = self.fs.lock.lock();
+        let entry = self.file_entry()?;
+        let end = offset.checked_add(buf.len()).ok_or(FsError::NoSpace)?;
+        if end > entry.size as usize {
+            self.resize(&entry, end)?;
+        } else if entry.attr & ATTR_READ_ONLY != 0 {
+            return Err(FsError::ReadOnly);
+        }
+        let chain = self.fs.chain(self.first_cluster()?)?;
+        self.fs
+            .for_each_span(&chain, offset, buf.len(), |sector, in_sector, span| {
+                self.fs.with_sector_mut(sector, |b| {
+                    b.data_mut()[in_sector..in_sector + span.len()].copy_from_slice(&buf[span])
+                })
+            })?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: usize) -> Result<()> {
+        let _guard = self.fs.lock.lock();
+        let entry = self.file_entry()?;
+        self.resize(&entry, size)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let _guard = self.fs.lock.lock();
+        let found = self.fs.find(self.dir_cluster()?, name)?;
+        Ok(self.child(&found))
+    }
+
+    fn create(&self, name: &str, kind: FileType) -> Result<Arc<dyn Inode>> {
+        let _guard = self.fs.lock.lock();
+        let dir = self.dir_cluster()?;
+        if !dir::is_valid_name(name) {
+            return Err(FsError::InvalidArgument);
+        }
+        let fs = &self.fs;
+        let slot = match kind {
+            FileType::Regular => fs.add_entry(dir, name, ATTR_ARCHIVE, 0)?,
+            FileType::Directory => {
+                let cluster = fs.alloc_cluster(None)?;
+                // `..` of a directory in the root refers to cluster 0.
+                let parent = if self.slot.is_none() { 0 } else { dir };
+                let first = fs.cluster_sector(cluster);
+                let dot = ShortEntry::new(*b".          ", ATTR_DIRECTORY, cluster).to_raw();
+                let dotdot = ShortEntry::new(*b"..         ", ATTR_DIRECTORY, parent).to_raw();
+                fs.write_slot(
+                    Slot {
+                        sector: first,
+                        offset: 0,
+                    },
+                    &dot,
+                )?;
+                fs.write_slot(
+                    Slot {
+                        sector: first,
+                        offset: ENTRY_SIZE,
+                    },
+                    &dotdot,
+                )?;
+                match fs.add_entry(dir, name, ATTR_DIRECTORY, cluster) {
+                    Ok(slot) => slot,
+                    Err(err) => {
+                        fs.free_chain(cluster)?;
+                        return Err(err);
+                    }
+                }
+            }
+            _ => return Err(FsError::NotSupported),
+        };
+        Ok(Arc::new(FatInode {
+            fs: fs.clone(),
+            slot: Some(slot),
+        }))
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        let _guard = self.fs.lock.lock();
+        let fs = &self.fs;
+        let found = fs.find(self.dir_cluster()?, name)?;
+        if found.entry.is_dir() && !fs.scan(found.entry.first_cluster)?.is_empty() {
+            return Err(FsError::NotEmpty);
+        }
+        for slot in found.long_slots.iter().chain(core::iter::once(&found.slot)) {
+            let mut raw = fs.read_slot(*slot)?;
+            raw[0] = DELETED;
+            fs.write_slot(*slot, &raw)?;
+        }
+        if found.entry.first_cluster != 0 {
+            fs.free_chain(found.entry.first_cluster)?;
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, index: usize) -> Result<Option<DirEntry>> {
+        let _guard = self.fs.lock.lock();
+        let found = self.fs.scan(self.dir_cluster()?)?;
+        Ok(found.into_iter().nth(index).map(|f| DirEntry {
+            ino: f.slot.ino(),
+            kind: if f.entry.is_dir() {
+                FileType::Directory
+            } else {
+                FileType::Regular
+            },
+            name: f.name,
+        }))
+    }
+
+    fn sync(&self) -> Result<()> {
+        use crate::fs::FileSystem;
+        self.fs.sync()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
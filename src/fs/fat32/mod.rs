@@ -0,0 +1,309 @@
+//! FAT32 filesystem.
+//!
+//! The volume is either the whole device, as `mkfs.vfat` creates it on a bare
+//! image, or the first FAT32 partition of an MBR partition table. Every
+//! access goes through the block cache, and the filesystem is serialized by a
+//! single lock since FAT offers little to gain from finer locking.
+
+mod dir;
+mod inode;
+
+use super::{FileSystem, FsError, Inode, Result};
+use crate::devices::block::{BlockDevice, BLOCK_SIZE};
+use crate::devices::block_cache::{self, BlockCache};
+use ::alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use inode::FatInode;
+use spin::Mutex;
+
+const BOOT_SIGNATURE: u16 = 0xaa55;
+/// MBR partition types of FAT32 volumes, with CHS and LBA addressing.
+const PARTITION_TYPES: [u8; 2] = [0x0b, 0x0c];
+const PARTITION_TABLE: usize = 446;
+
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_FREE_COUNT: usize = 488;
+const FSINFO_NEXT_FREE: usize = 492;
+
+/// FAT entries are 28 bits wide; the top four bits are reserved.
+const FAT_ENTRY_MASK: u32 = 0x0fff_ffff;
+const FAT_BAD: u32 = 0x0fff_fff7;
+/// Written to end a chain. Any value from 0x0ffffff8 up ends one.
+const FAT_EOC: u32 = 0x0fff_ffff;
+const FAT_EOC_MIN: u32 = 0x0fff_fff8;
+const FIRST_CLUSTER: u32 = 2;
+
+/// Where things are on the volume, in absolute sectors.
+struct Layout {
+    sectors_per_cluster: usize,
+    fat_start: usize,
+    fat_sectors: usize,
+    num_fats: usize,
+    data_start: usize,
+    /// Number of data clusters; valid clusters are 2..cluster_count + 2.
+    cluster_count: u32,
+    root_cluster: u32,
+    fs_info: Option<usize>,
+}
+
+pub struct Fat32 {
+    this: Weak<Fat32>,
+    device: Arc<dyn BlockDevice>,
+    layout: Layout,
+    /// Where to start looking for a free cluster.
+    next_free: AtomicU32,
+    /// Clusters were allocated or freed since FSInfo was last written.
+    fs_info_dirty: AtomicBool,
+    /// Serializes every operation on the volume.
+    lock: Mutex<()>,
+}
+
+fn le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Whether `sector` looks like a FAT boot sector rather than an MBR.
+fn is_boot_sector(sector: &[u8; BLOCK_SIZE]) -> bool {
+    matches!(sector[0], 0xeb | 0xe9)
+        && le_u16(sector, 11) == BLOCK_SIZE as u16
+        && sector[13].is_power_of_two()
+}
+
+impl Fat32 {
+    /// Opens the FAT32 volume on `device`.
+    pub fn open(device: Arc<dyn BlockDevice>) -> Result<Arc<Fat32>> {
+        let first = *block_cache::get(0, &device)?.lock().data();
+        if le_u16(&first, 510) != BOOT_SIGNATURE {
+            return Err(FsError::NotSupported);
+        }
+        let start = if is_boot_sector(&first) {
+            0
+        } else {
+            (0..4)
+                .map(|i| &first[PARTITION_TABLE + i * 16..PARTITION_TABLE + (i + 1) * 16])
+                .find(|entry| PARTITION_TYPES.contains(&entry[4]))
+                .map(|entry| le_u32(entry, 8) as usize)
+                .ok_or(FsError::NotSupported)?
+        };
+        let boot = *block_cache::get(start, &device)?.lock().data();
+        if !is_boot_sector(&boot) || le_u16(&boot, 510) != BOOT_SIGNATURE {
+            return Err(FsError::NotSupported);
+        }
+        // FAT12 and FAT16 have a fixed root directory and a 16-bit FAT size.
+        if le_u16(&boot, 17) != 0 || le_u16(&boot, 22) != 0 {
+            return Err(FsError::NotSupported);
+        }
+        let sectors_per_cluster = boot[13] as usize;
+        let reserved = le_u16(&boot, 14) as usize;
+        let num_fats = boot[16] as usize;
+        let total_sectors = match le_u16(&boot, 19) {
+            0 => le_u32(&boot, 32) as usize,
+            n => n as usize,
+        };
+        let fat_sectors = le_u32(&boot, 36) as usize;
+        let data_offset = reserved + num_fats * fat_sectors;
+        if num_fats == 0 || fat_sectors == 0 || data_offset >= total_sectors {
+            return Err(FsError::Corrupted);
+        }
+        let cluster_count = ((total_sectors - data_offset) / sectors_per_cluster)
+            .min(fat_sectors * BLOCK_SIZE / 4 - FIRST_CLUSTER as usize)
+            as u32;
+        let root_cluster = le_u32(&boot, 44);
+        let fs_info = match le_u16(&boot, 48) as usize {
+            0 | 0xffff => None,
+            sector => Some(start + sector),
+        };
+        let layout = Layout {
+            sectors_per_cluster,
+            fat_start: start + reserved,
+            fat_sectors,
+            num_fats,
+            data_start: start + data_offset,
+            cluster_count,
+            root_cluster,
+            fs_info,
+        };
+        if !layout.is_valid_cluster(root_cluster) {
+            return Err(FsError::Corrupted);
+        }
+        let fs = Arc::new_cyclic(|this| Fat32 {
+            this: this.clone(),
+            device,
+            layout,
+            next_free: AtomicU32::new(FIRST_CLUSTER),
+            fs_info_dirty: AtomicBool::new(false),
+            lock: Mutex::new(()),
+        });
+        if let Some(sector) = fs.layout.fs_info {
+            let hint = fs.with_sector(sector, |b| {
+                if b.read(0, |s: &u32| *s) == FSINFO_LEAD_SIGNATURE {
+                    b.read(FSINFO_NEXT_FREE, |n: &u32| *n)
+                } else {
+                    FIRST_CLUSTER
+                }
+            })?;
+            if fs.layout.is_valid_cluster(hint) {
+                fs.next_free.store(hint, Ordering::Relaxed);
+            }
+        }
+        Ok(fs)
+    }
+
+    fn with_sector<V>(&self, sector: usize, f: impl FnOnce(&BlockCache) -> V) -> Result<V> {
+        Ok(f(&block_cache::get(sector, &self.device)?.lock()))
+    }
+
+    fn with_sector_mut<V>(&self, sector: usize, f: impl FnOnce(&mut BlockCache) -> V) -> Result<V> {
+        Ok(f(&mut block_cache::get(sector, &self.device)?.lock()))
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.layout.sectors_per_cluster * BLOCK_SIZE
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> usize {
+        self.layout.data_start
+            + (cluster - FIRST_CLUSTER) as usize * self.layout.sectors_per_cluster
+    }
+
+    /// Sector and offset of byte `offset` of `cluster`.
+    fn cluster_position(&self, cluster: u32, offset: usize) -> (usize, usize) {
+        (
+            self.cluster_sector(cluster) + offset / BLOCK_SIZE,
+            offset % BLOCK_SIZE,
+        )
+    }
+
+    fn fat_position(&self, cluster: u32) -> (usize, usize) {
+        let offset = cluster as usize * 4;
+        (
+            self.layout.fat_start + offset / BLOCK_SIZE,
+            offset % BLOCK_SIZE,
+        )
+    }
+
+    fn fat_entry(&self, cluster: u32) -> Result<u32> {
+        let (sector, offset) = self.fat_position(cluster);
+        self.with_sector(sector, |b| b.read(offset, |e: &u32| *e & FAT_ENTRY_MASK))
+    }
+
+    /// Sets the FAT entry of `cluster` in every copy of the FAT.
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<()> {
+        let (sector, offset) = self.fat_position(cluster);
+        for copy in 0..self.layout.num_fats {
+            self.with_sector_mut(sector + copy * self.layout.fat_sectors, |b| {
+                b.modify(offset, |e: &mut u32| {
+                    *e = (*e & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK)
+                })
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The clusters of the chain starting at `first`, in order.
+    fn chain(&self, first: u32) -> Result<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 {
+            if !self.layout.is_valid_cluster(cluster)
+                || clusters.len() >= self.layout.cluster_count as usize
+            {
+                return Err(FsError::Corrupted);
+            }
+            clusters.push(cluster);
+            cluster = match self.fat_entry(cluster)? {
+                next if next >= FAT_EOC_MIN => 0,
+                FAT_BAD | 0 => return Err(FsError::Corrupted),
+                next => next,
+            };
+        }
+        Ok(clusters)
+    }
+
+    /// Takes a free cluster, zeroes it and appends it to the chain ending at
+    /// `last`, if any.
+    fn alloc_cluster(&self, last: Option<u32>) -> Result<u32> {
+        let count = self.layout.cluster_count;
+        let hint = self.next_free.load(Ordering::Relaxed);
+        let start = if self.layout.is_valid_cluster(hint) {
+            hint - FIRST_CLUSTER
+        } else {
+            0
+        };
+        let mut found = None;
+        for i in 0..count {
+            let cluster = FIRST_CLUSTER + (start + i) % count;
+            if self.fat_entry(cluster)? == 0 {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = found.ok_or(FsError::NoSpace)?;
+        self.set_fat_entry(cluster, FAT_EOC)?;
+        if let Some(last) = last {
+            self.set_fat_entry(last, cluster)?;
+        }
+        self.next_free.store(cluster + 1, Ordering::Relaxed);
+        self.fs_info_dirty.store(true, Ordering::Relaxed);
+        let first_sector = self.cluster_sector(cluster);
+        for sector in first_sector..first_sector + self.layout.sectors_per_cluster {
+            self.with_sector_mut(sector, |b| b.data_mut().fill(0))?;
+        }
+        Ok(cluster)
+    }
+
+    /// Frees every cluster of the chain starting at `first`.
+    fn free_chain(&self, first: u32) -> Result<()> {
+        for cluster in self.chain(first)? {
+            self.set_fat_entry(cluster, 0)?;
+        }
+        self.fs_info_dirty.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Records the allocation hint and marks the free count unknown, so
+    /// other systems recount instead of trusting a stale value.
+    fn write_fs_info(&self) -> Result<()> {
+        if !self.fs_info_dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        if let Some(sector) = self.layout.fs_info {
+            let next_free = self.next_free.load(Ordering::Relaxed);
+            self.with_sector_mut(sector, |b| {
+                if b.read(0, |s: &u32| *s) == FSINFO_LEAD_SIGNATURE {
+                    b.modify(FSINFO_FREE_COUNT, |n: &mut u32| *n = u32::MAX);
+                    b.modify(FSINFO_NEXT_FREE, |n: &mut u32| *n = next_free);
+                }
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Layout {
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= FIRST_CLUSTER && cluster < self.cluster_count + FIRST_CLUSTER
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn name(&self) -> &'static str {
+        "vfat"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(FatInode::root(self.this.upgrade().unwrap()))
+    }
+
+    fn sync(&self) -> Result<()> {
+        let _guard = self.lock.lock();
+        self.write_fs_info()
+    }
+}
@@ -0,0 +1,239 @@
+//! Open files and file descriptor tables.
+
+use super::page_cache::{self, CacheKey, PageCache};
+use super::{FileType, FsError, Inode, Metadata, Result};
+use ::alloc::{sync::Arc, vec::Vec};
+use spin::Mutex;
+
+/// Flags of `open`, with the values Linux uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(pub u32);
+
+impl OpenFlags {
+    pub const RDONLY: OpenFlags = OpenFlags(0);
+    pub const WRONLY: OpenFlags = OpenFlags(0o1);
+    pub const RDWR: OpenFlags = OpenFlags(0o2);
+    pub const CREATE: OpenFlags = OpenFlags(0o100);
+    pub const EXCL: OpenFlags = OpenFlags(0o200);
+    pub const TRUNC: OpenFlags = OpenFlags(0o1000);
+    pub const APPEND: OpenFlags = OpenFlags(0o2000);
+    pub const DIRECTORY: OpenFlags = OpenFlags(0o200000);
+
+    const ACCESS_MODE: u32 = 0o3;
+
+    pub fn contains(&self, other: OpenFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn readable(&self) -> bool {
+        self.0 & Self::ACCESS_MODE != Self::WRONLY.0
+    }
+
+    pub fn writable(&self) -> bool {
+        matches!(self.0 & Self::ACCESS_MODE, 1 | 2)
+    }
+}
+
+impl core::ops::BitOr for OpenFlags {
+    type Output = OpenFlags;
+
+    fn bitor(self, other: OpenFlags) -> OpenFlags {
+        OpenFlags(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(usize),
+    Current(isize),
+    End(isize),
+}
+
+/// An open file: a regular file, a directory, or a device.
+pub trait File: Send + Sync {
+    fn read(&self, buf: &mut [u8]) -> Result<usize>;
+
+    fn write(&self, buf: &[u8]) -> Result<usize>;
+
+    /// Moves the file position, returning the new one. The position in a
+    /// directory counts its entries.
+    fn seek(&self, _pos: SeekFrom) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn metadata(&self) -> Result<Metadata>;
+
+    /// Performs a device-specific request, returning the data it gives back,
+    /// if any, for the caller to store where the request points.
+    fn ioctl(&self, _request: u32) -> Result<Vec<u8>> {
+        Err(FsError::NotTty)
+    }
+
+    /// The inode the file was opened from, if any.
+    fn inode(&self) -> Option<Arc<dyn Inode>> {
+        None
+    }
+
+    /// The cache of the file's pages, for mapping them into memory. Writes
+    /// through the mapping reach the file only if `writable` is set, which
+    /// requires the file to be open for writing.
+    fn page_cache(&self, _writable: bool) -> Result<Arc<PageCache>> {
+        Err(FsError::NotSupported)
+    }
+}
+
+/// A file opened from an inode, with its own position.
+pub struct InodeFile {
+    inode: Arc<dyn Inode>,
+    flags: OpenFlags,
+    offset: Mutex<usize>,
+    /// Where to find the file's page cache, for a regular file.
+    cache_key: Option<CacheKey>,
+}
+
+impl InodeFile {
+    pub fn new(inode: Arc<dyn Inode>, flags: OpenFlags, cache_key: Option<CacheKey>) -> Self {
+        InodeFile {
+            inode,
+            flags,
+            offset: Mutex::new(0),
+            cache_key,
+        }
+    }
+
+    /// The file's page cache, if it is mapped anywhere.
+    fn cache(&self) -> Option<Arc<PageCache>> {
+        self.cache_key.and_then(page_cache::lookup)
+    }
+}
+
+impl File for InodeFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if !self.flags.readable() {
+            return Err(FsError::BadFd);
+        }
+        let mut offset = self.offset.lock();
+        let read = match self.cache() {
+            Some(cache) => cache.read_at(*offset, buf)?,
+            None => self.inode.read_at(*offset, buf)?,
+        };
+        *offset += read;
+        Ok(read)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        if !self.flags.writable() {
+            return Err(FsError::BadFd);
+        }
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = self.inode.metadata()?.size as usize;
+        }
+        let written = match self.cache() {
+            Some(cache) => cache.write_at(*offset, buf)?,
+            None => self.inode.write_at(*offset, buf)?,
+        };
+        *offset += written;
+        Ok(written)
+    }
+
+    fn seek(&self, pos: SeekFrom) -> Result<usize> {
+        let metadata = self.inode.metadata()?;
+        if !matches!(
+            metadata.kind,
+            FileType::Regular | FileType::Directory | FileType::BlockDevice
+        ) {
+            return Err(FsError::NotSupported);
+        }
+        let mut offset = self.offset.lock();
+        let new = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(delta) => offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => (metadata.size as usize).checked_add_signed(delta),
+        };
+        *offset = new.ok_or(FsError::InvalidArgument)?;
+        Ok(*offset)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        self.inode.metadata()
+    }
+
+    fn ioctl(&self, request: u32) -> Result<Vec<u8>> {
+        self.inode.ioctl(request)
+    }
+
+    fn inode(&self) -> Option<Arc<dyn Inode>> {
+        Some(self.inode.clone())
+    }
+
+    fn page_cache(&self, writable: bool) -> Result<Arc<PageCache>> {
+        let key = self.cache_key.ok_or(FsError::NotSupported)?;
+        if !self.flags.readable() || (writable && !self.flags.writable()) {
+            return Err(FsError::PermissionDenied);
+        }
+        Ok(page_cache::get_or_create(key, self.inode.clone()))
+    }
+}
+
+/// Most files a process can have open at once.
+pub const MAX_FDS: usize = 64;
+
+/// Open files of a process, indexed by file descriptor.
+#[derive(Clone, Default)]
+pub struct FdTable {
+    files: Vec<Option<Arc<dyn File>>>,
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        FdTable { files: Vec::new() }
+    }
+
+    /// Stores `file` under the lowest free descriptor and returns it.
+    pub fn insert(&mut self, file: Arc<dyn File>) -> Result<usize> {
+        let fd = match self.files.iter().position(|f| f.is_none()) {
+            Some(fd) => fd,
+            None if self.files.len() < MAX_FDS => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return Err(FsError::TooManyFiles),
+        };
+        self.files[fd] = Some(file);
+        Ok(fd)
+    }
+
+    /// Stores `file` under `fd`, closing what was open there.
+    pub fn insert_at(&mut self, fd: usize, file: Arc<dyn File>) -> Result<()> {
+        if fd >= MAX_FDS {
+            return Err(FsError::BadFd);
+        }
+        if self.files.len() <= fd {
+            self.files.resize(fd + 1, None);
+        }
+        self.files[fd] = Some(file);
+        Ok(())
+    }
+
+    pub fn get(&self, fd: usize) -> Result<Arc<dyn File>> {
+        self.files
+            .get(fd)
+            .and_then(|f| f.clone())
+            .ok_or(FsError::BadFd)
+    }
+
+    pub fn close(&mut self, fd: usize) -> Result<()> {
+        self.files
+            .get_mut(fd)
+            .and_then(|f| f.take())
+            .map(|_| ())
+            .ok_or(FsError::BadFd)
+    }
+
+    /// Opens another descriptor for the file behind `fd`.
+    pub fn dup(&mut self, fd: usize) -> Result<usize> {
+        let file = self.get(fd)?;
+        self.insert(file)
+    }
+}
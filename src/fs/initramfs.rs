@@ -0,0 +1,169 @@
+//! Initial files unpacked into the root tmpfs at boot.
+//!
+//! The archive is in the `newc` cpio format. `cargo xtask` packs one into
+//! the `.initramfs` section of the kernel image; an initrd loaded by the
+//! bootloader (QEMU's `-initrd`) takes its place when present.
+
+use super::{FsError, OpenFlags, Result};
+use crate::mm;
+use ::alloc::{collections::BTreeMap, string::String};
+use core::arch::global_asm;
+use core::ops::Range;
+
+global_asm!(concat!(
+    "    .section .initramfs, \"a\"\n",
+    "    .incbin \"",
+    env!("INITRAMFS"),
+    "\"\n"
+));
+
+const MAGIC: &[u8] = b"070701";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Header fields this reader uses.
+struct Header {
+    ino: u32,
+    mode: u32,
+    nlink: u32,
+    filesize: usize,
+    dev: (u32, u32),
+    namesize: usize,
+}
+
+impl Header {
+    fn parse(raw: &[u8]) -> Result<Header> {
+        if raw.len() < HEADER_SIZE || &raw[..6] != MAGIC {
+            return Err(FsError::Corrupted);
+        }
+        // Thirteen 8-digit hex fields follow the magic.
+        let field = |index: usize| {
+            let start = 6 + index * 8;
+            core::str::from_utf8(&raw[start..start + 8])
+                .ok()
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .ok_or(FsError::Corrupted)
+        };
+        Ok(Header {
+            ino: field(0)?,
+            mode: field(1)?,
+            nlink: field(4)?,
+            filesize: field(6)? as usize,
+            dev: (field(7)?, field(8)?),
+            namesize: field(11)? as usize,
+        })
+    }
+}
+
+/// Unpacks `archive` into the directory tree, relative to `/`.
+pub fn unpack(archive: &[u8]) -> Result<usize> {
+    // First path seen for each multiply linked inode.
+    let mut links: BTreeMap<(u32, u32, u32), String> = BTreeMap::new();
+    let mut pos = 0;
+    let mut count = 0;
+    loop {
+        let header = Header::parse(archive.get(pos..).ok_or(FsError::Corrupted)?)?;
+        let name_start = pos + HEADER_SIZE;
+        let data_start = (name_start + header.namesize).next_multiple_of(4);
+        let data_end = data_start + header.filesize;
+        let (name, data) = match (
+            archive.get(name_start..name_start + header.namesize),
+            archive.get(data_start..data_end),
+        ) {
+            (Some([name @ .., 0]), Some(data)) => (name, data),
+            _ => return Err(FsError::Corrupted),
+        };
+        let name = core::str::from_utf8(name).map_err(|_| FsError::Corrupted)?;
+        if name == TRAILER {
+            return Ok(count);
+        }
+        pos = data_end.next_multiple_of(4);
+
+        let path = name.trim_start_matches("./").trim_start_matches('/');
+        if path.is_empty() || path == "." {
+            continue;
+        }
+        match header.mode & S_IFMT {
+            S_IFDIR => match super::mkdir("/", path) {
+                Ok(_) | Err(FsError::Exists) => {}
+                Err(err) => return Err(err),
+            },
+            S_IFREG => {
+                let key = (header.dev.0, header.dev.1, header.ino);
+                let first = links.get(&key).cloned();
+                if first.is_none() && header.nlink > 1 {
+                    links.insert(key, String::from(path));
+                }
+                let flags = match &first {
+                    Some(first) => {
+                        super::link("/", first, path)?;
+                        OpenFlags::WRONLY
+                    }
+                    None => OpenFlags::WRONLY | OpenFlags::CREATE | OpenFlags::TRUNC,
+                };
+                // With hard links the data may come with any one of the names.
+                if first.is_none() || !data.is_empty() {
+                    let file = super::open("/", path, flags)?;
+                    let mut done = 0;
+                    while done < data.len() {
+                        done += file.write(&data[done..])?;
+                    }
+                }
+            }
+            S_IFLNK => {
+                let target = core::str::from_utf8(data).map_err(|_| FsError::Corrupted)?;
+                super::symlink("/", target, path)?;
+            }
+            _ => {
+                log!("initramfs: skipping {} with mode {:o}", path, header.mode);
+                continue;
+            }
+        }
+        count += 1;
+    }
+}
+
+/// Unpacks the initrd at physical `initrd`, or else the archive built into
+/// the kernel, then gives the initrd's frames back.
+pub fn init(initrd: Option<Range<usize>>) {
+    extern "C" {
+        fn sinitramfs();
+        fn einitramfs();
+    }
+    let (source, archive) = match &initrd {
+        Some(range) => ("initrd", unsafe {
+            core::slice::from_raw_parts(range.start as *const u8, range.len())
+        }),
+        None => ("built-in archive", unsafe {
+            core::slice::from_raw_parts(
+                sinitramfs as usize as *const u8,
+                einitramfs as usize - sinitramfs as usize,
+            )
+        }),
+    };
+    if archive.is_empty() {
+        log!("initramfs: no archive");
+    } else {
+        match unpack(archive) {
+            Ok(count) => {
+                log!(
+                    "initramfs: unpacked {} entries from the {} ({} bytes)",
+                    count,
+                    source,
+                    archive.len()
+                );
+            }
+            Err(err) => {
+                log!("initramfs: failed to unpack the {}: {}", source, err);
+            }
+        }
+    }
+    if let Some(range) = initrd {
+        mm::release_frames(range);
+    }
+}
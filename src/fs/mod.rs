@@ -0,0 +1,450 @@
+//! Virtual filesystem.
+//!
+//! Concrete filesystems implement [`FileSystem`] and [`Inode`] and are
+//! attached to the directory tree with [`mount`]. Paths are resolved across
+//! mount points by [`path::resolve`], and opened inodes are accessed through
+//! the [`File`] trait, which device files implement directly as well.
+
+pub mod devfs;
+pub mod ext2;
+pub mod fat32;
+mod file;
+pub mod initramfs;
+mod mount;
+pub mod page_cache;
+mod path;
+pub mod procfs;
+pub mod tmpfs;
+
+pub use file::{FdTable, File, InodeFile, OpenFlags, SeekFrom};
+pub use mount::mount;
+pub use path::{resolve, resolve_parent};
+
+use crate::devices::block::{BlockDevice, BlockError};
+use crate::task;
+use ::alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::any::Any;
+use core::fmt;
+use core::ops::Range;
+use core::time::Duration;
+
+/// Longest file name accepted in a path component.
+pub const NAME_MAX: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    /// No file or directory with that name.
+    NotFound,
+    /// A file with that name already exists.
+    Exists,
+    /// A path component that should be a directory is not.
+    NotDir,
+    /// The operation does not apply to directories.
+    IsDir,
+    /// The directory still has entries.
+    NotEmpty,
+    /// A path component is longer than [`NAME_MAX`].
+    NameTooLong,
+    /// Too many symbolic links were followed.
+    Loop,
+    /// An argument is invalid for the operation, e.g. removing `.`.
+    InvalidArgument,
+    /// The filesystem is mounted or formatted read-only.
+    ReadOnly,
+    /// The filesystem has no free space left.
+    NoSpace,
+    /// The filesystem or file does not implement the operation.
+    NotSupported,
+    /// The directory is a mount point or the filesystem is in use.
+    Busy,
+    /// A link would cross filesystems.
+    CrossDevice,
+    /// The file descriptor is not open.
+    BadFd,
+    /// The file descriptor table is full.
+    TooManyFiles,
+    /// The on-disk structures are inconsistent.
+    Corrupted,
+    /// The filesystem uses features this driver does not understand.
+    Incompatible,
+    /// The file does not support the ioctl request.
+    NotTty,
+    /// There is no memory for the operation.
+    NoMemory,
+    /// The file was not opened for the access asked for.
+    PermissionDenied,
+    /// The underlying block device failed.
+    Io(BlockError),
+}
+
+impl FsError {
+    /// The matching Linux `errno` value, for returning from system calls.
+    pub fn errno(&self) -> isize {
+        match self {
+            FsError::NotFound => 2,
+            FsError::Io(_) | FsError::Corrupted => 5,
+            FsError::BadFd => 9,
+            FsError::NoMemory => 12,
+            FsError::PermissionDenied => 13,
+            FsError::Busy => 16,
+            FsError::Exists => 17,
+            FsError::CrossDevice => 18,
+            FsError::NotDir => 20,
+            FsError::IsDir => 21,
+            FsError::InvalidArgument => 22,
+            FsError::TooManyFiles => 24,
+            FsError::NotTty => 25,
+            FsError::NoSpace => 28,
+            FsError::ReadOnly => 30,
+            FsError::NameTooLong => 36,
+            FsError::NotSupported => 38,
+            FsError::NotEmpty => 39,
+            FsError::Loop => 40,
+            FsError::Incompatible => 95,
+        }
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsError::NotFound => write!(f, "no such file or directory"),
+            FsError::Exists => write!(f, "file exists"),
+            FsError::NotDir => write!(f, "not a directory"),
+            FsError::IsDir => write!(f, "is a directory"),
+            FsError::NotEmpty => write!(f, "directory not empty"),
+            FsError::NameTooLong => write!(f, "file name too long"),
+            FsError::Loop => write!(f, "too many levels of symbolic links"),
+            FsError::InvalidArgument => write!(f, "invalid argument"),
+            FsError::ReadOnly => write!(f, "read-only filesystem"),
+            FsError::NoSpace => write!(f, "no space left on device"),
+            FsError::NotSupported => write!(f, "operation not supported"),
+            FsError::Busy => write!(f, "resource busy"),
+            FsError::CrossDevice => write!(f, "cross-device link"),
+            FsError::BadFd => write!(f, "bad file descriptor"),
+            FsError::TooManyFiles => write!(f, "too many open files"),
+            FsError::Corrupted => write!(f, "filesystem is corrupted"),
+            FsError::Incompatible => write!(f, "filesystem uses unsupported features"),
+            FsError::NotTty => write!(f, "inappropriate ioctl for device"),
+            FsError::NoMemory => write!(f, "out of memory"),
+            FsError::PermissionDenied => write!(f, "permission denied"),
+            FsError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<BlockError> for FsError {
+    fn from(err: BlockError) -> Self {
+        match err {
+            BlockError::ReadOnly => FsError::ReadOnly,
+            err => FsError::Io(err),
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, FsError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    /// Inode number, unique within the filesystem.
+    pub ino: u64,
+    pub kind: FileType,
+    /// Size in bytes.
+    pub size: u64,
+    /// Number of directory entries referring to the inode.
+    pub nlink: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub kind: FileType,
+}
+
+/// A file, directory or other object stored in a filesystem.
+///
+/// Operations that do not apply to the kind of inode fail by default, so
+/// implementations only provide what they support.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Result<Metadata>;
+
+    /// Reads from byte `offset`, returning how much was read; 0 at the end.
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    /// Writes at byte `offset`, growing the file as needed.
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    /// Shrinks or grows the file to `size` bytes.
+    fn truncate(&self, _size: usize) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Finds the entry `name` of this directory. `.` and `..` are handled by
+    /// path resolution and never passed in.
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>> {
+        Err(FsError::NotDir)
+    }
+
+    /// Creates an empty file or directory `name` in this directory.
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>> {
+        Err(FsError::NotDir)
+    }
+
+    /// Adds `name` as another entry for `target`.
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Removes the entry `name`. Directories must be empty.
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotDir)
+    }
+
+    /// Creates a symbolic link `name` pointing to `target`.
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>> {
+        Err(FsError::NotSupported)
+    }
+
+    /// The target of a symbolic link.
+    fn read_link(&self) -> Result<String> {
+        Err(FsError::InvalidArgument)
+    }
+
+    /// The `index`-th entry of this directory, without `.` and `..`.
+    fn read_dir(&self, _index: usize) -> Result<Option<DirEntry>> {
+        Err(FsError::NotDir)
+    }
+
+    /// Performs a device-specific request; see [`File::ioctl`].
+    fn ioctl(&self, _request: u32) -> Result<Vec<u8>> {
+        Err(FsError::NotTty)
+    }
+
+    /// Writes cached changes of this inode back to the device.
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Lets a filesystem recognize its own inodes, e.g. in [`Inode::link`].
+    fn as_any(&self) -> &dyn Any;
+}
+
+pub trait FileSystem: Send + Sync {
+    /// Name of the filesystem type, e.g. `tmpfs`.
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Writes every cached change back to the device.
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Opens a filesystem of one type on a block device, failing with
+/// [`FsError::NotSupported`] if the device holds another type.
+type OpenDisk = fn(Arc<dyn BlockDevice>) -> Result<Arc<dyn FileSystem>>;
+
+/// Filesystem types that can be found on a block device, tried in order.
+static DISK_FILESYSTEMS: &[OpenDisk] = &[
+    |device| Ok(fat32::Fat32::open(device)?),
+    |device| Ok(ext2::Ext2::open(device)?),
+];
+
+/// Mounts the filesystem on `device` at `target`, detecting its type.
+pub fn mount_device(target: &str, device: Arc<dyn BlockDevice>) -> Result<()> {
+    for open in DISK_FILESYSTEMS {
+        match open(device.clone()) {
+            Ok(fs) => return mount(target, fs),
+            Err(FsError::NotSupported) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(FsError::NotSupported)
+}
+
+/// Mounts a tmpfs as the root filesystem, fills it from the initramfs, and
+/// mounts the device files at `/dev`, kernel state at `/proc` and the first
+/// disk, if any, at `/mnt`.
+///
+/// `initrd` is the physical range of an initrd loaded by the bootloader.
+pub fn init(initrd: Option<Range<usize>>) {
+    mount("/", tmpfs::TmpFs::new()).expect("failed to mount the root tmpfs");
+    initramfs::init(initrd);
+    if let Err(err) = mount_point("/dev").and_then(|_| mount("/dev", Arc::new(devfs::DevFs))) {
+        log!("Failed to mount devfs at /dev: {}", err);
+    }
+    if let Err(err) = mount_point("/proc").and_then(|_| mount("/proc", Arc::new(procfs::ProcFs))) {
+        log!("Failed to mount procfs at /proc: {}", err);
+    }
+    let Some(disk) = crate::devices::block::get(0) else {
+        return;
+    };
+    if let Err(err) = mount_point("/mnt").and_then(|_| mount_device("/mnt", disk)) {
+        log!("Failed to mount disk at /mnt: {}", err);
+    }
+}
+
+/// Creates the directory `path` to mount on, unless the initramfs has it.
+fn mount_point(path: &str) -> Result<()> {
+    match mkdir("/", path) {
+        Ok(_) | Err(FsError::Exists) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Opens `path`, relative to `cwd` unless absolute.
+pub fn open(cwd: &str, path: &str, flags: OpenFlags) -> Result<Arc<dyn File>> {
+    let inode = if flags.contains(OpenFlags::CREATE) {
+        let (parent, name) = resolve_parent(cwd, path)?;
+        match path::lookup_in(&parent, &name) {
+            Ok(_) if flags.contains(OpenFlags::EXCL) => return Err(FsError::Exists),
+            Ok(_) => resolve(cwd, path, true)?,
+            Err(FsError::NotFound) => parent.create(&name, FileType::Regular)?,
+            Err(err) => return Err(err),
+        }
+    } else {
+        resolve(cwd, path, true)?
+    };
+    let kind = inode.metadata()?.kind;
+    if kind == FileType::Directory && flags.writable() {
+        return Err(FsError::IsDir);
+    }
+    if kind != FileType::Directory && flags.contains(OpenFlags::DIRECTORY) {
+        return Err(FsError::NotDir);
+    }
+    let cache_key = match kind {
+        FileType::Regular => {
+            let canonical = path::canonicalize(cwd, path, true)?;
+            Some((mount::fs_id(&canonical), inode.metadata()?.ino))
+        }
+        _ => None,
+    };
+    if kind == FileType::Regular && flags.writable() && flags.contains(OpenFlags::TRUNC) {
+        match cache_key.and_then(page_cache::lookup) {
+            Some(cache) => cache.truncate(0)?,
+            None => inode.truncate(0)?,
+        }
+    }
+    Ok(Arc::new(InodeFile::new(inode, flags, cache_key)))
+}
+
+/// The whole contents of the file at `path`.
+pub fn read(cwd: &str, path: &str) -> Result<Vec<u8>> {
+    let file = open(cwd, path, OpenFlags::RDONLY)?;
+    let mut data = vec![0; file.metadata()?.size as usize];
+    let mut done = 0;
+    while done < data.len() {
+        match file.read(&mut data[done..])? {
+            0 => break,
+            n => done += n,
+        }
+    }
+    data.truncate(done);
+    Ok(data)
+}
+
+pub fn mkdir(cwd: &str, path: &str) -> Result<Arc<dyn Inode>> {
+    let (parent, name) = resolve_parent(cwd, path)?;
+    match path::lookup_in(&parent, &name) {
+        Ok(_) => Err(FsError::Exists),
+        Err(FsError::NotFound) => parent.create(&name, FileType::Directory),
+        Err(err) => Err(err),
+    }
+}
+
+/// Removes the non-directory entry `path`.
+pub fn unlink(cwd: &str, path: &str) -> Result<()> {
+    let (parent, name) = resolve_parent(cwd, path)?;
+    if path::lookup_in(&parent, &name)?.metadata()?.kind == FileType::Directory {
+        return Err(FsError::IsDir);
+    }
+    parent.unlink(&name)
+}
+
+/// Removes the empty directory `path`.
+pub fn rmdir(cwd: &str, path: &str) -> Result<()> {
+    let resolved = path::canonicalize(cwd, path, false)?;
+    if mount::is_mount_point(&resolved) {
+        return Err(FsError::Busy);
+    }
+    let (parent, name) = resolve_parent(cwd, path)?;
+    if path::lookup_in(&parent, &name)?.metadata()?.kind != FileType::Directory {
+        return Err(FsError::NotDir);
+    }
+    parent.unlink(&name)
+}
+
+/// Creates `new_path` as a hard link to `old_path`.
+pub fn link(cwd: &str, old_path: &str, new_path: &str) -> Result<()> {
+    let target = resolve(cwd, old_path, false)?;
+    if target.metadata()?.kind == FileType::Directory {
+        return Err(FsError::IsDir);
+    }
+    let (parent, name) = resolve_parent(cwd, new_path)?;
+    parent.link(&name, &target)
+}
+
+/// Creates a symbolic link at `link_path` pointing to `target`.
+pub fn symlink(cwd: &str, target: &str, link_path: &str) -> Result<()> {
+    let (parent, name) = resolve_parent(cwd, link_path)?;
+    parent.symlink(&name, target).map(|_| ())
+}
+
+pub fn read_link(cwd: &str, path: &str) -> Result<String> {
+    resolve(cwd, path, false)?.read_link()
+}
+
+/// Metadata of `path`, of the link itself if `follow` is false.
+pub fn stat(cwd: &str, path: &str, follow: bool) -> Result<Metadata> {
+    resolve(cwd, path, follow)?.metadata()
+}
+
+/// Every entry of the directory `path`.
+pub fn read_dir(cwd: &str, path: &str) -> Result<Vec<DirEntry>> {
+    let dir = resolve(cwd, path, true)?;
+    let mut entries = Vec::new();
+    while let Some(entry) = dir.read_dir(entries.len())? {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// How often [`writeback`] syncs, in seconds.
+const WRITEBACK_INTERVAL_SECS: u64 = 5;
+
+/// Syncs every mounted filesystem every [`WRITEBACK_INTERVAL_SECS`], so that
+/// changes reach the disk without waiting for a sync or shutdown. Runs on
+/// an executor, spawned with [`task::spawn`].
+pub async fn writeback() {
+    loop {
+        task::sleep(Duration::from_secs(WRITEBACK_INTERVAL_SECS)).await;
+        if let Err(err) = sync() {
+            log!("writeback: {}", err);
+        }
+    }
+}
+
+/// Writes every mounted filesystem back to its device.
+pub fn sync() -> Result<()> {
+    page_cache::sync_all()?;
+    for (_, fs) in mount::filesystems() {
+        fs.sync()?;
+    }
+    crate::devices::block_cache::sync_all()?;
+    Ok(())
+}
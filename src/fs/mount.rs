@@ -0,0 +1,112 @@
+//! Mount table.
+
+use super::{path, FileSystem, FileType, FsError, Inode, Result};
+use ::alloc::{string::String, sync::Arc, vec::Vec};
+use spin::RwLock;
+
+struct Mount {
+    /// Canonical absolute path of the mount point.
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+lazy_static::lazy_static! {
+    static ref MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+}
+
+/// Attaches `fs` at the directory `target`. The first filesystem mounted
+/// must go to `/`.
+pub fn mount(target: &str, fs: Arc<dyn FileSystem>) -> Result<()> {
+    let path = if MOUNTS.read().is_empty() {
+        if target != "/" {
+            return Err(FsError::NotFound);
+        }
+        String::from("/")
+    } else {
+        let path = path::canonicalize("/", target, true)?;
+        if path::resolve("/", &path, true)?.metadata()?.kind != FileType::Directory {
+            return Err(FsError::NotDir);
+        }
+        path
+    };
+    let mut mounts = MOUNTS.write();
+    if mounts.iter().any(|m| m.path == path) {
+        return Err(FsError::Busy);
+    }
+    log!("vfs: mounted {} at {}", fs.name(), path);
+    mounts.push(Mount { path, fs });
+    Ok(())
+}
+
+/// Detaches the filesystem mounted at `target` after syncing it.
+pub fn umount(target: &str) -> Result<()> {
+    let path = path::canonicalize("/", target, true)?;
+    let fs = {
+        let mounts = MOUNTS.read();
+        let mount = mounts
+            .iter()
+            .find(|m| m.path == path)
+            .ok_or(FsError::InvalidArgument)?;
+        let nested = mounts
+            .iter()
+            .any(|m| m.path != path && is_under(&m.path, &path));
+        if nested {
+            return Err(FsError::Busy);
+        }
+        mount.fs.clone()
+    };
+    fs.sync()?;
+    MOUNTS.write().retain(|m| m.path != path);
+    Ok(())
+}
+
+fn is_under(path: &str, dir: &str) -> bool {
+    dir == "/"
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Root of the filesystem mounted at the canonical `path`, if any.
+pub(super) fn mounted_root(path: &str) -> Option<Arc<dyn Inode>> {
+    MOUNTS
+        .read()
+        .iter()
+        .rev()
+        .find(|m| m.path == path)
+        .map(|m| m.fs.root())
+}
+
+/// Identifies the filesystem holding the canonical `path`, for as long as
+/// it stays mounted.
+pub(super) fn fs_id(path: &str) -> usize {
+    let mounts = MOUNTS.read();
+    let mount = mounts
+        .iter()
+        .rev()
+        .filter(|m| path == m.path || is_under(path, &m.path))
+        .max_by_key(|m| m.path.len())
+        .expect("no filesystem mounted at /");
+    Arc::as_ptr(&mount.fs) as *const () as usize
+}
+
+pub(super) fn is_mount_point(path: &str) -> bool {
+    MOUNTS.read().iter().any(|m| m.path == path)
+}
+
+pub(super) fn filesystems() -> Vec<(String, Arc<dyn FileSystem>)> {
+    MOUNTS
+        .read()
+        .iter()
+        .map(|m| (m.path.clone(), m.fs.clone()))
+        .collect()
+}
+
+/// Mount points and the type of filesystem mounted there.
+pub fn mounts() -> Vec<(String, &'static str)> {
+    MOUNTS
+        .read()
+        .iter()
+        .map(|m| (m.path.clone(), m.fs.name()))
+        .collect()
+}
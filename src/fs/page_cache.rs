@@ -0,0 +1,249 @@
+//! Frames holding the contents of files that are mapped into memory.
+//!
+//! All mappings of a file share one [`PageCache`], found by filesystem and
+//! inode number, so that shared mappings see each other's writes. While it
+//! exists, reads and writes of the file go through it as well. Pages
+//! written through a mapping go back to the file when it is synced, and
+//! when the last mapping of it goes away.
+
+use super::{Inode, Result};
+use crate::mm::{frame_alloc, FrameTracker, SharedPages, PAGE_SIZE};
+use ::alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use lazy_static::lazy_static;
+use spin::{Mutex, MutexGuard};
+
+/// Identifies a file: the filesystem it is on, and its inode number there.
+pub type CacheKey = (usize, u64);
+
+struct CachedPage {
+    frame: Arc<FrameTracker>,
+    /// Written through a mapping since the file was last synced.
+    dirty: bool,
+}
+
+pub struct PageCache {
+    inode: Arc<dyn Inode>,
+    key: CacheKey,
+    /// Pages read in so far, by index in the file.
+    pages: Mutex<BTreeMap<usize, CachedPage>>,
+}
+
+struct Registry {
+    /// Caches of files that are mapped somewhere.
+    caches: BTreeMap<CacheKey, Weak<PageCache>>,
+    /// Files whose last cache is being written back as it goes away, each
+    /// with a lock held until that is done.
+    writebacks: BTreeMap<CacheKey, Arc<Mutex<()>>>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+        caches: BTreeMap::new(),
+        writebacks: BTreeMap::new(),
+    });
+}
+
+/// The registry, once no cache of the file `key` is going away, so that the
+/// file is not read before the writes of its last cache land.
+fn registry_for(key: CacheKey) -> MutexGuard<'static, Registry> {
+    loop {
+        let registry = REGISTRY.lock();
+        let writeback = registry.writebacks.get(&key).cloned();
+        // A cache whose last reference is gone but that has not yet taken
+        // itself out of the map is about to be written back.
+        let dropping = registry
+            .caches
+            .get(&key)
+            .is_some_and(|cache| cache.strong_count() == 0);
+        if writeback.is_none() && !dropping {
+            return registry;
+        }
+        drop(registry);
+        match writeback {
+            Some(writeback) => drop(writeback.lock()),
+            None => core::hint::spin_loop(),
+        }
+    }
+}
+
+/// The cache of the file `key`, with `inode` to read it through if there is
+/// none yet.
+pub fn get_or_create(key: CacheKey, inode: Arc<dyn Inode>) -> Arc<PageCache> {
+    let mut registry = registry_for(key);
+    if let Some(cache) = registry.caches.get(&key).and_then(Weak::upgrade) {
+        return cache;
+    }
+    let cache = Arc::new(PageCache {
+        inode,
+        key,
+        pages: Mutex::new(BTreeMap::new()),
+    });
+    registry.caches.insert(key, Arc::downgrade(&cache));
+    cache
+}
+
+/// The cache of the file `key`, if it is mapped.
+pub fn lookup(key: CacheKey) -> Option<Arc<PageCache>> {
+    registry_for(key).caches.get(&key).and_then(Weak::upgrade)
+}
+
+/// Writes back the pages written through mappings of every file.
+pub fn sync_all() -> Result<()> {
+    let caches: Vec<Arc<PageCache>> = REGISTRY
+        .lock()
+        .caches
+        .values()
+        .filter_map(Weak::upgrade)
+        .collect();
+    for cache in caches {
+        cache.sync()?;
+    }
+    Ok(())
+}
+
+impl PageCache {
+    /// Reads from the file at `offset`, taking the pages that are cached
+    /// from the cache.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let pages = self.pages.lock();
+        let size = self.inode.metadata()?.size as usize;
+        let len = buf.len().min(size.saturating_sub(offset));
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let n = (PAGE_SIZE - in_page).min(len - done);
+            match pages.get(&(pos / PAGE_SIZE)) {
+                Some(page) => buf[done..done + n]
+                    .copy_from_slice(&page.frame.ppn.get_bytes_array()[in_page..in_page + n]),
+                None => {
+                    let read = self.inode.read_at(pos, &mut buf[done..done + n])?;
+                    if read < n {
+                        return Ok(done + read);
+                    }
+                }
+            }
+            done += n;
+        }
+        Ok(done)
+    }
+
+    /// Writes to the file at `offset`, and to the pages of it that are
+    /// cached.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let pages = self.pages.lock();
+        let written = self.inode.write_at(offset, buf)?;
+        let mut done = 0;
+        while done < written {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let n = (PAGE_SIZE - in_page).min(written - done);
+            if let Some(page) = pages.get(&(pos / PAGE_SIZE)) {
+                page.frame.ppn.get_bytes_array()[in_page..in_page + n]
+                    .copy_from_slice(&buf[done..done + n]);
+            }
+            done += n;
+        }
+        Ok(written)
+    }
+
+    /// Truncates the file to `size` bytes. Cached pages stay mapped, but
+    /// read as zeros past the new end.
+    pub fn truncate(&self, size: usize) -> Result<()> {
+        let pages = self.pages.lock();
+        self.inode.truncate(size)?;
+        for (&index, page) in pages.range(size / PAGE_SIZE..) {
+            let start = size.saturating_sub(index * PAGE_SIZE);
+            page.frame.ppn.get_bytes_array()[start..].fill(0);
+        }
+        Ok(())
+    }
+
+    /// Writes the pages written through mappings back to the file, up to
+    /// its end.
+    pub fn sync(&self) -> Result<()> {
+        let mut pages = self.pages.lock();
+        let size = self.inode.metadata()?.size as usize;
+        for (&index, page) in pages.iter_mut().filter(|(_, page)| page.dirty) {
+            let start = index * PAGE_SIZE;
+            let len = PAGE_SIZE.min(size.saturating_sub(start));
+            self.inode
+                .write_at(start, &page.frame.ppn.get_bytes_array()[..len])?;
+            // Mappings write to the page again without faulting, so it
+            // stays dirty while mapped.
+            page.dirty = Arc::strong_count(&page.frame) > 1;
+        }
+        Ok(())
+    }
+}
+
+impl SharedPages for PageCache {
+    /// Reads the page in if it is not cached. Past the end of the file it
+    /// is zeros.
+    fn page(&self, index: usize) -> Option<Arc<FrameTracker>> {
+        let mut pages = self.pages.lock();
+        if let Some(page) = pages.get(&index) {
+            return Some(page.frame.clone());
+        }
+        let frame = Arc::new(frame_alloc()?);
+        let bytes = frame.ppn.get_bytes_array();
+        let mut done = 0;
+        while done < PAGE_SIZE {
+            match self
+                .inode
+                .read_at(index * PAGE_SIZE + done, &mut bytes[done..])
+            {
+                Ok(0) => break,
+                Ok(n) => done += n,
+                Err(_) => return None,
+            }
+        }
+        pages.insert(
+            index,
+            CachedPage {
+                frame: frame.clone(),
+                dirty: false,
+            },
+        );
+        Some(frame)
+    }
+
+    fn mark_dirty(&self, index: usize) {
+        if let Some(page) = self.pages.lock().get_mut(&index) {
+            page.dirty = true;
+        }
+    }
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        // Until the writes land, lookups of the file wait for `writeback`
+        // instead of reading it, without the registry held meanwhile.
+        let writeback = Arc::new(Mutex::new(()));
+        let writing = writeback.lock();
+        {
+            let mut registry = REGISTRY.lock();
+            if registry
+                .caches
+                .get(&self.key)
+                .is_some_and(|cache| cache.strong_count() == 0)
+            {
+                registry.caches.remove(&self.key);
+            }
+            registry.writebacks.insert(self.key, writeback.clone());
+        }
+        if let Err(err) = self.sync() {
+            log!(
+                "page cache: failed to write back inode {}: {}",
+                self.key.1,
+                err
+            );
+        }
+        REGISTRY.lock().writebacks.remove(&self.key);
+        drop(writing);
+    }
+}
@@ -0,0 +1,151 @@
+//! Path resolution.
+//!
+//! Paths are walked one component at a time from `/` or the working
+//! directory. Entering a mount point switches to the root of the filesystem
+//! mounted there, and `..` returns to the directory the walk came from, so it
+//! works across mount points and never leaves `/`.
+
+use super::{mount, FileType, FsError, Inode, Result, NAME_MAX};
+use ::alloc::{collections::VecDeque, string::String, sync::Arc, vec::Vec};
+
+/// Symbolic links followed in one resolution before giving up.
+const MAX_SYMLINKS: usize = 40;
+
+/// Directories walked so far, from just below `/` down.
+struct Walk {
+    root: Arc<dyn Inode>,
+    stack: Vec<(String, Arc<dyn Inode>)>,
+}
+
+impl Walk {
+    fn current(&self) -> &Arc<dyn Inode> {
+        self.stack.last().map_or(&self.root, |(_, inode)| inode)
+    }
+
+    fn path(&self) -> String {
+        if self.stack.is_empty() {
+            return String::from("/");
+        }
+        let mut path = String::new();
+        for (name, _) in self.stack.iter() {
+            path.push('/');
+            path.push_str(name);
+        }
+        path
+    }
+
+    fn child_path(&self, name: &str) -> String {
+        let mut path = self.path();
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+        path.push_str(name);
+        path
+    }
+}
+
+fn components(path: &str) -> impl Iterator<Item = String> + '_ {
+    path.split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .map(String::from)
+}
+
+/// Looks `name` up in `dir`, entering a filesystem mounted there.
+///
+/// `path` is the canonical path of the entry, or `None` if not known, in
+/// which case mount points are not considered.
+fn step(dir: &Arc<dyn Inode>, path: Option<String>, name: &str) -> Result<Arc<dyn Inode>> {
+    if name.len() > NAME_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    if dir.metadata()?.kind != FileType::Directory {
+        return Err(FsError::NotDir);
+    }
+    if let Some(root) = path.and_then(|path| mount::mounted_root(&path)) {
+        return Ok(root);
+    }
+    dir.lookup(name)
+}
+
+fn walk(cwd: &str, path: &str, follow_last: bool) -> Result<Walk> {
+    if path.is_empty() {
+        return Err(FsError::NotFound);
+    }
+    let root = mount::mounted_root("/").ok_or(FsError::NotFound)?;
+    let mut walk = Walk {
+        root,
+        stack: Vec::new(),
+    };
+    let mut pending: VecDeque<String> = VecDeque::new();
+    if !path.starts_with('/') {
+        pending.extend(components(cwd));
+    }
+    pending.extend(components(path));
+    let mut followed = 0;
+    while let Some(name) = pending.pop_front() {
+        if name == ".." {
+            walk.stack.pop();
+            continue;
+        }
+        let child_path = walk.child_path(&name);
+        let inode = step(walk.current(), Some(child_path), &name)?;
+        let is_last = pending.is_empty();
+        if inode.metadata()?.kind == FileType::Symlink && (!is_last || follow_last) {
+            followed += 1;
+            if followed > MAX_SYMLINKS {
+                return Err(FsError::Loop);
+            }
+            let target = inode.read_link()?;
+            if target.starts_with('/') {
+                walk.stack.clear();
+            }
+            for component in components(&target).collect::<Vec<_>>().into_iter().rev() {
+                pending.push_front(component);
+            }
+            continue;
+        }
+        walk.stack.push((name, inode));
+    }
+    Ok(walk)
+}
+
+/// Finds the inode at `path`, relative to the canonical directory `cwd`
+/// unless absolute. A symbolic link as the last component is only followed
+/// if `follow_last` is set.
+pub fn resolve(cwd: &str, path: &str, follow_last: bool) -> Result<Arc<dyn Inode>> {
+    let walk = walk(cwd, path, follow_last)?;
+    Ok(walk.current().clone())
+}
+
+/// The absolute path `path` refers to, with `.`, `..` and symbolic links
+/// resolved away.
+pub fn canonicalize(cwd: &str, path: &str, follow_last: bool) -> Result<String> {
+    Ok(walk(cwd, path, follow_last)?.path())
+}
+
+/// Splits `path` into its parent directory, which is resolved, and the last
+/// component, which need not exist.
+pub fn resolve_parent(cwd: &str, path: &str) -> Result<(Arc<dyn Inode>, String)> {
+    let trimmed = path.trim_end_matches('/');
+    let (dir, name) = match trimmed.rfind('/') {
+        Some(0) => ("/", &trimmed[1..]),
+        Some(index) => (&trimmed[..index], &trimmed[index + 1..]),
+        None => (".", trimmed),
+    };
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(FsError::InvalidArgument);
+    }
+    if name.len() > NAME_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    let parent = resolve(cwd, dir, true)?;
+    if parent.metadata()?.kind != FileType::Directory {
+        return Err(FsError::NotDir);
+    }
+    Ok((parent, String::from(name)))
+}
+
+/// Looks up a single entry of `dir`, without crossing mount points.
+pub(super) fn lookup_in(dir: &Arc<dyn Inode>, name: &str) -> Result<Arc<dyn Inode>> {
+    step(dir, None, name)
+}
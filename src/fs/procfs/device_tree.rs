@@ -0,0 +1,101 @@
+//! `/proc/device-tree`: a directory per node and a file per property
+//! holding its raw value.
+//!
+//! The tree is never modified once loaded, so the addresses of its nodes
+//! and values serve as inode numbers.
+
+use super::read_slice;
+use crate::fs::{DirEntry, FileType, FsError, Inode, Metadata, Result};
+use ::alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+use dtb::Node;
+
+pub struct DtDir {
+    node: &'static Node,
+}
+
+impl DtDir {
+    pub fn new(node: &'static Node) -> Self {
+        DtDir { node }
+    }
+}
+
+struct DtProp {
+    value: &'static Vec<u8>,
+}
+
+impl DtProp {
+    fn ino(&self) -> u64 {
+        self.value as *const Vec<u8> as u64
+    }
+}
+
+impl Inode for DtDir {
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            ino: self.node as *const Node as u64,
+            kind: FileType::Directory,
+            size: 0,
+            nlink: 2 + self.node.children.len() as u32,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        if let Some(value) = self.node.props.get(name) {
+            return Ok(Arc::new(DtProp { value }));
+        }
+        self.node
+            .child(name)
+            .map(|child| Arc::new(DtDir::new(child)) as Arc<dyn Inode>)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>> {
+        Err(FsError::NotSupported)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn read_dir(&self, index: usize) -> Result<Option<DirEntry>> {
+        let props = self.node.props.iter().map(|(name, value)| DirEntry {
+            name: name.clone(),
+            ino: DtProp { value }.ino(),
+            kind: FileType::Regular,
+        });
+        let children = self.node.children.iter().map(|child| DirEntry {
+            name: String::from(child.name.as_str()),
+            ino: child as *const Node as u64,
+            kind: FileType::Directory,
+        });
+        Ok(props.chain(children).nth(index))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Inode for DtProp {
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            ino: self.ino(),
+            kind: FileType::Regular,
+            size: self.value.len() as u64,
+            nlink: 1,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        Ok(read_slice(self.value, offset, buf))
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,245 @@
+//! Kernel state as files, mounted at `/proc`.
+//!
+//! Files are generated from the current state each time they are read, so
+//! they report a size of 0, as on Linux.
+
+mod device_tree;
+mod pid;
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Metadata, Result};
+use crate::devices::{device_tree as dt, driver, plic};
+use crate::{mm, process, sbi, timer};
+use ::alloc::{format, string::String, sync::Arc};
+use core::any::Any;
+use core::fmt::Write;
+
+const ROOT_INO: u64 = 1;
+
+/// Produces the contents of a file.
+type Generate = fn() -> String;
+
+/// Files in the root directory, numbered from inode 2 in this order.
+static FILES: &[(&str, Generate)] = &[
+    ("meminfo", meminfo),
+    ("cpuinfo", cpuinfo),
+    ("uptime", uptime),
+    ("interrupts", interrupts),
+    ("cmdline", cmdline),
+];
+
+const DEVICE_TREE: &str = "device-tree";
+
+/// Copies what `buf` can hold of `data` from `offset` on.
+fn read_slice(data: &[u8], offset: usize, buf: &mut [u8]) -> usize {
+    let data = data.get(offset..).unwrap_or_default();
+    let len = data.len().min(buf.len());
+    buf[..len].copy_from_slice(&data[..len]);
+    len
+}
+
+fn meminfo() -> String {
+    let stats = mm::stats();
+    let mut out = String::new();
+    for (name, bytes) in [
+        ("MemTotal", stats.total),
+        ("MemFree", stats.free),
+        ("HeapTotal", stats.heap_total),
+        ("HeapUsed", stats.heap_used),
+    ] {
+        writeln!(out, "{:<10}{:>10} kB", format!("{}:", name), bytes / 1024).unwrap();
+    }
+    out
+}
+
+/// Names of the SBI hart state management states.
+fn hart_state(hart: usize) -> &'static str {
+    let ret = sbi::hart_get_status(hart);
+    if ret.error != 0 {
+        return "unknown";
+    }
+    match ret.value {
+        0 => "started",
+        1 => "stopped",
+        2 => "start pending",
+        3 => "stop pending",
+        4 => "suspended",
+        5 => "suspend pending",
+        6 => "resume pending",
+        _ => "unknown",
+    }
+}
+
+fn cpuinfo() -> String {
+    let mut out = String::new();
+    let Some(cpus) = dt::tree().root.child("cpus") else {
+        return out;
+    };
+    let harts = cpus
+        .children
+        .iter()
+        .filter(|node| node.prop_str("device_type") == Some("cpu"));
+    for (processor, cpu) in harts.enumerate() {
+        let Some(hart) = cpu.prop_u32("reg") else {
+            continue;
+        };
+        let hart = hart as usize;
+        writeln!(out, "processor\t: {}", processor).unwrap();
+        writeln!(out, "hart\t\t: {}", hart).unwrap();
+        if let Some(isa) = cpu.prop_str("riscv,isa") {
+            writeln!(out, "isa\t\t: {}", isa).unwrap();
+        }
+        if let Some(mmu) = cpu.prop_str("mmu-type") {
+            writeln!(out, "mmu\t\t: {}", mmu.trim_start_matches("riscv,")).unwrap();
+        }
+        writeln!(out, "status\t\t: {}", hart_state(hart)).unwrap();
+        out.push('\n');
+    }
+    out
+}
+
+fn uptime() -> String {
+    let frequency = timer::frequency();
+    let ticks = timer::ticks();
+    let hundredths = ticks % frequency * 100 / frequency;
+    // Idle time is not accounted yet.
+    format!("{}.{:02} 0.00\n", ticks / frequency, hundredths)
+}
+
+fn interrupts() -> String {
+    let mut out = String::from("    ");
+    for hart in plic::harts() {
+        write!(out, " {:>10}", format!("CPU{}", hart)).unwrap();
+    }
+    out.push('\n');
+    let devices = driver::devices();
+    for (irq, counts) in plic::irq_counts() {
+        write!(out, "{:>3}:", irq).unwrap();
+        for count in counts {
+            write!(out, " {:>10}", count).unwrap();
+        }
+        let device = devices.iter().find(|device| device.irqs.contains(&irq));
+        match device {
+            Some(device) => writeln!(out, "  PLIC  {}", device.path).unwrap(),
+            None => writeln!(out, "  PLIC").unwrap(),
+        }
+    }
+    out
+}
+
+fn cmdline() -> String {
+    format!("{}\n", dt::bootargs())
+}
+
+struct ProcFile {
+    ino: u64,
+    generate: Generate,
+}
+
+impl Inode for ProcFile {
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            ino: self.ino,
+            kind: FileType::Regular,
+            size: 0,
+            nlink: 1,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        Ok(read_slice((self.generate)().as_bytes(), offset, buf))
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+struct ProcRoot;
+
+impl Inode for ProcRoot {
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            ino: ROOT_INO,
+            kind: FileType::Directory,
+            size: 0,
+            nlink: 3,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        if name == DEVICE_TREE {
+            return Ok(Arc::new(device_tree::DtDir::new(&dt::tree().root)));
+        }
+        // PIDs are written in decimal with no sign or leading zeros.
+        let is_pid = name.bytes().all(|byte| byte.is_ascii_digit()) && !name.starts_with('0');
+        if let (true, Ok(pid)) = (is_pid, name.parse::<usize>()) {
+            return pid::PidDir::new(pid)
+                .map(|dir| Arc::new(dir) as Arc<dyn Inode>)
+                .ok_or(FsError::NotFound);
+        }
+        FILES
+            .iter()
+            .enumerate()
+            .find(|(_, (file, _))| *file == name)
+            .map(|(index, &(_, generate))| {
+                Arc::new(ProcFile {
+                    ino: ROOT_INO + 1 + index as u64,
+                    generate,
+                }) as Arc<dyn Inode>
+            })
+            .ok_or(FsError::NotFound)
+    }
+
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>> {
+        Err(FsError::NotSupported)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn read_dir(&self, index: usize) -> Result<Option<DirEntry>> {
+        if let Some((name, _)) = FILES.get(index) {
+            return Ok(Some(DirEntry {
+                name: String::from(*name),
+                ino: ROOT_INO + 1 + index as u64,
+                kind: FileType::Regular,
+            }));
+        }
+        if index == FILES.len() {
+            let dir = device_tree::DtDir::new(&dt::tree().root);
+            return Ok(Some(DirEntry {
+                name: String::from(DEVICE_TREE),
+                ino: dir.metadata()?.ino,
+                kind: FileType::Directory,
+            }));
+        }
+        Ok(process::pids()
+            .get(index - FILES.len() - 1)
+            .map(|&pid| DirEntry {
+                name: format!("{}", pid),
+                ino: pid::dir_ino(pid),
+                kind: FileType::Directory,
+            }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct ProcFs;
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &'static str {
+        "proc"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(ProcRoot)
+    }
+}
@@ -0,0 +1,201 @@
+//! `/proc/<pid>`: a directory per process.
+//!
+//! A directory holds on to its process only weakly, so once the process is
+//! reaped the directory and its files report it as gone.
+
+use super::read_slice;
+use crate::fs::{DirEntry, FileType, FsError, Inode, Metadata, Result};
+use crate::process::{self, Process, State};
+use crate::{hart, timer};
+use ::alloc::{
+    format,
+    string::String,
+    sync::{Arc, Weak},
+};
+use core::any::Any;
+use core::fmt::Write;
+
+/// Inode numbers of process directories start here, with room for the
+/// files in each after it.
+const PID_INO_BASE: u64 = 1 << 32;
+const INOS_PER_PID: u64 = 16;
+
+/// Produces the contents of a file from its process.
+type Generate = fn(&Process) -> String;
+
+/// Files in each process directory, numbered after the directory in this
+/// order.
+static FILES: &[(&str, Generate)] = &[
+    ("status", status),
+    ("cmdline", cmdline),
+    ("schedstat", schedstat),
+];
+
+fn status(process: &Process) -> String {
+    let stats = process.sched_stats().unwrap_or_default();
+    let affinity = process.affinity();
+    let inner = process.inner();
+    let name = inner
+        .argv
+        .first()
+        .map(|arg0| arg0.rsplit('/').next().unwrap_or_default())
+        .unwrap_or_default();
+    let state = match inner.state {
+        State::Ready | State::Running => "R (running)",
+        State::Waiting => "S (sleeping)",
+        State::Zombie => "Z (zombie)",
+    };
+    let mut out = String::new();
+    writeln!(out, "Name:\t{}", name).unwrap();
+    writeln!(out, "State:\t{}", state).unwrap();
+    writeln!(out, "Pid:\t{}", process.pid()).unwrap();
+    writeln!(out, "PPid:\t{}", inner.ppid()).unwrap();
+    let vm_size = inner.memory_set().user_size() / 1024;
+    writeln!(out, "VmSize:\t{:>8} kB", vm_size).unwrap();
+    if let Some(affinity) = affinity {
+        writeln!(out, "Cpus_allowed:\t{:x}", affinity & hart::online_mask()).unwrap();
+    }
+    writeln!(
+        out,
+        "voluntary_ctxt_switches:\t{}",
+        stats.voluntary_switches
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "nonvoluntary_ctxt_switches:\t{}",
+        stats.involuntary_switches
+    )
+    .unwrap();
+    out
+}
+
+/// Time spent running and waiting to run, in nanoseconds, and how many
+/// times the process ran, as on Linux. All zeros once it has exited.
+fn schedstat(process: &Process) -> String {
+    let stats = process.sched_stats().unwrap_or_default();
+    format!(
+        "{} {} {}\n",
+        timer::ticks_to_nanos(stats.runtime),
+        timer::ticks_to_nanos(stats.wait_time),
+        stats.voluntary_switches + stats.involuntary_switches
+    )
+}
+
+/// The arguments, each ending in a NUL.
+fn cmdline(process: &Process) -> String {
+    process
+        .inner()
+        .argv
+        .iter()
+        .map(|arg| format!("{}\0", arg))
+        .collect()
+}
+
+/// Inode number of the directory for `pid`.
+pub fn dir_ino(pid: usize) -> u64 {
+    PID_INO_BASE + pid as u64 * INOS_PER_PID
+}
+
+pub struct PidDir {
+    pid: usize,
+    process: Weak<Process>,
+}
+
+impl PidDir {
+    /// The directory for the process with PID `pid`, if there is one.
+    pub fn new(pid: usize) -> Option<Self> {
+        let process = process::get(pid)?;
+        Some(PidDir {
+            pid,
+            process: Arc::downgrade(&process),
+        })
+    }
+
+    fn process(&self) -> Result<Arc<Process>> {
+        self.process.upgrade().ok_or(FsError::NotFound)
+    }
+}
+
+struct PidFile {
+    ino: u64,
+    process: Weak<Process>,
+    generate: Generate,
+}
+
+impl Inode for PidDir {
+    fn metadata(&self) -> Result<Metadata> {
+        self.process()?;
+        Ok(Metadata {
+            ino: dir_ino(self.pid),
+            kind: FileType::Directory,
+            size: 0,
+            nlink: 2,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        self.process()?;
+        FILES
+            .iter()
+            .enumerate()
+            .find(|(_, (file, _))| *file == name)
+            .map(|(index, &(_, generate))| {
+                Arc::new(PidFile {
+                    ino: dir_ino(self.pid) + 1 + index as u64,
+                    process: self.process.clone(),
+                    generate,
+                }) as Arc<dyn Inode>
+            })
+            .ok_or(FsError::NotFound)
+    }
+
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>> {
+        Err(FsError::NotSupported)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn read_dir(&self, index: usize) -> Result<Option<DirEntry>> {
+        self.process()?;
+        Ok(FILES.get(index).map(|(name, _)| DirEntry {
+            name: String::from(*name),
+            ino: dir_ino(self.pid) + 1 + index as u64,
+            kind: FileType::Regular,
+        }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Inode for PidFile {
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            ino: self.ino,
+            kind: FileType::Regular,
+            size: 0,
+            nlink: 1,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let process = self.process.upgrade().ok_or(FsError::NotFound)?;
+        Ok(read_slice(
+            (self.generate)(&process).as_bytes(),
+            offset,
+            buf,
+        ))
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,280 @@
+//! Filesystem kept entirely in memory.
+//!
+//! File contents live in page frames taken from the frame allocator, one
+//! frame per page of the file, with holes left unallocated; everything else
+//! lives on the kernel heap. Nothing survives a reboot.
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Metadata, Result, NAME_MAX};
+use crate::mm::{frame_alloc, FrameTracker, PAGE_SIZE};
+use ::alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use spin::RwLock;
+
+enum Content {
+    File {
+        size: usize,
+        pages: Vec<Option<FrameTracker>>,
+    },
+    Dir(BTreeMap<String, Arc<TmpInode>>),
+    Symlink(String),
+}
+
+pub struct TmpInode {
+    this: Weak<TmpInode>,
+    fs: Weak<TmpFs>,
+    ino: u64,
+    nlink: AtomicU32,
+    content: RwLock<Content>,
+}
+
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+    next_ino: AtomicU64,
+}
+
+impl TmpFs {
+    pub fn new() -> Arc<TmpFs> {
+        Arc::new_cyclic(|fs: &Weak<TmpFs>| TmpFs {
+            root: TmpInode::new(fs.clone(), 1, Content::Dir(BTreeMap::new())),
+            next_ino: AtomicU64::new(2),
+        })
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+impl TmpInode {
+    fn new(fs: Weak<TmpFs>, ino: u64, content: Content) -> Arc<TmpInode> {
+        Arc::new_cyclic(|this| TmpInode {
+            this: this.clone(),
+            fs,
+            ino,
+            nlink: AtomicU32::new(1),
+            content: RwLock::new(content),
+        })
+    }
+
+    /// Creates an inode in the same filesystem.
+    fn sibling(&self, content: Content) -> Result<Arc<TmpInode>> {
+        let fs = self.fs.upgrade().ok_or(FsError::NotFound)?;
+        let ino = fs.next_ino.fetch_add(1, Ordering::Relaxed);
+        Ok(TmpInode::new(self.fs.clone(), ino, content))
+    }
+
+    /// Adds `inode` to this directory as `name`.
+    fn insert(&self, name: &str, inode: Arc<TmpInode>) -> Result<()> {
+        if name.len() > NAME_MAX {
+            return Err(FsError::NameTooLong);
+        }
+        match &mut *self.content.write() {
+            Content::Dir(entries) => {
+                if entries.contains_key(name) {
+                    return Err(FsError::Exists);
+                }
+                entries.insert(String::from(name), inode);
+                Ok(())
+            }
+            _ => Err(FsError::NotDir),
+        }
+    }
+}
+
+/// Zeroes `range` of the page, if it is allocated.
+fn clear(page: &Option<FrameTracker>, range: core::ops::Range<usize>) {
+    if let Some(frame) = page {
+        frame.ppn.get_bytes_array()[range].fill(0);
+    }
+}
+
+impl Inode for TmpInode {
+    fn metadata(&self) -> Result<Metadata> {
+        let (kind, size) = match &*self.content.read() {
+            Content::File { size, .. } => (FileType::Regular, *size),
+            Content::Dir(entries) => (FileType::Directory, entries.len()),
+            Content::Symlink(target) => (FileType::Symlink, target.len()),
+        };
+        Ok(Metadata {
+            ino: self.ino,
+            kind,
+            size: size as u64,
+            nlink: self.nlink.load(Ordering::Relaxed),
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let content = self.content.read();
+        let (size, pages) = match &*content {
+            Content::File { size, pages } => (*size, pages),
+            _ => return Err(FsError::IsDir),
+        };
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let n = (PAGE_SIZE - in_page).min(len - done);
+            let out = &mut buf[done..done + n];
+            match &pages[pos / PAGE_SIZE] {
+                Some(frame) => {
+                    out.copy_from_slice(&frame.ppn.get_bytes_array()[in_page..in_page + n])
+                }
+                None => out.fill(0),
+            }
+            done += n;
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut content = self.content.write();
+        let (size, pages) = match &mut *content {
+            Content::File { size, pages } => (size, pages),
+            _ => return Err(FsError::IsDir),
+        };
+        let end = offset.checked_add(buf.len()).ok_or(FsError::NoSpace)?;
+        if pages.len() < end.div_ceil(PAGE_SIZE) {
+            pages.resize_with(end.div_ceil(PAGE_SIZE), || None);
+        }
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let n = (PAGE_SIZE - in_page).min(buf.len() - done);
+            let page = &mut pages[pos / PAGE_SIZE];
+            if page.is_none() {
+                *page = Some(frame_alloc().ok_or(FsError::NoSpace)?);
+            }
+            let frame = page.as_ref().unwrap();
+            frame.ppn.get_bytes_array()[in_page..in_page + n].copy_from_slice(&buf[done..done + n]);
+            done += n;
+            *size = (*size).max(pos + n);
+        }
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, new_size: usize) -> Result<()> {
+        let mut content = self.content.write();
+        let (size, pages) = match &mut *content {
+            Content::File { size, pages } => (size, pages),
+            _ => return Err(FsError::IsDir),
+        };
+        if new_size < *size {
+            pages.truncate(new_size.div_ceil(PAGE_SIZE));
+            // Bytes past the end must read as zero if the file grows again.
+            if let Some(last) = pages.last() {
+                let in_page = new_size % PAGE_SIZE;
+                if in_page != 0 {
+                    clear(last, in_page..PAGE_SIZE);
+                }
+            }
+        } else {
+            pages.resize_with(new_size.div_ceil(PAGE_SIZE), || None);
+        }
+        *size = new_size;
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        match &*self.content.read() {
+            Content::Dir(entries) => entries
+                .get(name)
+                .map(|inode| inode.clone() as Arc<dyn Inode>)
+                .ok_or(FsError::NotFound),
+            _ => Err(FsError::NotDir),
+        }
+    }
+
+    fn create(&self, name: &str, kind: FileType) -> Result<Arc<dyn Inode>> {
+        let content = match kind {
+            FileType::Regular => Content::File {
+                size: 0,
+                pages: Vec::new(),
+            },
+            FileType::Directory => Content::Dir(BTreeMap::new()),
+            _ => return Err(FsError::NotSupported),
+        };
+        let inode = self.sibling(content)?;
+        self.insert(name, inode.clone())?;
+        Ok(inode)
+    }
+
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> Result<()> {
+        let target = target
+            .as_any()
+            .downcast_ref::<TmpInode>()
+            .filter(|t| Weak::ptr_eq(&t.fs, &self.fs))
+            .and_then(|t| t.this.upgrade())
+            .ok_or(FsError::CrossDevice)?;
+        self.insert(name, target.clone())?;
+        target.nlink.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        let mut content = self.content.write();
+        let entries = match &mut *content {
+            Content::Dir(entries) => entries,
+            _ => return Err(FsError::NotDir),
+        };
+        let inode = entries.get(name).ok_or(FsError::NotFound)?;
+        if let Content::Dir(children) = &*inode.content.read() {
+            if !children.is_empty() {
+                return Err(FsError::NotEmpty);
+            }
+        }
+        let inode = entries.remove(name).unwrap();
+        inode.nlink.fetch_sub(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>> {
+        let inode = self.sibling(Content::Symlink(String::from(target)))?;
+        self.insert(name, inode.clone())?;
+        Ok(inode)
+    }
+
+    fn read_link(&self) -> Result<String> {
+        match &*self.content.read() {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn read_dir(&self, index: usize) -> Result<Option<DirEntry>> {
+        let content = self.content.read();
+        let entries = match &*content {
+            Content::Dir(entries) => entries,
+            _ => return Err(FsError::NotDir),
+        };
+        Ok(entries.iter().nth(index).map(|(name, inode)| DirEntry {
+            name: name.clone(),
+            ino: inode.ino,
+            kind: match &*inode.content.read() {
+                Content::File { .. } => FileType::Regular,
+                Content::Dir(_) => FileType::Directory,
+                Content::Symlink(_) => FileType::Symlink,
+            },
+        }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
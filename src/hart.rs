@@ -0,0 +1,90 @@
+//! Harts: which one is running, which ones are up, and starting and
+//! interrupting the others.
+
+use crate::devices::device_tree as dt;
+use crate::mm::{frame_alloc_contiguous, PAGE_SIZE};
+use crate::sbi;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use riscv::register::sie;
+
+/// Harts the kernel keeps per-hart state for, numbered from 0.
+pub const MAX_HARTS: usize = 8;
+
+/// Size of the stack a secondary hart boots on, the same as the boot hart's.
+const BOOT_STACK_PAGES: usize = 16;
+
+/// Harts running tasks, one bit per hart ID.
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// ID of the hart running this code.
+///
+/// The boot code keeps the hart ID in `tp`, which is never touched otherwise.
+#[inline(always)]
+pub fn id() -> usize {
+    let id;
+    unsafe {
+        asm!("mv {}, tp", out(reg) id);
+    }
+    id
+}
+
+/// Marks this hart as running tasks, and lets other harts interrupt it.
+pub fn set_online() {
+    unsafe { sie::set_ssoft() };
+    ONLINE.fetch_or(1 << id(), Ordering::Release);
+}
+
+/// Harts running tasks, one bit per hart ID.
+pub fn online_mask() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// IDs of the harts running tasks.
+pub fn online() -> impl Iterator<Item = usize> {
+    let mask = online_mask();
+    (0..MAX_HARTS).filter(move |hart| mask & (1 << hart) != 0)
+}
+
+/// Starts every other hart in the device tree at `_start_secondary`, on a
+/// stack of its own, and waits until each is running tasks.
+pub fn start_secondaries() {
+    extern "C" {
+        fn _start_secondary();
+    }
+    for hart in dt::harts() {
+        if hart == id() {
+            continue;
+        }
+        if hart >= MAX_HARTS {
+            log!("hart {}: only {} harts are supported", hart, MAX_HARTS);
+            continue;
+        }
+        let Some(stack) = frame_alloc_contiguous(BOOT_STACK_PAGES) else {
+            log!("hart {}: no memory for a boot stack", hart);
+            continue;
+        };
+        let top = stack.pa().0 + BOOT_STACK_PAGES * PAGE_SIZE;
+        let ret = sbi::hart_start(hart, _start_secondary as usize, top);
+        if ret.error != 0 {
+            log!("hart {}: failed to start: {:?}", hart, ret);
+            continue;
+        }
+        // The hart runs on the stack until shutdown.
+        core::mem::forget(stack);
+        while online_mask() & (1 << hart) == 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Interrupts `hart`, waking it up if it waits for an interrupt.
+pub fn send_ipi(hart: usize) {
+    sbi::send_ipi(1 << hart, 0);
+}
+
+/// Handles an interrupt from another hart, which only wakes this one.
+pub fn handle_ipi() {
+    // Clear `sip.SSIP`, which the SBI set.
+    unsafe { asm!("csrci sip, 2") };
+}
@@ -1,31 +1,40 @@
 #![no_std]
 #![no_main]
-#![feature(panic_info_message)]
-#![feature(default_alloc_error_handler)]
 
 #[macro_use]
 mod devices;
+mod fs;
+mod hart;
+mod mm;
 mod panic;
+mod process;
 mod sbi;
+mod syscall;
+mod task;
+mod timer;
+mod trap;
 
 extern crate alloc;
 
 use alloc::string::*;
 use alloc::*;
-use buddy_system_allocator::LockedHeap;
 use core::arch::global_asm;
 
-#[global_allocator]
-static HEAP: LockedHeap<32> = LockedHeap::empty();
-
 global_asm!(
     "
    .section .text.entry
    .globl _start
 _start:
     la      sp, boot_stack_top
+    mv      tp, a0
     j main
 
+    .globl _start_secondary
+_start_secondary:
+    mv      sp, a1
+    mv      tp, a0
+    j secondary_main
+
    .section .bss.stack
    .globl boot_stack
 boot_stack:
@@ -44,7 +53,8 @@ heap_end:
 #[no_mangle]
 extern "C" fn main(hartid: usize, dtb_pa: usize) {
     clear_bss();
-    init_heap();
+    mm::init_heap();
+    trap::init();
     log!("[{}] Hello, world!, {:p}", hartid, dtb_pa as *const u8);
 
     for i in 0..=1 {
@@ -54,8 +64,39 @@ extern "C" fn main(hartid: usize, dtb_pa: usize) {
     unsafe {
         devices::device_tree::print_tree(dtb_pa);
     }
+    let (memory_start, memory_size) =
+        devices::device_tree::memory_range().expect("no memory in device tree");
+    let initrd = devices::device_tree::initrd_range();
+    mm::init(memory_start + memory_size, initrd.clone());
+    devices::init();
+    mm::init_kernel_space(memory_start + memory_size, &devices::mmio_ranges());
+    fs::init(initrd);
+    task::init();
+    hart::start_secondaries();
+    task::spawn(fs::writeback());
+    process::run_init();
+    if let Err(err) = fs::sync() {
+        log!("Failed to sync filesystems: {}", err);
+    }
+    log!("{}", devices::block_cache::stats());
+    devices::console::flush();
     sbi::shutdown();
 }
+
+/// Where the other harts start once the boot hart has set up the kernel,
+/// each on a stack of its own, and run tasks until shutdown.
+#[no_mangle]
+extern "C" fn secondary_main(hartid: usize) -> ! {
+    mm::activate_kernel_space();
+    trap::init();
+    devices::plic::init_hart();
+    task::init_hart();
+    log!("[{}] online", hartid);
+    loop {
+        task::run_until(|| false);
+    }
+}
+
 fn clear_bss() {
     extern "C" {
         fn sbss();
@@ -66,17 +107,3 @@ fn clear_bss() {
     log!("sbss: {:p}", sbss as *const u8);
     log!("ebss: {:p}", ebss as *const u8);
 }
-
-fn init_heap() {
-    extern "C" {
-        fn heap_start();
-        fn heap_end();
-    }
-    unsafe {
-        log!("heap_start: {:p}", heap_start as *const u8);
-        log!("heap_end: {:p}", heap_end as *const u8);
-        log!("heap_size: 0x{:x}", heap_end as usize - heap_start as usize);
-        HEAP.lock()
-            .init(heap_start as usize, heap_end as usize - heap_start as usize);
-    }
-}
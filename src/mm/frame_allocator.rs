@@ -0,0 +1,109 @@
+use super::{PhysAddr, PhysPageNum};
+use buddy_system_allocator::FrameAllocator;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Physical page frames owned by the holder, freed on drop.
+pub struct FrameTracker {
+    pub ppn: PhysPageNum,
+    /// Number of contiguous frames starting at `ppn`.
+    pub count: usize,
+}
+
+impl FrameTracker {
+    /// Zeroes the frames so no stale data leaks to the new owner.
+    fn new(ppn: PhysPageNum, count: usize) -> Self {
+        for i in 0..count {
+            PhysPageNum(ppn.0 + i).get_bytes_array().fill(0);
+        }
+        Self { ppn, count }
+    }
+
+    pub fn pa(&self) -> PhysAddr {
+        self.ppn.into()
+    }
+}
+
+impl core::fmt::Debug for FrameTracker {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "FrameTracker:{:?}+{}", self.ppn, self.count)
+    }
+}
+
+impl Drop for FrameTracker {
+    fn drop(&mut self) {
+        FRAME_ALLOCATOR.lock().dealloc(self.ppn.0, self.count);
+        ALLOCATED_FRAMES.fetch_sub(self.count, Ordering::Relaxed);
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref FRAME_ALLOCATOR: Mutex<FrameAllocator> = Mutex::new(FrameAllocator::new());
+}
+
+/// Frames handed to the allocator, and those currently allocated.
+static TOTAL_FRAMES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+fn add_frames(allocator: &mut FrameAllocator, start: usize, end: usize) {
+    allocator.add_frame(start, end);
+    TOTAL_FRAMES.fetch_add(end - start, Ordering::Relaxed);
+}
+
+/// Hands the frames between the end of the kernel image and `memory_end`
+/// to the allocator, except those overlapping `reserved`.
+pub fn init_frame_allocator(memory_end: usize, reserved: Option<Range<usize>>) {
+    extern "C" {
+        fn ekernel();
+    }
+    let start = PhysAddr::from(ekernel as usize).ceil();
+    let end = PhysAddr::from(memory_end).floor();
+    log!("frames: [{:?}, {:?})", start, end);
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    match reserved {
+        Some(reserved) => {
+            let (hole_start, hole_end) = frames_of(reserved);
+            log!("frames: reserved [{:?}, {:?})", hole_start, hole_end);
+            if start.0 < hole_start.0 {
+                add_frames(&mut allocator, start.0, hole_start.0.min(end.0));
+            }
+            if hole_end.0 < end.0 {
+                add_frames(&mut allocator, hole_end.0.max(start.0), end.0);
+            }
+        }
+        None => add_frames(&mut allocator, start.0, end.0),
+    }
+}
+
+/// Gives frames held back by [`init_frame_allocator`] to the allocator.
+pub fn release_frames(reserved: Range<usize>) {
+    let (start, end) = frames_of(reserved);
+    add_frames(&mut FRAME_ALLOCATOR.lock(), start.0, end.0);
+    log!("frames: released [{:?}, {:?})", start, end);
+}
+
+/// The frames covering any part of `range`.
+fn frames_of(range: Range<usize>) -> (PhysPageNum, PhysPageNum) {
+    (
+        PhysAddr::from(range.start).floor(),
+        PhysAddr::from(range.end).ceil(),
+    )
+}
+
+pub fn frame_alloc() -> Option<FrameTracker> {
+    frame_alloc_contiguous(1)
+}
+
+/// Allocates `count` physically contiguous frames, e.g. for DMA.
+pub fn frame_alloc_contiguous(count: usize) -> Option<FrameTracker> {
+    let ppn = FRAME_ALLOCATOR.lock().alloc(count)?;
+    ALLOCATED_FRAMES.fetch_add(count, Ordering::Relaxed);
+    Some(FrameTracker::new(PhysPageNum(ppn), count))
+}
+
+/// Frames managed by the allocator and how many of them are free.
+pub fn frame_stats() -> (usize, usize) {
+    let total = TOTAL_FRAMES.load(Ordering::Relaxed);
+    (total, total - ALLOCATED_FRAMES.load(Ordering::Relaxed))
+}
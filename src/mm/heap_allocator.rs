@@ -0,0 +1,37 @@
+use super::{frame_alloc_contiguous, PAGE_SIZE};
+use buddy_system_allocator::LockedHeap;
+
+#[global_allocator]
+static HEAP: LockedHeap<32> = LockedHeap::empty();
+
+/// Sets up the heap in the region reserved for it in `.bss`, which is all
+/// there is until the frame allocator knows the size of memory.
+pub fn init_heap() {
+    extern "C" {
+        fn heap_start();
+        fn heap_end();
+    }
+    unsafe {
+        log!("heap_start: {:p}", heap_start as *const u8);
+        log!("heap_end: {:p}", heap_end as *const u8);
+        log!("heap_size: 0x{:x}", heap_end as usize - heap_start as usize);
+        HEAP.lock()
+            .init(heap_start as usize, heap_end as usize - heap_start as usize);
+    }
+}
+
+/// Moves `count` contiguous frames from the frame allocator to the heap for
+/// good.
+pub fn grow_heap(count: usize) {
+    let frames = frame_alloc_contiguous(count).expect("no memory to grow the heap");
+    let start = frames.pa().0;
+    core::mem::forget(frames);
+    unsafe { HEAP.lock().add_to_heap(start, start + count * PAGE_SIZE) };
+    log!("heap: added {:#x} bytes at {:#x}", count * PAGE_SIZE, start);
+}
+
+/// Bytes given to the heap and bytes currently allocated from it.
+pub fn heap_stats() -> (usize, usize) {
+    let heap = HEAP.lock();
+    (heap.stats_total_bytes(), heap.stats_alloc_actual())
+}
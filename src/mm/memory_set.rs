@@ -0,0 +1,736 @@
+use super::{
+    frame_alloc, Backing, FrameTracker, PTEFlags, PageTable, PageTableEntry, PhysAddr, PhysPageNum,
+    VirtAddr, VirtPageNum, PAGE_SIZE, STACK_GUARD_GAP, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_MAX,
+};
+use crate::{hart, sbi};
+use ::alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use bitflags::bitflags;
+use core::arch::asm;
+use core::ops::Range;
+use once_cell::race::OnceBox;
+
+bitflags! {
+    /// Access allowed to a mapped area, with the bit positions of the
+    /// matching page table entry flags.
+    pub struct MapPermission: u8 {
+        const R = 1 << 1;
+        const W = 1 << 2;
+        const X = 1 << 3;
+        const U = 1 << 4;
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MapType {
+    /// Virtual addresses equal physical ones.
+    Identical,
+    /// Every page gets a frame, owned by the area and shared copy-on-write
+    /// with the address spaces forked from it.
+    Framed,
+    /// Like `Framed`, but a page gets its frame, zeroed, when first
+    /// accessed.
+    Lazy,
+}
+
+/// A contiguous range of pages mapped the same way.
+pub struct MapArea {
+    start: VirtPageNum,
+    end: VirtPageNum,
+    /// Frames of the mapped pages, shared between forked address spaces
+    /// until one of them writes to the page.
+    frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
+    map_type: MapType,
+    perm: MapPermission,
+    /// What a lazy area maps instead of zeroed pages.
+    backing: Option<Backing>,
+}
+
+impl MapArea {
+    /// The pages covering any part of `[start, end)`.
+    pub fn new(start: VirtAddr, end: VirtAddr, map_type: MapType, perm: MapPermission) -> Self {
+        Self {
+            start: start.floor(),
+            end: end.ceil(),
+            frames: BTreeMap::new(),
+            map_type,
+            perm,
+            backing: None,
+        }
+    }
+
+    /// An area over the same pages as `other`, mapped the same way, but
+    /// without frames yet.
+    fn from_another(other: &MapArea) -> Self {
+        Self {
+            start: other.start,
+            end: other.end,
+            frames: BTreeMap::new(),
+            map_type: other.map_type,
+            perm: other.perm,
+            backing: other.backing.clone(),
+        }
+    }
+
+    fn pages(&self) -> impl Iterator<Item = VirtPageNum> {
+        (self.start.0..self.end.0).map(VirtPageNum)
+    }
+
+    fn pte_flags(&self) -> PTEFlags {
+        PTEFlags::from_bits(self.perm.bits).unwrap()
+    }
+
+    fn is_shared(&self) -> bool {
+        self.backing.as_ref().is_some_and(|backing| backing.shared)
+    }
+
+    /// Maps `vpn`, with a new frame unless the area is identity mapped.
+    /// `None` if there is no memory for the frame.
+    fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Option<()> {
+        let ppn = match self.map_type {
+            MapType::Identical => PhysPageNum(vpn.0),
+            MapType::Framed | MapType::Lazy => {
+                let frame = frame_alloc()?;
+                let ppn = frame.ppn;
+                self.frames.insert(vpn, Arc::new(frame));
+                ppn
+            }
+        };
+        page_table.map(vpn, ppn, self.pte_flags());
+        Some(())
+    }
+
+    /// Drops the frame of `vpn` and unmaps it, if it is mapped: a lazy page
+    /// may never have been, and an inaccessible one is not.
+    fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        self.frames.remove(&vpn);
+        if page_table.translate(vpn).is_some() {
+            page_table.unmap(vpn);
+            flush_tlb(vpn);
+        }
+    }
+
+    /// Maps every page of the area, except in lazy ones.
+    fn map(&mut self, page_table: &mut PageTable) {
+        if self.map_type == MapType::Lazy {
+            return;
+        }
+        for vpn in self.pages() {
+            self.map_one(page_table, vpn)
+                .expect("no memory to map a page");
+        }
+    }
+
+    /// Resolves a fault on an `access` to `vpn` that the area allows. A
+    /// page of a lazy area without a frame gets one, from the backing
+    /// object or zeroed. A write to a private frame others share gives the
+    /// area a copy of it, and the last sharer takes the frame as it is.
+    ///
+    /// Pages shared with others stay read-only until written: private ones
+    /// to be copied then, and those of a shared object to tell it of the
+    /// write. `None` if there is no memory for a frame.
+    fn handle_fault(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        access: MapPermission,
+    ) -> Option<()> {
+        let index = vpn.0 - self.start.0;
+        if !self.frames.contains_key(&vpn) {
+            if self.map_type != MapType::Lazy {
+                return None;
+            }
+            let frame = match &self.backing {
+                Some(backing) => backing.pages.page(backing.offset + index)?,
+                None => Arc::new(frame_alloc()?),
+            };
+            self.frames.insert(vpn, frame);
+        }
+        let write = access.contains(MapPermission::W);
+        let mut flags = self.pte_flags();
+        if let Some(backing) = self.backing.as_ref().filter(|backing| backing.shared) {
+            if write {
+                backing.pages.mark_dirty(backing.offset + index);
+            } else {
+                flags -= PTEFlags::W;
+            }
+        }
+        let shared = self.is_shared();
+        let frame = self.frames.get_mut(&vpn).unwrap();
+        if !shared && Arc::strong_count(frame) > 1 {
+            if write {
+                let copy = frame_alloc()?;
+                copy.ppn
+                    .get_bytes_array()
+                    .copy_from_slice(frame.ppn.get_bytes_array());
+                *frame = Arc::new(copy);
+            } else {
+                flags -= PTEFlags::W;
+            }
+        }
+        if page_table.translate(vpn).is_some() {
+            page_table.remap(vpn, frame.ppn, flags);
+        } else {
+            page_table.map(vpn, frame.ppn, flags);
+        }
+        Some(())
+    }
+
+    /// Whether the area has pages, all in `[start, end)`. An empty heap
+    /// lies in no range.
+    fn lies_in(&self, start: VirtPageNum, end: VirtPageNum) -> bool {
+        start <= self.start && self.end <= end && self.start < self.end
+    }
+
+    /// Cuts the area at page `at`, inside it, keeping the pages below and
+    /// returning an area of those from `at` on.
+    fn split_off(&mut self, at: VirtPageNum) -> Self {
+        let mut backing = self.backing.clone();
+        if let Some(backing) = &mut backing {
+            backing.offset += at.0 - self.start.0;
+        }
+        let upper = Self {
+            start: at,
+            end: self.end,
+            frames: self.frames.split_off(&at),
+            map_type: self.map_type,
+            perm: self.perm,
+            backing,
+        };
+        self.end = at;
+        upper
+    }
+
+    /// Copies `data` to `offset` bytes into the area, which must be framed
+    /// and mapped in `page_table`.
+    fn copy_data(&self, page_table: &PageTable, offset: usize, data: &[u8]) {
+        assert!(data.is_empty() || self.map_type == MapType::Framed);
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done;
+            let vpn = VirtPageNum(self.start.0 + pos / PAGE_SIZE);
+            let in_page = pos % PAGE_SIZE;
+            let n = (PAGE_SIZE - in_page).min(data.len() - done);
+            let ppn = page_table.translate(vpn).unwrap().ppn();
+            ppn.get_bytes_array()[in_page..in_page + n].copy_from_slice(&data[done..done + n]);
+            done += n;
+        }
+    }
+}
+
+/// An address space: a page table and the areas mapped in it.
+pub struct MemorySet {
+    page_table: PageTable,
+    areas: Vec<MapArea>,
+    /// End of the stack area, which grows down on faults below it.
+    stack_top: Option<VirtPageNum>,
+    /// Harts that have run the address space, one bit per hart ID, which
+    /// may cache its translations.
+    harts: usize,
+}
+
+impl MemorySet {
+    pub fn new_bare() -> Self {
+        Self {
+            page_table: PageTable::new(),
+            areas: Vec::new(),
+            stack_top: None,
+            harts: 0,
+        }
+    }
+
+    /// Value for `satp` that switches to this address space.
+    pub fn token(&self) -> usize {
+        self.page_table.token()
+    }
+
+    /// Value for `satp` that runs this address space on this hart, which
+    /// from then on takes part in flushing translations that lose access.
+    pub fn enter(&mut self) -> usize {
+        self.harts |= 1 << hart::id();
+        self.token()
+    }
+
+    /// Has the other harts that ran the address space drop their
+    /// translations of `[start, end)`, which lost access. This hart flushes
+    /// its own as it changes them.
+    fn flush_remote(&self, start: VirtPageNum, end: VirtPageNum) {
+        let others = self.harts & !(1 << hart::id());
+        if others != 0 && start < end {
+            let va = VirtAddr::from(start).0;
+            sbi::remote_sfence_vma(others, 0, va, (end.0 - start.0) * PAGE_SIZE);
+        }
+    }
+
+    /// Maps `area`, then copies `data` to its start if given.
+    pub fn push(&mut self, area: MapArea, data: Option<&[u8]>) {
+        self.push_at(area, 0, data.unwrap_or_default());
+    }
+
+    /// Maps `area`, then copies `data` to `offset` bytes into it.
+    pub fn push_at(&mut self, mut area: MapArea, offset: usize, data: &[u8]) {
+        area.map(&mut self.page_table);
+        area.copy_data(&self.page_table, offset, data);
+        self.areas.push(area);
+    }
+
+    pub fn insert_framed_area(&mut self, start: VirtAddr, end: VirtAddr, perm: MapPermission) {
+        self.push(MapArea::new(start, end, MapType::Framed, perm), None);
+    }
+
+    /// Moves the end of the area that starts at page `start` to cover up to
+    /// `end`, mapping or unmapping pages at its end. Returns whether there
+    /// is such an area, and it does not run into another.
+    pub fn set_area_end(&mut self, start: VirtPageNum, end: VirtAddr) -> bool {
+        let Some(index) = self.areas.iter().position(|area| area.start == start) else {
+            return false;
+        };
+        let end = end.ceil().max(start);
+        let old_end = self.areas[index].end;
+        if end > old_end && self.mapped_pages(old_end, end) != 0 {
+            return false;
+        }
+        let area = &mut self.areas[index];
+        for vpn in (end.0..area.end.0).map(VirtPageNum) {
+            area.unmap_one(&mut self.page_table, vpn);
+        }
+        if area.map_type != MapType::Lazy {
+            for vpn in (area.end.0..end.0).map(VirtPageNum) {
+                area.map_one(&mut self.page_table, vpn)
+                    .expect("no memory to map a page");
+            }
+        }
+        area.end = end;
+        self.flush_remote(end, old_end);
+        true
+    }
+
+    /// Maps a user stack of `size` bytes below `top`, allocated as it is
+    /// touched. Faults below it grow it down to [`USER_STACK_MAX`].
+    pub fn insert_stack(&mut self, top: usize, size: usize) {
+        self.push(
+            MapArea::new(
+                (top - size).into(),
+                top.into(),
+                MapType::Lazy,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            ),
+            None,
+        );
+        self.stack_top = Some(VirtAddr::from(top).ceil());
+    }
+
+    fn area_index(&self, vpn: VirtPageNum) -> Option<usize> {
+        self.areas
+            .iter()
+            .position(|area| area.start <= vpn && vpn < area.end)
+    }
+
+    /// Extends the stack down to `vpn` and returns its index, if that keeps
+    /// it within [`USER_STACK_MAX`] and [`STACK_GUARD_GAP`] above whatever
+    /// is mapped below it.
+    fn grow_stack(&mut self, vpn: VirtPageNum) -> Option<usize> {
+        let top = self.stack_top?;
+        let index = self.areas.iter().position(|area| area.end == top)?;
+        let start = self.areas[index].start;
+        if vpn >= start || vpn.0 < top.0 - USER_STACK_MAX / PAGE_SIZE {
+            return None;
+        }
+        let below = self
+            .areas
+            .iter()
+            .filter(|area| area.end <= start)
+            .map(|area| area.end.0)
+            .max()
+            .unwrap_or(0);
+        if vpn.0 < below + STACK_GUARD_GAP / PAGE_SIZE {
+            return None;
+        }
+        // The stack is lazy, so the new pages are mapped as they fault.
+        self.areas[index].start = vpn;
+        Some(index)
+    }
+
+    /// Resolves a fault on a U-mode `access` at `va`: maps a lazy page,
+    /// copies a shared page written to, or grows the stack. Returns false
+    /// if the access is not allowed, or memory runs out.
+    pub fn handle_page_fault(&mut self, va: VirtAddr, access: MapPermission) -> bool {
+        let vpn = va.floor();
+        let Some(index) = self.area_index(vpn).or_else(|| self.grow_stack(vpn)) else {
+            return false;
+        };
+        let area = &mut self.areas[index];
+        if !area.perm.contains(access | MapPermission::U) {
+            return false;
+        }
+        let access_flags = PTEFlags::from_bits(access.bits).unwrap();
+        if let Some(pte) = self.page_table.translate(vpn) {
+            if pte.flags().contains(access_flags) {
+                // Mapped already, and the TLB is flushed on the way back.
+                return true;
+            }
+        }
+        area.handle_fault(&mut self.page_table, vpn, access)
+            .is_some()
+    }
+
+    /// Resolves the faults U-mode would take reading, or writing if `write`
+    /// is set, `len` bytes at `ptr`, so that the kernel can access them
+    /// through the page table. Returns false if any access would fail.
+    pub fn fault_in(&mut self, ptr: usize, len: usize, write: bool) -> bool {
+        let Some(end) = ptr.checked_add(len) else {
+            return false;
+        };
+        if len == 0 {
+            return true;
+        }
+        let access = if write {
+            MapPermission::W
+        } else {
+            MapPermission::R
+        };
+        let start = VirtAddr::from(ptr).floor();
+        let end = VirtAddr::from(end).ceil();
+        for vpn in (start.0..end.0).map(VirtPageNum) {
+            let ready = self
+                .page_table
+                .translate(vpn)
+                .is_some_and(|pte| pte.user() && pte.readable() && (!write || pte.writable()));
+            if !ready && !self.handle_page_fault(vpn.into(), access) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The kernel's view of `len` bytes of user memory at `ptr`, one slice
+    /// per page, after resolving the faults U-mode would take on them.
+    pub fn translated_byte_buffer(
+        &mut self,
+        ptr: usize,
+        len: usize,
+        write: bool,
+    ) -> Option<Vec<&mut [u8]>> {
+        if !self.fault_in(ptr, len, write) {
+            return None;
+        }
+        self.page_table.translated_byte_buffer(ptr, len, write)
+    }
+
+    /// Pages of `[start, end)` that areas cover.
+    fn mapped_pages(&self, start: VirtPageNum, end: VirtPageNum) -> usize {
+        self.areas
+            .iter()
+            .map(|area| area.end.min(end).0.saturating_sub(area.start.max(start).0))
+            .sum()
+    }
+
+    /// Whether no area covers any page of `[start, end)`.
+    pub fn is_free(&self, start: VirtPageNum, end: VirtPageNum) -> bool {
+        self.mapped_pages(start, end) == 0
+    }
+
+    /// The highest start of `pages` free pages between `lowest` and
+    /// `highest`, if there is room.
+    pub fn find_free(
+        &self,
+        pages: usize,
+        lowest: VirtPageNum,
+        highest: VirtPageNum,
+    ) -> Option<VirtPageNum> {
+        let mut ranges: Vec<(usize, usize)> = self
+            .areas
+            .iter()
+            .map(|area| (area.start.0, area.end.0))
+            .collect();
+        ranges.sort_unstable_by(|a, b| b.cmp(a));
+        // Walk down from `highest`, trying the gap above each area.
+        let mut end = highest.0;
+        for (area_start, area_end) in ranges {
+            if area_start >= end {
+                continue;
+            }
+            let bottom = area_end.max(lowest.0);
+            if end >= bottom + pages {
+                return Some(VirtPageNum(end - pages));
+            }
+            end = area_start;
+        }
+        (end >= lowest.0 + pages).then(|| VirtPageNum(end - pages))
+    }
+
+    /// Maps `[start, end)` with `perm`, to the pages of `backing` if given
+    /// and to zeroed ones otherwise, each as it is first accessed. The
+    /// range must be free.
+    pub fn insert_lazy_area(
+        &mut self,
+        start: VirtPageNum,
+        end: VirtPageNum,
+        perm: MapPermission,
+        backing: Option<Backing>,
+    ) {
+        let mut area = MapArea::new(start.into(), end.into(), MapType::Lazy, perm);
+        area.backing = backing;
+        self.push(area, None);
+    }
+
+    /// Cuts the area holding page `at`, if it starts below it, in two.
+    fn split_at(&mut self, at: VirtPageNum) {
+        if let Some(index) = self.area_index(at) {
+            if self.areas[index].start < at {
+                let upper = self.areas[index].split_off(at);
+                self.areas.push(upper);
+            }
+        }
+    }
+
+    /// Unmaps the pages of `[start, end)`, splitting areas that lie partly
+    /// inside it.
+    pub fn unmap(&mut self, start: VirtPageNum, end: VirtPageNum) {
+        self.split_at(start);
+        self.split_at(end);
+        let (removed, kept): (Vec<MapArea>, Vec<MapArea>) = core::mem::take(&mut self.areas)
+            .into_iter()
+            .partition(|area| area.lies_in(start, end));
+        self.areas = kept;
+        for mut area in removed {
+            for vpn in area.pages() {
+                area.unmap_one(&mut self.page_table, vpn);
+            }
+        }
+        self.flush_remote(start, end);
+    }
+
+    /// Whether the areas in `[start, end)` may be made writable: those
+    /// mapping an object shared only if it was opened for writing.
+    pub fn can_write(&self, start: VirtPageNum, end: VirtPageNum) -> bool {
+        self.areas
+            .iter()
+            .filter(|area| area.start < end && start < area.end)
+            .filter_map(|area| area.backing.as_ref())
+            .all(|backing| !backing.shared || backing.writable)
+    }
+
+    /// Changes the permissions of `[start, end)` to `perm`, splitting areas
+    /// that lie partly inside it. Returns false, changing nothing, unless
+    /// areas cover the whole range.
+    ///
+    /// Mapped pages lose write access, which a write fault gives back if
+    /// `perm` allows it, and without any access they are unmapped.
+    pub fn protect(&mut self, start: VirtPageNum, end: VirtPageNum, perm: MapPermission) -> bool {
+        if self.mapped_pages(start, end) != end.0 - start.0 {
+            return false;
+        }
+        self.split_at(start);
+        self.split_at(end);
+        let accessible = perm.intersects(MapPermission::R | MapPermission::W | MapPermission::X);
+        let flags = PTEFlags::from_bits(perm.bits).unwrap() - PTEFlags::W;
+        for area in &mut self.areas {
+            if !area.lies_in(start, end) {
+                continue;
+            }
+            area.perm = perm;
+            for vpn in area.pages() {
+                let Some(pte) = self.page_table.translate(vpn) else {
+                    continue;
+                };
+                if accessible {
+                    self.page_table.remap(vpn, pte.ppn(), flags);
+                } else {
+                    self.page_table.unmap(vpn);
+                }
+                flush_tlb(vpn);
+            }
+        }
+        self.flush_remote(start, end);
+        true
+    }
+
+    /// Maps the trap entry and exit code at the top of the address space.
+    /// The page is not tracked in an area: it belongs to the kernel image.
+    fn map_trampoline(&mut self) {
+        extern "C" {
+            fn strampoline();
+        }
+        self.page_table.map(
+            VirtAddr::from(TRAMPOLINE).into(),
+            PhysAddr::from(strampoline as usize).into(),
+            PTEFlags::R | PTEFlags::X,
+        );
+    }
+
+    /// The kernel's address space: the kernel image with the permissions of
+    /// each section, the rest of memory up to `memory_end`, and the `mmio`
+    /// windows of devices, all identity mapped.
+    pub fn new_kernel(memory_end: usize, mmio: &[Range<usize>]) -> Self {
+        extern "C" {
+            fn stext();
+            fn etext();
+            fn srodata();
+            fn erodata();
+            fn sdata();
+        }
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        let identical = [
+            (
+                stext as usize,
+                etext as usize,
+                MapPermission::R | MapPermission::X,
+            ),
+            (srodata as usize, erodata as usize, MapPermission::R),
+            // .data, .bss, and after the image the memory the frame
+            // allocator hands out.
+            (
+                sdata as usize,
+                memory_end,
+                MapPermission::R | MapPermission::W,
+            ),
+        ];
+        for (start, end, perm) in identical {
+            memory_set.push(
+                MapArea::new(start.into(), end.into(), MapType::Identical, perm),
+                None,
+            );
+        }
+        // Windows of different devices may share a page.
+        let mut pages: Vec<Range<usize>> = mmio
+            .iter()
+            .map(|range| {
+                let start = VirtAddr::from(range.start).floor().0;
+                start..VirtAddr::from(range.end).ceil().0
+            })
+            .collect();
+        pages.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = Vec::new();
+        for range in pages {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        for range in merged {
+            memory_set.push(
+                MapArea::new(
+                    VirtPageNum(range.start).into(),
+                    VirtPageNum(range.end).into(),
+                    MapType::Identical,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            );
+        }
+        memory_set
+    }
+
+    /// An address space for a user process, holding only the trampoline
+    /// and the page for the process's trap context.
+    pub fn new_user() -> Self {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        memory_set.insert_framed_area(
+            TRAP_CONTEXT.into(),
+            TRAMPOLINE.into(),
+            MapPermission::R | MapPermission::W,
+        );
+        memory_set
+    }
+
+    /// A copy of this user address space that shares its frames until
+    /// either side writes to them: writable pages become read-only in both,
+    /// and a write fault gives the writer a copy. Pages of shared mappings
+    /// stay shared, and pages only the kernel accesses, like the trap
+    /// context, are copied right away.
+    ///
+    /// The caller's page table changes, so this hart must flush its TLB
+    /// before returning to it, as entering U-mode does. Other harts are
+    /// told to flush theirs.
+    pub fn fork(&mut self) -> Self {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        memory_set.stack_top = self.stack_top;
+        for area in &self.areas {
+            let mut new_area = MapArea::from_another(area);
+            if area.map_type == MapType::Identical || !area.perm.contains(MapPermission::U) {
+                new_area.map(&mut memory_set.page_table);
+                for (vpn, frame) in &area.frames {
+                    let copy = memory_set.translate(*vpn).unwrap().ppn();
+                    copy.get_bytes_array()
+                        .copy_from_slice(frame.ppn.get_bytes_array());
+                }
+            } else {
+                let shared = area.is_shared();
+                for (&vpn, frame) in &area.frames {
+                    new_area.frames.insert(vpn, frame.clone());
+                    // Inaccessible pages keep their frames unmapped.
+                    let Some(pte) = self.page_table.translate(vpn) else {
+                        continue;
+                    };
+                    let mut flags = pte.flags();
+                    if !shared {
+                        flags -= PTEFlags::W;
+                        self.page_table.remap(vpn, frame.ppn, flags);
+                    }
+                    memory_set.page_table.map(vpn, frame.ppn, flags);
+                }
+                if !shared {
+                    self.flush_remote(area.start, area.end);
+                }
+            }
+            memory_set.areas.push(new_area);
+        }
+        memory_set
+    }
+
+    /// Frees the frames of all areas. The page table stays, with entries
+    /// pointing at freed frames, so the address space must not be used
+    /// again.
+    pub fn recycle_data_pages(&mut self) {
+        self.areas.clear();
+    }
+
+    /// Bytes mapped for U-mode.
+    pub fn user_size(&self) -> usize {
+        self.areas
+            .iter()
+            .filter(|area| area.perm.contains(MapPermission::U))
+            .map(|area| (area.end.0 - area.start.0) * PAGE_SIZE)
+            .sum()
+    }
+
+    pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
+        self.page_table.translate(vpn)
+    }
+
+    /// Switches this hart to the address space.
+    pub fn activate(&self) {
+        let satp = self.token();
+        unsafe {
+            asm!("csrw satp, {}", "sfence.vma", in(reg) satp);
+        }
+    }
+}
+
+/// Drops any translation of `vpn` this hart has cached.
+fn flush_tlb(vpn: VirtPageNum) {
+    let va = VirtAddr::from(vpn).0;
+    unsafe {
+        asm!("sfence.vma {}, zero", in(reg) va);
+    }
+}
+
+static KERNEL_SPACE: OnceBox<MemorySet> = OnceBox::new();
+
+/// Builds the kernel's address space and turns on paging with it.
+pub fn init_kernel_space(memory_end: usize, mmio: &[Range<usize>]) {
+    let space = KERNEL_SPACE.get_or_init(|| Box::new(MemorySet::new_kernel(memory_end, mmio)));
+    space.activate();
+    log!("mm: paging enabled, satp = {:#x}", space.token());
+}
+
+/// Switches this hart to the kernel's address space, once it is built.
+pub fn activate_kernel_space() {
+    KERNEL_SPACE
+        .get()
+        .expect("kernel space not built")
+        .activate();
+}
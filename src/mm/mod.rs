@@ -0,0 +1,66 @@
+mod address;
+mod frame_allocator;
+mod heap_allocator;
+mod memory_set;
+mod page_table;
+mod shared;
+
+pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+pub use frame_allocator::{frame_alloc, frame_alloc_contiguous, release_frames, FrameTracker};
+pub use heap_allocator::init_heap;
+pub use memory_set::{
+    activate_kernel_space, init_kernel_space, MapArea, MapPermission, MapType, MemorySet,
+};
+pub use page_table::{PTEFlags, PageTable, PageTableEntry};
+pub use shared::{AnonPages, Backing, SharedPages};
+
+use core::ops::Range;
+
+pub const PAGE_SIZE: usize = 0x1000;
+
+/// The trap entry and exit code, mapped at the same address in every
+/// address space so it keeps running across the switch of `satp`.
+pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
+/// A process's saved registers, in the page below the trampoline.
+pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
+/// User stacks grow down from the top of the lower half of the Sv39
+/// address space.
+pub const USER_STACK_TOP: usize = 1 << 38;
+/// Initial size of a user stack, which grows down on faults up to
+/// [`USER_STACK_MAX`].
+pub const USER_STACK_SIZE: usize = 8 * PAGE_SIZE;
+pub const USER_STACK_MAX: usize = 8 * 1024 * 1024;
+/// Unmapped space a growing stack keeps above any mapping below it, as
+/// Linux does.
+pub const STACK_GUARD_GAP: usize = 256 * PAGE_SIZE;
+
+/// Frames given to the heap once memory is known: 4 MiB.
+const HEAP_GROWTH_FRAMES: usize = 1024;
+
+/// Sets up the frame allocator, keeping `reserved` out of it until it is
+/// given back with [`release_frames`].
+pub fn init(memory_end: usize, reserved: Option<Range<usize>>) {
+    frame_allocator::init_frame_allocator(memory_end, reserved);
+    heap_allocator::grow_heap(HEAP_GROWTH_FRAMES);
+}
+
+/// Memory usage, in bytes.
+pub struct MemoryStats {
+    /// Memory managed by the frame allocator.
+    pub total: usize,
+    pub free: usize,
+    /// Memory given to the kernel heap, mostly frames allocated from `total`.
+    pub heap_total: usize,
+    pub heap_used: usize,
+}
+
+pub fn stats() -> MemoryStats {
+    let (total_frames, free_frames) = frame_allocator::frame_stats();
+    let (heap_total, heap_used) = heap_allocator::heap_stats();
+    MemoryStats {
+        total: total_frames * PAGE_SIZE,
+        free: free_frames * PAGE_SIZE,
+        heap_total,
+        heap_used,
+    }
+}
@@ -0,0 +1,176 @@
+use super::{frame_alloc, FrameTracker, PhysPageNum, VirtAddr, VirtPageNum, PAGE_SIZE};
+use ::alloc::{vec, vec::Vec};
+use bitflags::bitflags;
+
+bitflags! {
+    pub struct PTEFlags: u8 {
+        const V = 1 << 0;
+        const R = 1 << 1;
+        const W = 1 << 2;
+        const X = 1 << 3;
+        const U = 1 << 4;
+        const G = 1 << 5;
+        const A = 1 << 6;
+        const D = 1 << 7;
+    }
+}
+
+/// An Sv39 page table entry.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct PageTableEntry {
+    pub bits: usize,
+}
+
+impl PageTableEntry {
+    pub fn new(ppn: PhysPageNum, flags: PTEFlags) -> Self {
+        PageTableEntry {
+            bits: ppn.0 << 10 | flags.bits as usize,
+        }
+    }
+
+    pub fn empty() -> Self {
+        PageTableEntry { bits: 0 }
+    }
+
+    pub fn ppn(&self) -> PhysPageNum {
+        (self.bits >> 10 & ((1usize << 44) - 1)).into()
+    }
+
+    pub fn flags(&self) -> PTEFlags {
+        PTEFlags::from_bits_truncate(self.bits as u8)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.flags().contains(PTEFlags::V)
+    }
+
+    pub fn readable(&self) -> bool {
+        self.flags().contains(PTEFlags::R)
+    }
+
+    pub fn writable(&self) -> bool {
+        self.flags().contains(PTEFlags::W)
+    }
+
+    pub fn executable(&self) -> bool {
+        self.flags().contains(PTEFlags::X)
+    }
+
+    pub fn user(&self) -> bool {
+        self.flags().contains(PTEFlags::U)
+    }
+}
+
+/// A three-level Sv39 page table, owning the frames of its directories.
+pub struct PageTable {
+    root_ppn: PhysPageNum,
+    frames: Vec<FrameTracker>,
+}
+
+impl PageTable {
+    pub fn new() -> Self {
+        let frame = frame_alloc().expect("no memory for a page table");
+        PageTable {
+            root_ppn: frame.ppn,
+            frames: vec![frame],
+        }
+    }
+
+    fn find_pte_create(&mut self, vpn: VirtPageNum) -> &mut PageTableEntry {
+        let idxs = vpn.indexes();
+        let mut ppn = self.root_ppn;
+        for (level, &idx) in idxs.iter().enumerate() {
+            let pte = &mut ppn.get_pte_array()[idx];
+            if level == 2 {
+                return pte;
+            }
+            if !pte.is_valid() {
+                let frame = frame_alloc().expect("no memory for a page table");
+                *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+                self.frames.push(frame);
+            }
+            ppn = pte.ppn();
+        }
+        unreachable!()
+    }
+
+    fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        let idxs = vpn.indexes();
+        let mut ppn = self.root_ppn;
+        for (level, &idx) in idxs.iter().enumerate() {
+            let pte = &mut ppn.get_pte_array()[idx];
+            if level == 2 {
+                return Some(pte);
+            }
+            if !pte.is_valid() {
+                return None;
+            }
+            ppn = pte.ppn();
+        }
+        None
+    }
+
+    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pte = self.find_pte_create(vpn);
+        assert!(!pte.is_valid(), "{:?} is mapped before mapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    }
+
+    /// Points the mapped page `vpn` at `ppn` with `flags` instead.
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pte = self
+            .find_pte(vpn)
+            .expect("remapping a page without a table");
+        assert!(pte.is_valid(), "{:?} is invalid before remapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    }
+
+    pub fn unmap(&mut self, vpn: VirtPageNum) {
+        let pte = self
+            .find_pte(vpn)
+            .expect("unmapping a page without a table");
+        assert!(pte.is_valid(), "{:?} is invalid before unmapping", vpn);
+        *pte = PageTableEntry::empty();
+    }
+
+    /// The leaf entry for `vpn`, if it is mapped.
+    pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
+        self.find_pte(vpn)
+            .map(|pte| *pte)
+            .filter(|pte| pte.is_valid())
+    }
+
+    /// Value for `satp` that selects this table in Sv39 mode.
+    pub fn token(&self) -> usize {
+        8usize << 60 | self.root_ppn.0
+    }
+
+    /// The kernel's view of `len` bytes of user memory at `ptr`, one slice
+    /// per page. `None` if any page is unmapped, not accessible to U-mode,
+    /// or not writable when `write` is set. The slices borrow the table, so
+    /// the mapping cannot change while they are in use.
+    pub fn translated_byte_buffer(
+        &mut self,
+        ptr: usize,
+        len: usize,
+        write: bool,
+    ) -> Option<Vec<&mut [u8]>> {
+        let end = ptr.checked_add(len)?;
+        let mut start = ptr;
+        let mut buffers = Vec::new();
+        while start < end {
+            let start_va = VirtAddr::from(start);
+            let pte = self.translate(start_va.floor())?;
+            if !pte.user() || !pte.readable() || (write && !pte.writable()) {
+                return None;
+            }
+            let page_end = (start - start_va.page_offset() + PAGE_SIZE).min(end);
+            let bytes = pte.ppn().get_bytes_array();
+            let offset = start_va.page_offset();
+            buffers.push(&mut bytes[offset..offset + page_end - start]);
+            start = page_end;
+        }
+        Some(buffers)
+    }
+}
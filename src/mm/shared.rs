@@ -0,0 +1,56 @@
+//! Objects whose pages several areas map at once: files, and anonymous
+//! memory mapped shared.
+
+use super::{frame_alloc, FrameTracker};
+use ::alloc::{collections::BTreeMap, sync::Arc};
+use spin::Mutex;
+
+/// Pages of one object, the same frames for every area that maps it.
+pub trait SharedPages: Send + Sync {
+    /// The frame holding page `index` of the object, allocated or read in
+    /// on first use. `None` if that fails.
+    fn page(&self, index: usize) -> Option<Arc<FrameTracker>>;
+
+    /// Notes that page `index` is written through a mapping.
+    fn mark_dirty(&self, _index: usize) {}
+}
+
+/// The object an area maps, and where in it.
+#[derive(Clone)]
+pub struct Backing {
+    pub pages: Arc<dyn SharedPages>,
+    /// Page of the object at the start of the area.
+    pub offset: usize,
+    /// Whether writes go to the object, rather than to private copies of
+    /// its pages.
+    pub shared: bool,
+    /// Whether the area may be made writable if `shared`: not for a file
+    /// opened read-only.
+    pub writable: bool,
+}
+
+/// Anonymous memory mapped shared: zeroed pages that live as long as any
+/// area maps them.
+pub struct AnonPages {
+    pages: Mutex<BTreeMap<usize, Arc<FrameTracker>>>,
+}
+
+impl AnonPages {
+    pub fn new() -> Self {
+        AnonPages {
+            pages: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl SharedPages for AnonPages {
+    fn page(&self, index: usize) -> Option<Arc<FrameTracker>> {
+        let mut pages = self.pages.lock();
+        if let Some(frame) = pages.get(&index) {
+            return Some(frame.clone());
+        }
+        let frame = Arc::new(frame_alloc()?);
+        pages.insert(index, frame.clone());
+        Some(frame)
+    }
+}
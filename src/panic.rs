@@ -1,3 +1,4 @@
+use crate::devices::console;
 use crate::sbi::shutdown;
 use core::panic::PanicInfo;
 
@@ -8,10 +9,11 @@ fn panic(info: &PanicInfo) -> ! {
             "Panicked at {}:{} {}",
             location.file(),
             location.line(),
-            info.message().unwrap()
+            info.message()
         );
     } else {
-        println!("Panicked: {}", info.message().unwrap());
+        println!("Panicked: {}", info.message());
     }
+    console::flush();
     shutdown()
 }
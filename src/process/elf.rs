@@ -0,0 +1,356 @@
+//! Loading ELF64 executables into a new address space.
+//!
+//! Statically linked executables and position-independent ones are
+//! supported. Segments are copied rather than mapped from the file, and the
+//! frames behind them start out zeroed, which takes care of `.bss`.
+
+use super::{map_user_stack, USER_MEMORY_END};
+use crate::fs::devfs;
+use crate::mm::{
+    MapArea, MapPermission, MapType, MemorySet, VirtAddr, PAGE_SIZE, USER_STACK_SIZE,
+    USER_STACK_TOP,
+};
+use ::alloc::vec::Vec;
+use core::fmt;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_RISCV: u16 = 243;
+/// Float ABI bits of `e_flags`: zero for the soft-float ABI.
+const EF_RISCV_FLOAT_ABI: u32 = 0x6;
+
+const PHDR_SIZE: usize = 56;
+
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+const PT_PHDR: u32 = 6;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+// Auxiliary vector keys.
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_ENTRY: usize = 9;
+const AT_UID: usize = 11;
+const AT_EUID: usize = 12;
+const AT_GID: usize = 13;
+const AT_EGID: usize = 14;
+const AT_SECURE: usize = 23;
+const AT_RANDOM: usize = 25;
+
+/// Where position-independent executables are loaded.
+const PIE_BASE: usize = 0x1000_0000;
+
+/// Room for the arguments, environment and auxiliary vector on the stack.
+/// Linux allows them a quarter of the stack, too.
+pub const ARG_MAX: usize = USER_STACK_SIZE / 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The file ends inside a structure it describes.
+    Truncated,
+    /// The file does not start with the ELF magic.
+    NotElf,
+    /// Not a little-endian ELF64 file of the current version.
+    UnsupportedFormat,
+    /// Built for another architecture, with this `e_machine`.
+    WrongMachine(u16),
+    /// Neither an executable nor position-independent, with this `e_type`.
+    NotExecutable(u16),
+    /// Uses the hardware float ABI, and user processes run without the FPU.
+    FloatAbi,
+    /// Needs a dynamic linker.
+    Dynamic,
+    /// The program header with this index is inconsistent, overlaps
+    /// another segment, or lies outside user memory.
+    BadSegment(usize),
+    /// There is nothing to load.
+    NoSegments,
+    /// The entry point is not in an executable segment.
+    BadEntry,
+    /// The arguments and environment do not fit on the stack.
+    ArgumentsTooLong,
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::Truncated => write!(f, "truncated ELF file"),
+            ElfError::NotElf => write!(f, "not an ELF file"),
+            ElfError::UnsupportedFormat => write!(f, "not a little-endian ELF64 file"),
+            ElfError::WrongMachine(machine) => {
+                write!(f, "built for machine {}, not RISC-V", machine)
+            }
+            ElfError::NotExecutable(kind) => write!(f, "ELF type {} is not executable", kind),
+            ElfError::FloatAbi => write!(f, "uses the hardware floating-point ABI"),
+            ElfError::Dynamic => write!(f, "dynamically linked executables are not supported"),
+            ElfError::BadSegment(index) => write!(f, "bad program header {}", index),
+            ElfError::NoSegments => write!(f, "no loadable segments"),
+            ElfError::BadEntry => write!(f, "entry point outside executable segments"),
+            ElfError::ArgumentsTooLong => write!(f, "argument list too long"),
+        }
+    }
+}
+
+impl ElfError {
+    /// The `errno` value for the error: `E2BIG` for arguments that do not
+    /// fit, `ENOEXEC` for a file that cannot run.
+    pub fn errno(&self) -> isize {
+        match self {
+            ElfError::ArgumentsTooLong => 7,
+            _ => 8,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, ElfError>;
+
+fn read<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N]> {
+    offset
+        .checked_add(N)
+        .and_then(|end| data.get(offset..end))
+        .map(|bytes| bytes.try_into().unwrap())
+        .ok_or(ElfError::Truncated)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    read(data, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    read(data, offset).map(u32::from_le_bytes)
+}
+
+fn read_usize(data: &[u8], offset: usize) -> Result<usize> {
+    read(data, offset).map(|bytes| u64::from_le_bytes(bytes) as usize)
+}
+
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    filesz: usize,
+    memsz: usize,
+}
+
+impl ProgramHeader {
+    fn parse(data: &[u8], offset: usize) -> Result<Self> {
+        Ok(Self {
+            kind: read_u32(data, offset)?,
+            flags: read_u32(data, offset + 4)?,
+            offset: read_usize(data, offset + 8)?,
+            vaddr: read_usize(data, offset + 16)?,
+            filesz: read_usize(data, offset + 32)?,
+            memsz: read_usize(data, offset + 40)?,
+        })
+    }
+
+    fn permission(&self) -> MapPermission {
+        let mut perm = MapPermission::U;
+        // Sv39 has no write-only pages.
+        if self.flags & (PF_R | PF_W) != 0 {
+            perm |= MapPermission::R;
+        }
+        if self.flags & PF_W != 0 {
+            perm |= MapPermission::W;
+        }
+        if self.flags & PF_X != 0 {
+            perm |= MapPermission::X;
+        }
+        perm
+    }
+}
+
+/// An executable mapped into its address space, ready to start.
+pub struct Image {
+    pub memory_set: MemorySet,
+    pub entry: usize,
+    /// Initial stack pointer, at `argc`.
+    pub sp: usize,
+    /// First page after the loaded segments.
+    pub heap_start: usize,
+}
+
+/// Maps the executable `elf` into a new address space with a stack holding
+/// `argv`, `envp` and the auxiliary vector.
+pub fn load(elf: &[u8], argv: &[&str], envp: &[&str]) -> Result<Image> {
+    if elf.get(..4) != Some(ELF_MAGIC) {
+        return Err(ElfError::NotElf);
+    }
+    let ident: [u8; 16] = read(elf, 0)?;
+    if ident[4] != ELFCLASS64 || ident[5] != ELFDATA2LSB || ident[6] != EV_CURRENT {
+        return Err(ElfError::UnsupportedFormat);
+    }
+    let kind = read_u16(elf, 16)?;
+    let machine = read_u16(elf, 18)?;
+    if machine != EM_RISCV {
+        return Err(ElfError::WrongMachine(machine));
+    }
+    let base = match kind {
+        ET_EXEC => 0,
+        ET_DYN => PIE_BASE,
+        _ => return Err(ElfError::NotExecutable(kind)),
+    };
+    if read_u32(elf, 48)? & EF_RISCV_FLOAT_ABI != 0 {
+        return Err(ElfError::FloatAbi);
+    }
+    let entry = base.wrapping_add(read_usize(elf, 24)?);
+    let phoff = read_usize(elf, 32)?;
+    let phentsize = read_u16(elf, 54)? as usize;
+    let phnum = read_u16(elf, 56)? as usize;
+    if phentsize != PHDR_SIZE {
+        return Err(ElfError::UnsupportedFormat);
+    }
+    phnum
+        .checked_mul(PHDR_SIZE)
+        .and_then(|size| size.checked_add(phoff))
+        .filter(|&end| end <= elf.len())
+        .ok_or(ElfError::Truncated)?;
+    let headers = (0..phnum)
+        .map(|index| ProgramHeader::parse(elf, phoff + index * PHDR_SIZE))
+        .collect::<Result<Vec<_>>>()?;
+    if headers.iter().any(|ph| ph.kind == PT_INTERP) {
+        return Err(ElfError::Dynamic);
+    }
+
+    let mut memory_set = MemorySet::new_user();
+    // Page ranges mapped so far, to catch overlapping segments before the
+    // page table does.
+    let mut mapped: Vec<(usize, usize)> = Vec::new();
+    let mut phdr = None;
+    let mut entry_mapped = false;
+    let mut load_end = 0;
+    for (index, ph) in headers.iter().enumerate() {
+        if ph.kind == PT_PHDR {
+            phdr = Some(base.wrapping_add(ph.vaddr));
+        }
+        if ph.kind != PT_LOAD || ph.memsz == 0 {
+            continue;
+        }
+        let bad = ElfError::BadSegment(index);
+        let start = base.checked_add(ph.vaddr).ok_or(bad)?;
+        let end = start.checked_add(ph.memsz).ok_or(bad)?;
+        let file_end = ph.offset.checked_add(ph.filesz).ok_or(bad)?;
+        if ph.filesz > ph.memsz || file_end > elf.len() || end > USER_MEMORY_END {
+            return Err(bad);
+        }
+        let pages = (
+            VirtAddr::from(start).floor().0,
+            VirtAddr::from(end).ceil().0,
+        );
+        if mapped.iter().any(|&(s, e)| pages.0 < e && s < pages.1) {
+            return Err(bad);
+        }
+        mapped.push(pages);
+        load_end = load_end.max(end);
+        entry_mapped |= ph.flags & PF_X != 0 && (start..end).contains(&entry);
+        // Program headers inside a loaded segment are visible to the program.
+        if phdr.is_none() && ph.offset <= phoff && phoff < file_end {
+            phdr = Some(start + (phoff - ph.offset));
+        }
+        memory_set.push_at(
+            MapArea::new(start.into(), end.into(), MapType::Framed, ph.permission()),
+            VirtAddr::from(start).page_offset(),
+            &elf[ph.offset..file_end],
+        );
+    }
+    if mapped.is_empty() {
+        return Err(ElfError::NoSegments);
+    }
+    if !entry_mapped {
+        return Err(ElfError::BadEntry);
+    }
+
+    map_user_stack(&mut memory_set);
+    let auxv = [
+        (AT_PHDR, phdr.unwrap_or(0)),
+        (AT_PHENT, PHDR_SIZE),
+        (AT_PHNUM, phnum),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, 0),
+        (AT_ENTRY, entry),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_SECURE, 0),
+    ];
+    let sp = push_arguments(&mut memory_set, argv, envp, &auxv)?;
+    Ok(Image {
+        memory_set,
+        entry,
+        sp,
+        heap_start: load_end.next_multiple_of(PAGE_SIZE),
+    })
+}
+
+/// Copies `data` to the user address `addr` in `memory_set`.
+fn write_user(memory_set: &mut MemorySet, addr: usize, data: &[u8]) {
+    let buffers = memory_set
+        .translated_byte_buffer(addr, data.len(), true)
+        .expect("user stack is not mapped");
+    let mut done = 0;
+    for buffer in buffers {
+        buffer.copy_from_slice(&data[done..done + buffer.len()]);
+        done += buffer.len();
+    }
+}
+
+/// Lays out the initial stack as the RISC-V Linux ABI has it and returns the
+/// stack pointer: `argc`, the `argv` and `envp` pointer arrays, each ending
+/// in a null pointer, and the auxiliary vector, with the strings they point
+/// to above them.
+fn push_arguments(
+    memory_set: &mut MemorySet,
+    argv: &[&str],
+    envp: &[&str],
+    auxv: &[(usize, usize)],
+) -> Result<usize> {
+    let strings_size: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+    let words = 1 + argv.len() + 1 + envp.len() + 1 + 2 * (auxv.len() + 2);
+    if strings_size + 16 + words * 8 > ARG_MAX {
+        return Err(ElfError::ArgumentsTooLong);
+    }
+
+    let mut sp = USER_STACK_TOP;
+    let mut push_string = |s: &str| {
+        sp -= s.len() + 1;
+        write_user(memory_set, sp, s.as_bytes());
+        write_user(memory_set, sp + s.len(), &[0]);
+        sp
+    };
+    let envp: Vec<usize> = envp.iter().map(|s| push_string(s)).collect();
+    let argv: Vec<usize> = argv.iter().map(|s| push_string(s)).collect();
+    sp -= 16;
+    let random = sp;
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&devfs::random_u64().to_ne_bytes());
+    bytes[8..].copy_from_slice(&devfs::random_u64().to_ne_bytes());
+    write_user(memory_set, random, &bytes);
+
+    let mut table = Vec::with_capacity(words);
+    table.push(argv.len());
+    table.extend(&argv);
+    table.push(0);
+    table.extend(&envp);
+    table.push(0);
+    for &(key, value) in auxv.iter().chain(&[(AT_RANDOM, random), (AT_NULL, 0)]) {
+        table.push(key);
+        table.push(value);
+    }
+    sp = (sp - table.len() * 8) & !0xf;
+    let table: Vec<u8> = table.iter().flat_map(|word| word.to_le_bytes()).collect();
+    write_user(memory_set, sp, &table);
+    Ok(sp)
+}
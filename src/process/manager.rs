@@ -0,0 +1,42 @@
+//! Every live process by PID, and init, which adopts orphans.
+
+use super::Process;
+use ::alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+lazy_static! {
+    /// Processes from creation until they are reaped.
+    static ref PROCESSES: Mutex<BTreeMap<usize, Arc<Process>>> = Mutex::new(BTreeMap::new());
+    static ref INIT: Mutex<Option<Arc<Process>>> = Mutex::new(None);
+}
+
+pub fn insert(process: Arc<Process>) {
+    PROCESSES.lock().insert(process.pid(), process);
+}
+
+pub fn remove(pid: usize) {
+    PROCESSES.lock().remove(&pid);
+}
+
+pub fn get(pid: usize) -> Option<Arc<Process>> {
+    PROCESSES.lock().get(&pid).cloned()
+}
+
+/// PIDs of all processes, in increasing order.
+pub fn pids() -> Vec<usize> {
+    PROCESSES.lock().keys().copied().collect()
+}
+
+/// Whether every process has been reaped.
+pub fn is_empty() -> bool {
+    PROCESSES.lock().is_empty()
+}
+
+pub fn init() -> Option<Arc<Process>> {
+    INIT.lock().clone()
+}
+
+pub fn set_init(process: Option<Arc<Process>>) {
+    *INIT.lock() = process;
+}
@@ -0,0 +1,704 @@
+//! User processes.
+//!
+//! A process runs in U-mode in an address space of its own. The kernel
+//! enters it with [`trap::enter_user`] and gets control back on every trap,
+//! which it handles on behalf of the process before resuming it.
+//!
+//! Processes form a tree through `fork`, and each is kept as a zombie from
+//! its exit until its parent reaps it with `waitpid`. Each process runs on
+//! a task of its own, which the scheduler switches out when the process
+//! yields, blocks or uses up its time slice. A process waiting for a child
+//! is not suspended inside the kernel: its `sepc` is moved back to the
+//! `ecall`, and it makes the call again once woken.
+
+pub mod elf;
+
+mod manager;
+mod pid;
+
+use crate::devices::plic;
+use crate::fs::{self, FdTable, OpenFlags};
+use crate::hart;
+use crate::mm::{
+    MapArea, MapPermission, MapType, MemorySet, PhysPageNum, VirtAddr, PAGE_SIZE, STACK_GUARD_GAP,
+    TRAP_CONTEXT, USER_STACK_MAX, USER_STACK_SIZE, USER_STACK_TOP,
+};
+use crate::syscall;
+use crate::task::{self, SchedStats, Task};
+use crate::timer;
+use crate::trap::{self, UserContext};
+use ::alloc::{
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::arch::global_asm;
+use elf::Image;
+use pid::{pid_alloc, PidHandle};
+use riscv::register::scause::{Exception, Interrupt, Trap};
+use riscv::register::stval;
+use spin::{Mutex, MutexGuard};
+
+/// Where [`Process::from_code`] loads code.
+const USER_TEXT_BASE: usize = 0x1_0000;
+
+/// Code, heap and memory mappings end below the room the stack may grow
+/// into.
+pub const USER_MEMORY_END: usize = USER_STACK_TOP - USER_STACK_MAX - STACK_GUARD_GAP;
+
+// Programs built into the kernel, each a run of position-independent code.
+global_asm!(
+    "
+    .section .rodata.user
+    .p2align 2
+    .globl suser_hello
+    .globl euser_hello
+suser_hello:
+    li a7, 64
+    li a0, 1
+    lla a1, 1f
+    lla a2, 2f
+    sub a2, a2, a1
+    ecall
+    li a7, 93
+    li a0, 0
+    ecall
+1:
+    .ascii \"Hello from U-mode!\\n\"
+2:
+    .p2align 2
+euser_hello:
+
+    .globl suser_fault
+    .globl euser_fault
+suser_fault:
+    sd zero, 0(zero)
+euser_fault:
+
+    .globl suser_ioctl
+    .globl euser_ioctl
+suser_ioctl:
+    # Asks the console for its window size, then for a request it does not
+    # know, which must fail with ENOTTY.
+    addi sp, sp, -16
+    li a7, 29
+    li a0, 1
+    li a1, 0x5413
+    mv a2, sp
+    ecall
+    bnez a0, 1f
+    lhu t0, 2(sp)
+    li t1, 80
+    bne t0, t1, 1f
+    li a7, 29
+    li a0, 1
+    li a1, 0x1234
+    mv a2, sp
+    ecall
+    li t0, -25
+    bne a0, t0, 1f
+    lla a1, 2f
+    lla a2, 3f
+    j 4f
+1:
+    lla a1, 3f
+    lla a2, 5f
+4:
+    sub a2, a2, a1
+    li a7, 64
+    li a0, 1
+    ecall
+    li a7, 93
+    li a0, 0
+    ecall
+2:
+    .ascii \"ioctl OK\\n\"
+3:
+    .ascii \"ioctl failed\\n\"
+5:
+    .p2align 2
+euser_ioctl:
+"
+);
+
+/// Code of a program built into the kernel.
+pub fn builtin(name: &str) -> Option<&'static [u8]> {
+    extern "C" {
+        fn suser_hello();
+        fn euser_hello();
+        fn suser_fault();
+        fn euser_fault();
+        fn suser_ioctl();
+        fn euser_ioctl();
+    }
+    let (start, end) = match name {
+        "hello" => (suser_hello as usize, euser_hello as usize),
+        "fault" => (suser_fault as usize, euser_fault as usize),
+        "ioctl" => (suser_ioctl as usize, euser_ioctl as usize),
+        _ => return None,
+    };
+    Some(unsafe { core::slice::from_raw_parts(start as *const u8, end - start) })
+}
+
+/// Maps the user stack, below [`USER_STACK_TOP`].
+fn map_user_stack(memory_set: &mut MemorySet) {
+    memory_set.insert_stack(USER_STACK_TOP, USER_STACK_SIZE);
+}
+
+/// Standard input, output and error, all on the console.
+fn standard_files() -> FdTable {
+    let mut fd_table = FdTable::new();
+    for flags in [OpenFlags::RDONLY, OpenFlags::WRONLY, OpenFlags::WRONLY] {
+        match fs::open("/", "/dev/console", flags) {
+            Ok(file) => {
+                fd_table.insert(file).unwrap();
+            }
+            Err(err) => {
+                log!("/dev/console: {}", err);
+                break;
+            }
+        }
+    }
+    fd_table
+}
+
+/// Signals a fault kills a process with, as `waitpid` reports them.
+pub const SIGILL: i32 = 4;
+pub const SIGTRAP: i32 = 5;
+pub const SIGBUS: i32 = 7;
+pub const SIGSEGV: i32 = 11;
+
+/// The signal for a fault of kind `cause`.
+fn fault_signal(cause: Exception) -> i32 {
+    match cause {
+        Exception::IllegalInstruction => SIGILL,
+        Exception::Breakpoint => SIGTRAP,
+        Exception::InstructionMisaligned | Exception::StoreMisaligned => SIGBUS,
+        _ => SIGSEGV,
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum State {
+    /// Yielding, or woken and waiting to run.
+    Ready,
+    /// Running on a hart.
+    Running,
+    /// Blocked in `waitpid` until a child exits.
+    Waiting,
+    /// Exited, and kept until its parent reaps it.
+    Zombie,
+}
+
+/// What [`Process::wait`] found.
+pub enum WaitStatus {
+    /// A child with this PID exited with this status, and is reaped.
+    Exited(usize, i32),
+    /// No matching child has exited yet.
+    Running,
+    /// There is no matching child.
+    NoChild,
+}
+
+pub struct Process {
+    pid: PidHandle,
+    inner: Mutex<ProcessInner>,
+}
+
+/// The state of a process that changes once it runs, behind a lock so that
+/// other harts can look at it and its relatives can update it.
+pub struct ProcessInner {
+    pub state: State,
+    /// The task the process runs on, from when it starts until it exits.
+    task: Option<Arc<Task>>,
+    parent: Option<Weak<Process>>,
+    children: Vec<Arc<Process>>,
+    memory_set: MemorySet,
+    /// Frame of the page at [`TRAP_CONTEXT`], for access from the kernel.
+    trap_cx_ppn: PhysPageNum,
+    pub fd_table: FdTable,
+    /// Working directory, which relative paths start from.
+    pub cwd: String,
+    /// Arguments the current image was started with.
+    pub argv: Vec<String>,
+    /// Start of the heap, right after the loaded image.
+    heap_start: usize,
+    /// The program break: the end of the heap.
+    brk: usize,
+    /// What `waitpid` reports once the process is a zombie: the exit code
+    /// in bits 8 to 15, or the signal that killed it in bits 0 to 6.
+    wait_status: i32,
+}
+
+impl ProcessInner {
+    /// Switches to the address space of `image`, with an empty heap at its
+    /// end, and resets the trap context to start it.
+    fn load(&mut self, image: Image) {
+        let mut memory_set = image.memory_set;
+        memory_set.push(
+            MapArea::new(
+                image.heap_start.into(),
+                image.heap_start.into(),
+                MapType::Lazy,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            ),
+            None,
+        );
+        self.trap_cx_ppn = trap_cx_ppn(&memory_set);
+        *self.trap_cx_ppn.get_mut() = UserContext::new(image.entry, image.sp);
+        self.memory_set = memory_set;
+        self.heap_start = image.heap_start;
+        self.brk = image.heap_start;
+    }
+
+    pub fn memory_set(&self) -> &MemorySet {
+        &self.memory_set
+    }
+
+    pub fn memory_set_mut(&mut self) -> &mut MemorySet {
+        &mut self.memory_set
+    }
+
+    pub fn context(&self) -> &'static mut UserContext {
+        self.trap_cx_ppn.get_mut()
+    }
+
+    /// PID of the parent, or 0 for a process the kernel started or whose
+    /// parent is gone.
+    pub fn ppid(&self) -> usize {
+        self.parent
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .map_or(0, |parent| parent.pid())
+    }
+
+    pub fn brk(&self) -> usize {
+        self.brk
+    }
+
+    /// Moves the program break to `brk`, mapping or unmapping heap pages,
+    /// unless that would take it outside the heap or into a mapping.
+    pub fn set_brk(&mut self, brk: usize) {
+        if brk < self.heap_start || brk > USER_MEMORY_END {
+            return;
+        }
+        let heap = VirtAddr::from(self.heap_start).floor();
+        if self.memory_set.set_area_end(heap, brk.into()) {
+            self.brk = brk;
+        }
+    }
+}
+
+/// Frame of the page at [`TRAP_CONTEXT`] in `memory_set`.
+fn trap_cx_ppn(memory_set: &MemorySet) -> PhysPageNum {
+    memory_set
+        .translate(VirtAddr::from(TRAP_CONTEXT).into())
+        .expect("no trap context page")
+        .ppn()
+}
+
+impl Process {
+    /// A process that runs `image`, started with the arguments `argv`, with
+    /// the standard files open. It is registered but not started.
+    fn new(image: Image, argv: Vec<String>) -> Arc<Self> {
+        let mut inner = ProcessInner {
+            state: State::Ready,
+            task: None,
+            parent: None,
+            children: Vec::new(),
+            memory_set: MemorySet::new_bare(),
+            trap_cx_ppn: PhysPageNum(0),
+            fd_table: standard_files(),
+            cwd: String::from("/"),
+            argv,
+            heap_start: 0,
+            brk: 0,
+            wait_status: 0,
+        };
+        inner.load(image);
+        let process = Arc::new(Self {
+            pid: pid_alloc(),
+            inner: Mutex::new(inner),
+        });
+        manager::insert(process.clone());
+        process
+    }
+
+    /// A process running `code` from its first byte.
+    pub fn from_code(name: &str, code: &[u8]) -> Arc<Self> {
+        let mut memory_set = MemorySet::new_user();
+        memory_set.push(
+            MapArea::new(
+                USER_TEXT_BASE.into(),
+                (USER_TEXT_BASE + code.len()).into(),
+                MapType::Framed,
+                MapPermission::R | MapPermission::X | MapPermission::U,
+            ),
+            Some(code),
+        );
+        map_user_stack(&mut memory_set);
+        let image = Image {
+            memory_set,
+            entry: USER_TEXT_BASE,
+            sp: USER_STACK_TOP,
+            heap_start: (USER_TEXT_BASE + code.len()).next_multiple_of(PAGE_SIZE),
+        };
+        Self::new(image, vec![String::from(name)])
+    }
+
+    /// A process running the executable `elf` with the arguments `argv` and
+    /// the environment `envp`.
+    pub fn from_elf(elf: &[u8], argv: &[&str], envp: &[&str]) -> elf::Result<Arc<Self>> {
+        let image = elf::load(elf, argv, envp)?;
+        Ok(Self::new(
+            image,
+            argv.iter().map(|&arg| arg.into()).collect(),
+        ))
+    }
+
+    pub fn pid(&self) -> usize {
+        self.pid.0
+    }
+
+    pub fn inner(&self) -> MutexGuard<'_, ProcessInner> {
+        self.inner.lock()
+    }
+
+    /// A child that is a copy of the process, with a copy-on-write copy of
+    /// its address space and the same open files, started. It resumes where
+    /// the process is, except that its `a0` is 0, and its `sp` is `stack`
+    /// if given.
+    pub fn fork(self: &Arc<Self>, stack: Option<usize>) -> Arc<Self> {
+        let mut inner = self.inner();
+        let memory_set = inner.memory_set.fork();
+        let trap_cx_ppn = trap_cx_ppn(&memory_set);
+        let user = &mut trap_cx_ppn.get_mut::<UserContext>().user;
+        user.x[10] = 0;
+        if let Some(stack) = stack {
+            user.x[2] = stack;
+        }
+        let child = Arc::new(Self {
+            pid: pid_alloc(),
+            inner: Mutex::new(ProcessInner {
+                state: State::Ready,
+                task: None,
+                parent: Some(Arc::downgrade(self)),
+                children: Vec::new(),
+                memory_set,
+                trap_cx_ppn,
+                fd_table: inner.fd_table.clone(),
+                cwd: inner.cwd.clone(),
+                argv: inner.argv.clone(),
+                heap_start: inner.heap_start,
+                brk: inner.brk,
+                wait_status: 0,
+            }),
+        });
+        inner.children.push(child.clone());
+        drop(inner);
+        manager::insert(child.clone());
+        child.start();
+        child
+    }
+
+    /// Starts a task that runs the process until it exits.
+    pub fn start(self: &Arc<Self>) {
+        let process = self.clone();
+        // The task waits for the lock to run, until it is recorded.
+        let mut inner = self.inner();
+        inner.task = Some(task::spawn_kernel_thread(move || process.run()));
+    }
+
+    /// Replaces the image of the process with the executable `elf`, started
+    /// with the arguments `argv` and the environment `envp`. Open files and
+    /// the working directory stay. On failure the process is unchanged.
+    pub fn exec(&self, elf: &[u8], argv: &[&str], envp: &[&str]) -> elf::Result<()> {
+        let image = elf::load(elf, argv, envp)?;
+        let mut inner = self.inner();
+        inner.load(image);
+        inner.argv = argv.iter().map(|&arg| arg.into()).collect();
+        Ok(())
+    }
+
+    /// Ends the process with the exit code `code`.
+    pub fn exit(self: &Arc<Self>, code: i32) {
+        self.terminate((code & 0xff) << 8);
+    }
+
+    /// Ends the process as if killed by `signal`.
+    pub fn kill(self: &Arc<Self>, signal: i32) {
+        self.terminate(signal & 0x7f);
+    }
+
+    /// Turns the process into a zombie reporting `wait_status`, frees its
+    /// memory and files, hands its children to init, and wakes its parent.
+    /// A process without a parent is reaped right away.
+    fn terminate(self: &Arc<Self>, wait_status: i32) {
+        let mut inner = self.inner();
+        inner.state = State::Zombie;
+        inner.wait_status = wait_status;
+        inner.memory_set.recycle_data_pages();
+        inner.fd_table = FdTable::new();
+        // The task ends once it sees the process exited.
+        inner.task = None;
+        let children = core::mem::take(&mut inner.children);
+        let parent = inner.parent.as_ref().and_then(Weak::upgrade);
+        drop(inner);
+
+        let init = manager::init();
+        let is_init = init.as_ref().is_some_and(|init| Arc::ptr_eq(init, self));
+        if is_init {
+            manager::set_init(None);
+        }
+        reparent(children, init.as_ref().filter(|_| !is_init));
+        match parent {
+            Some(parent) => wake(&parent),
+            None => {
+                log!("[pid {}] exited with status {:#x}", self.pid(), wait_status);
+                manager::remove(self.pid());
+            }
+        }
+    }
+
+    /// Reaps a zombie child: the one with PID `pid`, or any if `None`. If
+    /// there are children but none of them has exited and `block` is set,
+    /// the process waits for one to exit and then makes the system call
+    /// again, as its `sepc` is moved back to the `ecall`.
+    pub fn wait(&self, pid: Option<usize>, block: bool) -> WaitStatus {
+        let mut inner = self.inner();
+        let mut found = false;
+        let mut exited = None;
+        for (index, child) in inner.children.iter().enumerate() {
+            if pid.is_some_and(|pid| pid != child.pid()) {
+                continue;
+            }
+            found = true;
+            let child = child.inner();
+            if child.state == State::Zombie {
+                exited = Some((index, child.wait_status));
+                break;
+            }
+        }
+        if let Some((index, wait_status)) = exited {
+            let child = inner.children.remove(index);
+            manager::remove(child.pid());
+            return WaitStatus::Exited(child.pid(), wait_status);
+        }
+        if !found {
+            return WaitStatus::NoChild;
+        }
+        if block {
+            inner.context().user.sepc -= 4;
+            inner.state = State::Waiting;
+        }
+        WaitStatus::Running
+    }
+
+    /// Nice value of the process, or `None` once it has exited.
+    pub fn nice(&self) -> Option<i32> {
+        self.inner().task.as_ref().map(|task| task.nice())
+    }
+
+    /// Sets the nice value of the process, unless it has exited.
+    pub fn set_nice(&self, nice: i32) -> bool {
+        match &self.inner().task {
+            Some(task) => {
+                task.set_nice(nice);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Harts the process may run on, or `None` once it has exited.
+    pub fn affinity(&self) -> Option<usize> {
+        self.inner().task.as_ref().map(|task| task.affinity())
+    }
+
+    /// Restricts the process to the harts in `mask`, unless it has exited.
+    pub fn set_affinity(&self, mask: usize) -> bool {
+        match &self.inner().task {
+            Some(task) => {
+                task.set_affinity(mask);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// How long the process has run and waited to run, unless it has
+    /// exited.
+    pub fn sched_stats(&self) -> Option<SchedStats> {
+        self.inner().task.as_ref().map(|task| task.stats())
+    }
+
+    /// Moves the process's task to the back of the run queue once its
+    /// current trap is handled.
+    pub fn yield_now(&self) {
+        self.inner().state = State::Ready;
+    }
+
+    /// Handles a trap the process took.
+    fn handle_trap(self: &Arc<Self>, trap: Trap) {
+        match trap {
+            Trap::Exception(Exception::UserEnvCall) => {
+                let cx = &mut self.inner().context().user;
+                // Resume after the `ecall`.
+                cx.sepc += 4;
+                let id = cx.x[17];
+                let args = [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]];
+                let ret = syscall::syscall(self, id, args);
+                let inner = self.inner();
+                // An exited process has no trap context left, and `execve`
+                // replaces it.
+                if let (Some(ret), false) = (ret, inner.state == State::Zombie) {
+                    inner.context().user.x[10] = ret as usize;
+                }
+            }
+            Trap::Interrupt(Interrupt::SupervisorExternal) => plic::handle_external(),
+            Trap::Interrupt(Interrupt::SupervisorTimer) => timer::handle_interrupt(),
+            Trap::Interrupt(Interrupt::SupervisorSoft) => hart::handle_ipi(),
+            Trap::Exception(
+                cause @ (Exception::LoadPageFault
+                | Exception::StorePageFault
+                | Exception::InstructionPageFault),
+            ) => {
+                let access = match cause {
+                    Exception::LoadPageFault => MapPermission::R,
+                    Exception::StorePageFault => MapPermission::W,
+                    _ => MapPermission::X,
+                };
+                let va = VirtAddr::from(stval::read());
+                if !self.inner().memory_set.handle_page_fault(va, access) {
+                    self.fault(cause);
+                }
+            }
+            Trap::Exception(cause) => self.fault(cause),
+            Trap::Interrupt(cause) => {
+                log!("[pid {}] unexpected interrupt {:?}", self.pid(), cause);
+            }
+        }
+    }
+
+    /// Kills the process for a fault it cannot recover from.
+    fn fault(self: &Arc<Self>, cause: Exception) {
+        log!(
+            "[pid {}] {:?} at {:#x}, stval = {:#x}, killed",
+            self.pid(),
+            cause,
+            self.inner().context().user.sepc,
+            stval::read()
+        );
+        self.kill(fault_signal(cause));
+    }
+
+    /// Runs the process until it exits, on its task: gives up the hart when
+    /// it yields or its time slice is up, and blocks the task while it
+    /// waits.
+    fn run(self: &Arc<Self>) {
+        loop {
+            let token = {
+                let mut inner = self.inner();
+                inner.state = State::Running;
+                inner.memory_set.enter()
+            };
+            self.handle_trap(trap::enter_user(token));
+            let inner = self.inner();
+            match inner.state {
+                State::Running => {
+                    drop(inner);
+                    task::cond_resched();
+                }
+                State::Ready => {
+                    drop(inner);
+                    task::yield_now();
+                }
+                // A child exiting wakes it under the same lock.
+                State::Waiting => task::block_current(inner),
+                State::Zombie => return,
+            }
+        }
+    }
+}
+
+/// Makes `children` of an exiting process children of `init`, or leaves
+/// them without a parent if there is no init. Exited ones go to init to
+/// reap, or are reaped right away.
+fn reparent(children: Vec<Arc<Process>>, init: Option<&Arc<Process>>) {
+    let Some(init) = init else {
+        for child in children {
+            let mut inner = child.inner();
+            inner.parent = None;
+            if inner.state == State::Zombie {
+                manager::remove(child.pid());
+            }
+        }
+        return;
+    };
+    // Init stays locked while its children change, so that one exiting in
+    // the meantime wakes it only once it is in the list.
+    let mut init_inner = init.inner();
+    let mut exited = false;
+    for child in children {
+        let mut inner = child.inner();
+        inner.parent = Some(Arc::downgrade(init));
+        exited |= inner.state == State::Zombie;
+        drop(inner);
+        init_inner.children.push(child);
+    }
+    if exited {
+        wake_locked(&mut init_inner);
+    }
+}
+
+/// Lets `process` run again if it is waiting for a child.
+fn wake(process: &Arc<Process>) {
+    wake_locked(&mut process.inner());
+}
+
+fn wake_locked(inner: &mut ProcessInner) {
+    if inner.state != State::Waiting {
+        return;
+    }
+    inner.state = State::Ready;
+    if let Some(task) = &inner.task {
+        task::wake(task);
+    }
+}
+
+/// PIDs of all processes that are not yet reaped.
+pub fn pids() -> Vec<usize> {
+    manager::pids()
+}
+
+pub fn get(pid: usize) -> Option<Arc<Process>> {
+    manager::get(pid)
+}
+
+/// Runs `/init` and whatever it starts until they are all done, or the
+/// built-in programs if there is no `/init`.
+pub fn run_init() {
+    const INIT: &str = "/init";
+    let elf = match fs::read("/", INIT) {
+        Ok(elf) => elf,
+        Err(err) => {
+            log!("{}: {}, running the built-in programs", INIT, err);
+            for name in ["hello", "fault", "ioctl"] {
+                Process::from_code(name, builtin(name).unwrap()).start();
+            }
+            task::run_until(manager::is_empty);
+            return;
+        }
+    };
+    match Process::from_elf(&elf, &[INIT], &[]) {
+        Ok(init) => {
+            manager::set_init(Some(init.clone()));
+            init.start();
+            task::run_until(manager::is_empty);
+        }
+        Err(err) => {
+            log!("{}: {}", INIT, err);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Process IDs, handed out from 1 and reused once freed.
+
+use ::alloc::vec::Vec;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+struct PidAllocator {
+    /// The lowest PID never handed out.
+    next: usize,
+    /// Freed PIDs, reused before new ones.
+    recycled: Vec<usize>,
+}
+
+impl PidAllocator {
+    fn alloc(&mut self) -> usize {
+        self.recycled.pop().unwrap_or_else(|| {
+            self.next += 1;
+            self.next - 1
+        })
+    }
+
+    fn dealloc(&mut self, pid: usize) {
+        assert!(pid < self.next, "PID {} was never allocated", pid);
+        assert!(!self.recycled.contains(&pid), "PID {} freed twice", pid);
+        self.recycled.push(pid);
+    }
+}
+
+lazy_static! {
+    static ref PID_ALLOCATOR: Mutex<PidAllocator> = Mutex::new(PidAllocator {
+        next: 1,
+        recycled: Vec::new(),
+    });
+}
+
+/// A PID, freed for reuse when dropped.
+pub struct PidHandle(pub usize);
+
+impl Drop for PidHandle {
+    fn drop(&mut self) {
+        PID_ALLOCATOR.lock().dealloc(self.0);
+    }
+}
+
+pub fn pid_alloc() -> PidHandle {
+    PidHandle(PID_ALLOCATOR.lock().alloc())
+}
@@ -170,6 +170,24 @@ pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> SbiRet {
     )
 }
 
+const FUNCTION_RFENCE_REMOTE_SFENCE_VMA: usize = 0x1;
+
+pub fn remote_sfence_vma(
+    hart_mask: usize,
+    hart_mask_base: usize,
+    start_addr: usize,
+    size: usize,
+) -> SbiRet {
+    sbi_call_4(
+        EXTENSION_RFENCE,
+        FUNCTION_RFENCE_REMOTE_SFENCE_VMA,
+        hart_mask,
+        hart_mask_base,
+        start_addr,
+        size,
+    )
+}
+
 const FUNCTION_HSM_HART_START: usize = 0x0;
 const FUNCTION_HSM_HART_STOP: usize = 0x1;
 const FUNCTION_HSM_HART_GET_STATUS: usize = 0x2;
@@ -289,3 +307,32 @@ fn sbi_call_3(extension: usize, function: usize, arg0: usize, arg1: usize, arg2:
     };
     SbiRet { error, value }
 }
+
+#[inline(always)]
+fn sbi_call_4(
+    extension: usize,
+    function: usize,
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+) -> SbiRet {
+    let (error, value);
+    match () {
+        #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+        () => unsafe {
+            asm!(
+                "ecall",
+                in("a0") arg0, in("a1") arg1, in("a2") arg2, in("a3") arg3,
+                in("a6") function, in("a7") extension,
+                lateout("a0") error, lateout("a1") value,
+            )
+        },
+        #[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
+        () => {
+            drop((extension, function, arg0, arg1, arg2, arg3));
+            unimplemented!("not RISC-V instruction set architecture")
+        }
+    };
+    SbiRet { error, value }
+}
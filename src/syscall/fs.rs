@@ -0,0 +1,173 @@
+use super::{check_user, read_path, read_user, write_user, Errno, SyscallResult};
+use crate::fs::{self, DirEntry, FileType, FsError, OpenFlags, SeekFrom};
+use crate::mm::PAGE_SIZE;
+use crate::process::Process;
+use ::alloc::{sync::Arc, vec, vec::Vec};
+
+/// `dirfd` of `openat` that stands for the working directory.
+const AT_FDCWD: isize = -100;
+
+/// Flag of `unlinkat` to remove a directory instead.
+const AT_REMOVEDIR: usize = 0x200;
+
+const SEEK_SET: usize = 0;
+const SEEK_CUR: usize = 1;
+const SEEK_END: usize = 2;
+
+/// Most bytes `read` and `write` move through a kernel buffer at once.
+const CHUNK_SIZE: usize = PAGE_SIZE;
+
+/// Performs `request` on the file, storing what it gives back, if anything,
+/// at `arg`.
+pub fn sys_ioctl(process: &Arc<Process>, [fd, request, arg, ..]: [usize; 6]) -> SyscallResult {
+    let file = process.inner().fd_table.get(fd)?;
+    let reply = file.ioctl(request as u32)?;
+    if !reply.is_empty() {
+        write_user(process, arg, &reply)?;
+    }
+    Ok(0)
+}
+
+pub fn sys_openat(process: &Arc<Process>, [dirfd, path, flags, ..]: [usize; 6]) -> SyscallResult {
+    let path = read_path(process, path)?;
+    if dirfd as isize != AT_FDCWD && !path.starts_with('/') {
+        // Open files do not keep their path to resolve against.
+        process.inner().fd_table.get(dirfd)?;
+        return Err(Errno::EOPNOTSUPP);
+    }
+    let cwd = process.inner().cwd.clone();
+    let file = fs::open(&cwd, &path, OpenFlags(flags as u32))?;
+    Ok(process.inner().fd_table.insert(file)?)
+}
+
+pub fn sys_unlinkat(process: &Arc<Process>, [dirfd, path, flags, ..]: [usize; 6]) -> SyscallResult {
+    let path = read_path(process, path)?;
+    if dirfd as isize != AT_FDCWD && !path.starts_with('/') {
+        process.inner().fd_table.get(dirfd)?;
+        return Err(Errno::EOPNOTSUPP);
+    }
+    if flags & !AT_REMOVEDIR != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let cwd = process.inner().cwd.clone();
+    if flags & AT_REMOVEDIR != 0 {
+        fs::rmdir(&cwd, &path)?;
+    } else {
+        fs::unlink(&cwd, &path)?;
+    }
+    Ok(0)
+}
+
+pub fn sys_close(process: &Arc<Process>, [fd, ..]: [usize; 6]) -> SyscallResult {
+    process.inner().fd_table.close(fd)?;
+    Ok(0)
+}
+
+/// `struct linux_dirent64` for `entry`, at directory position `index`.
+fn encode_dirent(entry: &DirEntry, index: usize) -> Vec<u8> {
+    let kind: u8 = match entry.kind {
+        FileType::Regular => 8,
+        FileType::Directory => 4,
+        FileType::Symlink => 10,
+        FileType::CharDevice => 2,
+        FileType::BlockDevice => 6,
+    };
+    // Inode and next position, record length and type, then the name with
+    // its NUL, padded to 8 bytes.
+    let len = (19 + entry.name.len() + 1).next_multiple_of(8);
+    let mut record = Vec::with_capacity(len);
+    record.extend_from_slice(&entry.ino.to_ne_bytes());
+    record.extend_from_slice(&(index as u64 + 1).to_ne_bytes());
+    record.extend_from_slice(&(len as u16).to_ne_bytes());
+    record.push(kind);
+    record.extend_from_slice(entry.name.as_bytes());
+    record.resize(len, 0);
+    record
+}
+
+pub fn sys_getdents64(process: &Arc<Process>, [fd, buf, len, ..]: [usize; 6]) -> SyscallResult {
+    let file = process.inner().fd_table.get(fd)?;
+    let dir = file
+        .inode()
+        .filter(|inode| matches!(inode.metadata().map(|m| m.kind), Ok(FileType::Directory)))
+        .ok_or(Errno::ENOTDIR)?;
+    let start = file.seek(SeekFrom::Current(0))?;
+    let mut records = Vec::new();
+    let mut index = start;
+    while let Some(entry) = dir.read_dir(index)? {
+        let record = encode_dirent(&entry, index);
+        if records.len() + record.len() > len {
+            if index == start {
+                return Err(Errno::EINVAL);
+            }
+            break;
+        }
+        records.extend_from_slice(&record);
+        index += 1;
+    }
+    write_user(process, buf, &records)?;
+    file.seek(SeekFrom::Start(index))?;
+    Ok(records.len())
+}
+
+pub fn sys_lseek(process: &Arc<Process>, [fd, offset, whence, ..]: [usize; 6]) -> SyscallResult {
+    let file = process.inner().fd_table.get(fd)?;
+    let pos = match whence {
+        SEEK_SET => SeekFrom::Start(offset),
+        SEEK_CUR => SeekFrom::Current(offset as isize),
+        SEEK_END => SeekFrom::End(offset as isize),
+        _ => return Err(Errno::EINVAL),
+    };
+    file.seek(pos).map_err(|err| match err {
+        FsError::NotSupported => Errno::ESPIPE,
+        err => err.into(),
+    })
+}
+
+/// Moves `len` bytes between a file and user memory at `addr` with
+/// `transfer`, given where a chunk goes in user memory and a kernel buffer
+/// for it, until one transfer comes up short. An error after some data has
+/// moved ends the call early instead of failing it. The file is read and
+/// written without the process locked, since that may block or look at the
+/// process, hence the kernel buffer.
+fn transfer(
+    addr: usize,
+    len: usize,
+    mut transfer: impl FnMut(usize, &mut [u8]) -> Result<usize, Errno>,
+) -> SyscallResult {
+    let mut chunk = vec![0u8; len.min(CHUNK_SIZE)];
+    let mut total = 0;
+    while total < len {
+        let n = (len - total).min(CHUNK_SIZE);
+        match transfer(addr + total, &mut chunk[..n]) {
+            Ok(done) => {
+                total += done;
+                if done < n {
+                    break;
+                }
+            }
+            Err(_) if total > 0 => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(total)
+}
+
+pub fn sys_read(process: &Arc<Process>, [fd, buf, len, ..]: [usize; 6]) -> SyscallResult {
+    let file = process.inner().fd_table.get(fd)?;
+    check_user(process, buf, len, true)?;
+    transfer(buf, len, |addr, chunk| {
+        let n = file.read(chunk)?;
+        write_user(process, addr, &chunk[..n])?;
+        Ok(n)
+    })
+}
+
+pub fn sys_write(process: &Arc<Process>, [fd, buf, len, ..]: [usize; 6]) -> SyscallResult {
+    let file = process.inner().fd_table.get(fd)?;
+    check_user(process, buf, len, false)?;
+    transfer(buf, len, |addr, chunk| {
+        read_user(process, addr, chunk)?;
+        Ok(file.write(chunk)?)
+    })
+}
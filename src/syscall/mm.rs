@@ -0,0 +1,170 @@
+use super::{Errno, SyscallResult};
+use crate::fs::{File, FsError};
+use crate::mm::{
+    AnonPages, Backing, MapPermission, VirtAddr, VirtPageNum, PAGE_SIZE, USER_STACK_TOP,
+};
+use crate::process::{Process, USER_MEMORY_END};
+use ::alloc::sync::Arc;
+
+const PROT_READ: usize = 1;
+const PROT_WRITE: usize = 2;
+const PROT_EXEC: usize = 4;
+
+/// Bits of the `mmap` flags that say whether writes are shared.
+const MAP_TYPE: usize = 0xf;
+const MAP_SHARED: usize = 1;
+const MAP_PRIVATE: usize = 2;
+const MAP_SHARED_VALIDATE: usize = 3;
+const MAP_FIXED: usize = 0x10;
+const MAP_ANONYMOUS: usize = 0x20;
+const MAP_FIXED_NOREPLACE: usize = 0x100000;
+
+/// Lowest address a mapping may start at, keeping null pointers faulting.
+const MMAP_MIN_ADDR: usize = 0x1_0000;
+
+fn map_permission(prot: usize) -> Result<MapPermission, Errno> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut perm = MapPermission::U;
+    // Pages cannot be writable without being readable.
+    if prot & (PROT_READ | PROT_WRITE) != 0 {
+        perm |= MapPermission::R;
+    }
+    if prot & PROT_WRITE != 0 {
+        perm |= MapPermission::W;
+    }
+    if prot & PROT_EXEC != 0 {
+        perm |= MapPermission::X;
+    }
+    Ok(perm)
+}
+
+/// The pages of `[addr, addr + len)`, where `addr` must be page aligned and
+/// the range within user memory.
+fn page_range(addr: usize, len: usize) -> Result<(VirtPageNum, VirtPageNum), Errno> {
+    if !addr.is_multiple_of(PAGE_SIZE) {
+        return Err(Errno::EINVAL);
+    }
+    let end = addr
+        .checked_add(len)
+        .filter(|&end| end <= USER_STACK_TOP)
+        .ok_or(Errno::ENOMEM)?;
+    Ok((VirtAddr::from(addr).floor(), VirtAddr::from(end).ceil()))
+}
+
+/// The pages of `file` from `offset` on. Writes through a
+/// `shared` mapping of them go to the file, and are only allowed if it is
+/// open for writing.
+fn file_backing(file: &dyn File, offset: usize, shared: bool) -> Result<Backing, Errno> {
+    let pages = file.page_cache(false).map_err(|err| match err {
+        FsError::NotSupported => Errno::ENODEV,
+        err => err.into(),
+    })?;
+    Ok(Backing {
+        pages,
+        offset: offset / PAGE_SIZE,
+        shared,
+        writable: !shared || file.page_cache(true).is_ok(),
+    })
+}
+
+/// Maps `len` bytes of the file `fd` from `offset`, or of zeroed memory if
+/// `MAP_ANONYMOUS` is given, and returns where. Pages are read in or
+/// allocated as they are first accessed, and those past the end of the
+/// file read as zeros.
+///
+/// Without `MAP_FIXED`, `addr` is only a hint, and the mapping goes there
+/// if it is free and highest below the room the stack grows into
+/// otherwise. With it, the mapping replaces whatever is mapped at `addr`,
+/// unless `MAP_FIXED_NOREPLACE` makes that fail instead.
+pub fn sys_mmap(
+    process: &Arc<Process>,
+    [addr, len, prot, flags, fd, offset]: [usize; 6],
+) -> SyscallResult {
+    if len == 0 || !offset.is_multiple_of(PAGE_SIZE) {
+        return Err(Errno::EINVAL);
+    }
+    let perm = map_permission(prot)?;
+    let shared = match flags & MAP_TYPE {
+        MAP_SHARED | MAP_SHARED_VALIDATE => true,
+        MAP_PRIVATE => false,
+        _ => return Err(Errno::EINVAL),
+    };
+    let pages = len.div_ceil(PAGE_SIZE);
+    let backing = if flags & MAP_ANONYMOUS != 0 {
+        shared.then(|| Backing {
+            pages: Arc::new(AnonPages::new()),
+            offset: 0,
+            shared: true,
+            writable: true,
+        })
+    } else {
+        let file = process.inner().fd_table.get(fd)?;
+        let backing = file_backing(file.as_ref(), offset, shared)?;
+        if perm.contains(MapPermission::W) && !backing.writable {
+            return Err(Errno::EACCES);
+        }
+        Some(backing)
+    };
+    let mut inner = process.inner();
+    let memory_set = inner.memory_set_mut();
+    let start = if flags & (MAP_FIXED | MAP_FIXED_NOREPLACE) != 0 {
+        if addr < MMAP_MIN_ADDR {
+            return Err(Errno::EINVAL);
+        }
+        let (start, end) = page_range(addr, len)?;
+        if !memory_set.is_free(start, end) {
+            if flags & MAP_FIXED_NOREPLACE != 0 {
+                return Err(Errno::EEXIST);
+            }
+            memory_set.unmap(start, end);
+        }
+        start
+    } else {
+        let lowest = VirtAddr::from(MMAP_MIN_ADDR).floor();
+        let highest = VirtAddr::from(USER_MEMORY_END).floor();
+        let hint = VirtAddr::from(addr).ceil();
+        let hint_free = hint >= lowest
+            && hint
+                .0
+                .checked_add(pages)
+                .is_some_and(|end| end <= highest.0)
+            && memory_set.is_free(hint, VirtPageNum(hint.0 + pages));
+        if hint_free {
+            hint
+        } else {
+            memory_set
+                .find_free(pages, lowest, highest)
+                .ok_or(Errno::ENOMEM)?
+        }
+    };
+    memory_set.insert_lazy_area(start, VirtPageNum(start.0 + pages), perm, backing);
+    Ok(VirtAddr::from(start).0)
+}
+
+/// Unmaps the pages of `[addr, addr + len)`, wherever they are mapped.
+pub fn sys_munmap(process: &Arc<Process>, [addr, len, ..]: [usize; 6]) -> SyscallResult {
+    if len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let (start, end) = page_range(addr, len).map_err(|_| Errno::EINVAL)?;
+    process.inner().memory_set_mut().unmap(start, end);
+    Ok(0)
+}
+
+/// Changes the access allowed to the pages of `[addr, addr + len)`, which
+/// must all be mapped.
+pub fn sys_mprotect(process: &Arc<Process>, [addr, len, prot, ..]: [usize; 6]) -> SyscallResult {
+    let perm = map_permission(prot)?;
+    let (start, end) = page_range(addr, len)?;
+    let mut inner = process.inner();
+    let memory_set = inner.memory_set_mut();
+    if perm.contains(MapPermission::W) && !memory_set.can_write(start, end) {
+        return Err(Errno::EACCES);
+    }
+    if !memory_set.protect(start, end, perm) {
+        return Err(Errno::ENOMEM);
+    }
+    Ok(0)
+}
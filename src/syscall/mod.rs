@@ -0,0 +1,218 @@
+//! System calls, numbered as on Linux for RISC-V.
+//!
+//! Arguments come in `a0` to `a5` and the number in `a7`. The result goes
+//! back in `a0`, with errors as negated `errno` values. Pointers from user
+//! space are translated through the process's page table, and any that do
+//! not point to memory the process may access fail with `EFAULT`.
+
+mod fs;
+mod mm;
+mod process;
+mod time;
+
+use crate::fs::FsError;
+use crate::mm::{VirtAddr, PAGE_SIZE};
+use crate::process::Process;
+use ::alloc::{string::String, sync::Arc, vec, vec::Vec};
+
+const SYSCALL_IOCTL: usize = 29;
+const SYSCALL_UNLINKAT: usize = 35;
+const SYSCALL_OPENAT: usize = 56;
+const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_GETDENTS64: usize = 61;
+const SYSCALL_LSEEK: usize = 62;
+const SYSCALL_READ: usize = 63;
+const SYSCALL_WRITE: usize = 64;
+const SYSCALL_EXIT: usize = 93;
+const SYSCALL_EXIT_GROUP: usize = 94;
+const SYSCALL_NANOSLEEP: usize = 101;
+const SYSCALL_CLOCK_GETTIME: usize = 113;
+const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+const SYSCALL_SCHED_GETAFFINITY: usize = 123;
+const SYSCALL_SCHED_YIELD: usize = 124;
+const SYSCALL_SETPRIORITY: usize = 140;
+const SYSCALL_GETPRIORITY: usize = 141;
+const SYSCALL_GETTIMEOFDAY: usize = 169;
+const SYSCALL_GETPID: usize = 172;
+const SYSCALL_GETPPID: usize = 173;
+const SYSCALL_BRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
+const SYSCALL_CLONE: usize = 220;
+const SYSCALL_EXECVE: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_WAIT4: usize = 260;
+
+/// An `errno` value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(pub isize);
+
+impl Errno {
+    pub const ESRCH: Errno = Errno(3);
+    pub const E2BIG: Errno = Errno(7);
+    pub const ECHILD: Errno = Errno(10);
+    pub const ENOMEM: Errno = Errno(12);
+    pub const EACCES: Errno = Errno(13);
+    pub const EFAULT: Errno = Errno(14);
+    pub const EEXIST: Errno = Errno(17);
+    pub const ENODEV: Errno = Errno(19);
+    pub const ENOTDIR: Errno = Errno(20);
+    pub const EINVAL: Errno = Errno(22);
+    pub const ESPIPE: Errno = Errno(29);
+    pub const ENAMETOOLONG: Errno = Errno(36);
+    pub const ENOSYS: Errno = Errno(38);
+    pub const EOPNOTSUPP: Errno = Errno(95);
+    /// Never reaches user space: the process is blocked and makes the call
+    /// again once woken.
+    pub const ERESTARTNOINTR: Errno = Errno(513);
+}
+
+impl From<FsError> for Errno {
+    fn from(err: FsError) -> Self {
+        Errno(err.errno())
+    }
+}
+
+pub type SyscallResult = Result<usize, Errno>;
+
+/// Handles a system call, given the arguments `a0` to `a5`.
+type Handler = fn(&Arc<Process>, [usize; 6]) -> SyscallResult;
+
+static SYSCALLS: &[(usize, Handler)] = &[
+    (SYSCALL_IOCTL, fs::sys_ioctl),
+    (SYSCALL_UNLINKAT, fs::sys_unlinkat),
+    (SYSCALL_OPENAT, fs::sys_openat),
+    (SYSCALL_CLOSE, fs::sys_close),
+    (SYSCALL_GETDENTS64, fs::sys_getdents64),
+    (SYSCALL_LSEEK, fs::sys_lseek),
+    (SYSCALL_READ, fs::sys_read),
+    (SYSCALL_WRITE, fs::sys_write),
+    (SYSCALL_EXIT, process::sys_exit),
+    (SYSCALL_EXIT_GROUP, process::sys_exit),
+    (SYSCALL_NANOSLEEP, time::sys_nanosleep),
+    (SYSCALL_CLOCK_GETTIME, time::sys_clock_gettime),
+    (SYSCALL_SCHED_SETAFFINITY, process::sys_sched_setaffinity),
+    (SYSCALL_SCHED_GETAFFINITY, process::sys_sched_getaffinity),
+    (SYSCALL_SCHED_YIELD, process::sys_sched_yield),
+    (SYSCALL_SETPRIORITY, process::sys_setpriority),
+    (SYSCALL_GETPRIORITY, process::sys_getpriority),
+    (SYSCALL_GETTIMEOFDAY, time::sys_gettimeofday),
+    (SYSCALL_GETPID, process::sys_getpid),
+    (SYSCALL_GETPPID, process::sys_getppid),
+    (SYSCALL_BRK, process::sys_brk),
+    (SYSCALL_MUNMAP, mm::sys_munmap),
+    (SYSCALL_CLONE, process::sys_clone),
+    (SYSCALL_EXECVE, process::sys_execve),
+    (SYSCALL_MMAP, mm::sys_mmap),
+    (SYSCALL_MPROTECT, mm::sys_mprotect),
+    (SYSCALL_WAIT4, process::sys_wait4),
+];
+
+/// Handles system call `id` and returns what goes in `a0`, or `None` if the
+/// call is to be made again once the process is woken.
+pub fn syscall(process: &Arc<Process>, id: usize, args: [usize; 6]) -> Option<isize> {
+    let Some(&(_, handler)) = SYSCALLS.iter().find(|(number, _)| *number == id) else {
+        log!("[pid {}] unsupported syscall {}", process.pid(), id);
+        return Some(-Errno::ENOSYS.0);
+    };
+    match handler(process, args) {
+        Ok(ret) => Some(ret as isize),
+        Err(Errno::ERESTARTNOINTR) => None,
+        Err(errno) => Some(-errno.0),
+    }
+}
+
+/// Longest path accepted from user space, with its terminating NUL.
+const PATH_MAX: usize = 4096;
+
+/// Calls `f` with the kernel's view of `len` bytes of user memory at `addr`,
+/// one slice per page. The process stays locked meanwhile, so that its
+/// pages cannot be unmapped or copied on write under `f`.
+fn with_user_buffers<R>(
+    process: &Process,
+    addr: usize,
+    len: usize,
+    write: bool,
+    f: impl FnOnce(Vec<&mut [u8]>) -> R,
+) -> Result<R, Errno> {
+    let mut inner = process.inner();
+    let buffers = inner
+        .memory_set_mut()
+        .translated_byte_buffer(addr, len, write)
+        .ok_or(Errno::EFAULT)?;
+    Ok(f(buffers))
+}
+
+/// Fails unless `len` bytes of user memory at `addr` can be accessed, with
+/// the faults U-mode would take on them resolved.
+fn check_user(process: &Process, addr: usize, len: usize, write: bool) -> Result<(), Errno> {
+    with_user_buffers(process, addr, len, write, |_| ())
+}
+
+/// Copies `data` to user memory at `addr`.
+fn write_user(process: &Process, addr: usize, data: &[u8]) -> Result<(), Errno> {
+    with_user_buffers(process, addr, data.len(), true, |buffers| {
+        let mut done = 0;
+        for buffer in buffers {
+            buffer.copy_from_slice(&data[done..done + buffer.len()]);
+            done += buffer.len();
+        }
+    })
+}
+
+/// Fills `data` from user memory at `addr`.
+fn read_user(process: &Process, addr: usize, data: &mut [u8]) -> Result<(), Errno> {
+    with_user_buffers(process, addr, data.len(), false, |buffers| {
+        let mut done = 0;
+        for buffer in buffers {
+            data[done..done + buffer.len()].copy_from_slice(buffer);
+            done += buffer.len();
+        }
+    })
+}
+
+/// Copies `value` to user memory at `addr`. `T` must be plain data.
+fn copy_to_user<T: Copy>(process: &Process, addr: usize, value: &T) -> Result<(), Errno> {
+    let bytes = unsafe {
+        core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+    };
+    write_user(process, addr, bytes)
+}
+
+/// Reads a `T` from user memory at `addr`. `T` must be plain data that any
+/// bit pattern is valid for.
+fn copy_from_user<T: Copy>(process: &Process, addr: usize) -> Result<T, Errno> {
+    let mut bytes = vec![0u8; core::mem::size_of::<T>()];
+    read_user(process, addr, &mut bytes)?;
+    Ok(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+/// Reads the NUL-terminated path at `addr` from user memory.
+fn read_path(process: &Process, addr: usize) -> Result<String, Errno> {
+    read_str(process, addr, PATH_MAX, Errno::ENAMETOOLONG)
+}
+
+/// Reads the NUL-terminated string at `addr` from user memory, failing with
+/// `too_long` if it takes more than `max` bytes with the NUL.
+fn read_str(process: &Process, addr: usize, max: usize, too_long: Errno) -> Result<String, Errno> {
+    let mut inner = process.inner();
+    let memory_set = inner.memory_set_mut();
+    let mut bytes = Vec::new();
+    let mut addr = addr;
+    while bytes.len() < max {
+        // Up to the end of the page, which may be the last one mapped.
+        let len = (PAGE_SIZE - VirtAddr::from(addr).page_offset()).min(max - bytes.len());
+        let chunk = memory_set
+            .translated_byte_buffer(addr, len, false)
+            .ok_or(Errno::EFAULT)?
+            .pop()
+            .unwrap();
+        if let Some(end) = chunk.iter().position(|&byte| byte == 0) {
+            bytes.extend_from_slice(&chunk[..end]);
+            return String::from_utf8(bytes).map_err(|_| Errno::EINVAL);
+        }
+        bytes.extend_from_slice(chunk);
+        addr += len;
+    }
+    Err(too_long)
+}
@@ -0,0 +1,216 @@
+use super::{
+    check_user, copy_from_user, copy_to_user, read_path, read_str, read_user, Errno, SyscallResult,
+};
+use crate::fs;
+use crate::hart;
+use crate::process::{elf::ARG_MAX, Process, WaitStatus};
+use ::alloc::{string::String, sync::Arc, vec::Vec};
+use core::mem::size_of;
+
+/// Bits of the `clone` flags that hold the signal sent to the parent when
+/// the child exits.
+const CSIGNAL: usize = 0xff;
+
+const WNOHANG: usize = 1;
+const WUNTRACED: usize = 2;
+const WCONTINUED: usize = 8;
+
+/// `which` value of `setpriority` and `getpriority` naming a process.
+const PRIO_PROCESS: usize = 0;
+
+/// Size of `struct rusage`: two `timeval`s and 14 longs.
+const RUSAGE_SIZE: usize = 144;
+
+pub fn sys_exit(process: &Arc<Process>, [code, ..]: [usize; 6]) -> SyscallResult {
+    process.exit(code as i32);
+    Ok(0)
+}
+
+pub fn sys_sched_yield(process: &Arc<Process>, _args: [usize; 6]) -> SyscallResult {
+    process.yield_now();
+    Ok(0)
+}
+
+/// The process with PID `pid`, or the caller if it is 0.
+fn target(process: &Arc<Process>, pid: usize) -> Result<Arc<Process>, Errno> {
+    match pid {
+        0 => Ok(process.clone()),
+        pid => crate::process::get(pid).ok_or(Errno::ESRCH),
+    }
+}
+
+/// The process `who` names for `setpriority` and `getpriority`. There are
+/// no process groups or users to name.
+fn priority_target(
+    process: &Arc<Process>,
+    which: usize,
+    who: usize,
+) -> Result<Arc<Process>, Errno> {
+    if which != PRIO_PROCESS {
+        return Err(Errno::EINVAL);
+    }
+    target(process, who)
+}
+
+/// Sets the nice value of a process, which weighs it against others under
+/// the stride and fair schedulers. Values out of range are clamped.
+pub fn sys_setpriority(
+    process: &Arc<Process>,
+    [which, who, nice, ..]: [usize; 6],
+) -> SyscallResult {
+    let target = priority_target(process, which, who)?;
+    if !target.set_nice(nice as i32) {
+        return Err(Errno::ESRCH);
+    }
+    Ok(0)
+}
+
+/// Returns the nice value of a process as 20 minus it, from 1 to 40, as
+/// the raw Linux call does so that no value looks like an error.
+pub fn sys_getpriority(process: &Arc<Process>, [which, who, ..]: [usize; 6]) -> SyscallResult {
+    let target = priority_target(process, which, who)?;
+    let nice = target.nice().ok_or(Errno::ESRCH)?;
+    Ok((20 - nice) as usize)
+}
+
+/// Restricts a process to the harts in the CPU mask of `len` bytes at
+/// `mask`, bit `n` standing for hart `n`. Harts that are not up are left
+/// out, and at least one must be left.
+pub fn sys_sched_setaffinity(
+    process: &Arc<Process>,
+    [pid, len, mask, ..]: [usize; 6],
+) -> SyscallResult {
+    let target = target(process, pid)?;
+    let mut bytes = [0u8; size_of::<usize>()];
+    read_user(process, mask, &mut bytes[..len.min(size_of::<usize>())])?;
+    let mask = usize::from_le_bytes(bytes) & hart::online_mask();
+    if mask == 0 {
+        return Err(Errno::EINVAL);
+    }
+    if !target.set_affinity(mask) {
+        return Err(Errno::ESRCH);
+    }
+    Ok(0)
+}
+
+/// Writes the CPU mask of a process to `mask`, and returns its size in
+/// bytes, which `len` must be a multiple of.
+pub fn sys_sched_getaffinity(
+    process: &Arc<Process>,
+    [pid, len, mask, ..]: [usize; 6],
+) -> SyscallResult {
+    if len == 0 || !len.is_multiple_of(size_of::<usize>()) {
+        return Err(Errno::EINVAL);
+    }
+    let target = target(process, pid)?;
+    let affinity = target.affinity().ok_or(Errno::ESRCH)? & hart::online_mask();
+    copy_to_user(process, mask, &affinity)?;
+    Ok(size_of::<usize>())
+}
+
+pub fn sys_getpid(process: &Arc<Process>, _args: [usize; 6]) -> SyscallResult {
+    Ok(process.pid())
+}
+
+pub fn sys_getppid(process: &Arc<Process>, _args: [usize; 6]) -> SyscallResult {
+    Ok(process.inner().ppid())
+}
+
+/// Moves the program break, and returns where it ends up: an out-of-range
+/// request leaves it where it was, and 0 just asks where it is.
+pub fn sys_brk(process: &Arc<Process>, [brk, ..]: [usize; 6]) -> SyscallResult {
+    let mut inner = process.inner();
+    if brk != 0 {
+        inner.set_brk(brk);
+    }
+    Ok(inner.brk())
+}
+
+/// Creates a child process, as `fork` does: only the exit signal may be
+/// given in `flags`, as nothing can be shared but open files. The child
+/// starts on `stack` if it is not 0.
+pub fn sys_clone(process: &Arc<Process>, [flags, stack, ..]: [usize; 6]) -> SyscallResult {
+    if flags & !CSIGNAL != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let child = process.fork((stack != 0).then_some(stack));
+    Ok(child.pid())
+}
+
+/// Reads the null-terminated array of string pointers at `addr`, which may
+/// itself be null. The strings and pointers together get [`ARG_MAX`] bytes.
+fn read_str_array(process: &Process, addr: usize) -> Result<Vec<String>, Errno> {
+    let mut strings = Vec::new();
+    if addr == 0 {
+        return Ok(strings);
+    }
+    let mut room = ARG_MAX;
+    loop {
+        let ptr_addr = addr
+            .checked_add(strings.len() * size_of::<usize>())
+            .ok_or(Errno::EFAULT)?;
+        let ptr: usize = copy_from_user(process, ptr_addr)?;
+        if ptr == 0 {
+            return Ok(strings);
+        }
+        room = room.checked_sub(size_of::<usize>()).ok_or(Errno::E2BIG)?;
+        let string = read_str(process, ptr, room, Errno::E2BIG)?;
+        room -= string.len() + 1;
+        strings.push(string);
+    }
+}
+
+/// Replaces the process's image with the executable at `path`. On success
+/// the call does not return, and the new image starts with `a0` at 0.
+pub fn sys_execve(process: &Arc<Process>, [path, argv, envp, ..]: [usize; 6]) -> SyscallResult {
+    let path = read_path(process, path)?;
+    let argv = read_str_array(process, argv)?;
+    let envp = read_str_array(process, envp)?;
+    let cwd = process.inner().cwd.clone();
+    let elf = fs::read(&cwd, &path)?;
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+    let envp: Vec<&str> = envp.iter().map(String::as_str).collect();
+    process
+        .exec(&elf, &argv, &envp)
+        .map_err(|err| Errno(err.errno()))?;
+    Ok(0)
+}
+
+/// Reaps a child that has exited and returns its PID, or 0 if none has and
+/// `WNOHANG` is given. There are no process groups, so a `pid` of 0 or
+/// below -1 matches any child, as -1 does. Stopped children are never
+/// reported, and resource usage is all zeros.
+pub fn sys_wait4(
+    process: &Arc<Process>,
+    [pid, wstatus, options, rusage, ..]: [usize; 6],
+) -> SyscallResult {
+    if options & !(WNOHANG | WUNTRACED | WCONTINUED) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    // Fail before reaping a child whose status would be lost.
+    if wstatus != 0 {
+        check_user(process, wstatus, size_of::<i32>(), true)?;
+    }
+    if rusage != 0 {
+        check_user(process, rusage, RUSAGE_SIZE, true)?;
+    }
+    let pid = match pid as isize {
+        pid if pid > 0 => Some(pid as usize),
+        _ => None,
+    };
+    let nohang = options & WNOHANG != 0;
+    match process.wait(pid, !nohang) {
+        WaitStatus::Exited(pid, status) => {
+            if wstatus != 0 {
+                copy_to_user(process, wstatus, &status)?;
+            }
+            if rusage != 0 {
+                copy_to_user(process, rusage, &[0u8; RUSAGE_SIZE])?;
+            }
+            Ok(pid)
+        }
+        WaitStatus::Running if nohang => Ok(0),
+        WaitStatus::Running => Err(Errno::ERESTARTNOINTR),
+        WaitStatus::NoChild => Err(Errno::ECHILD),
+    }
+}
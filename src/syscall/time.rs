@@ -0,0 +1,73 @@
+use super::{copy_from_user, copy_to_user, Errno, SyscallResult};
+use crate::process::Process;
+use crate::task;
+use crate::timer::{self, NANOS_PER_SEC};
+use ::alloc::sync::Arc;
+
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_MONOTONIC_RAW: usize = 4;
+const CLOCK_REALTIME_COARSE: usize = 5;
+const CLOCK_MONOTONIC_COARSE: usize = 6;
+const CLOCK_BOOTTIME: usize = 7;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TimeSpec {
+    sec: i64,
+    nsec: i64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TimeVal {
+    sec: i64,
+    usec: i64,
+}
+
+pub fn sys_gettimeofday(process: &Arc<Process>, [tv, ..]: [usize; 6]) -> SyscallResult {
+    if tv != 0 {
+        // There is no real-time clock yet, so the epoch is boot.
+        let nanos = timer::nanos();
+        let time = TimeVal {
+            sec: (nanos / NANOS_PER_SEC) as i64,
+            usec: (nanos % NANOS_PER_SEC / 1000) as i64,
+        };
+        copy_to_user(process, tv, &time)?;
+    }
+    Ok(0)
+}
+
+pub fn sys_clock_gettime(process: &Arc<Process>, [clock, tp, ..]: [usize; 6]) -> SyscallResult {
+    match clock {
+        // All the same until there is a real-time clock.
+        CLOCK_REALTIME
+        | CLOCK_MONOTONIC
+        | CLOCK_MONOTONIC_RAW
+        | CLOCK_REALTIME_COARSE
+        | CLOCK_MONOTONIC_COARSE
+        | CLOCK_BOOTTIME => {}
+        _ => return Err(Errno::EINVAL),
+    }
+    let nanos = timer::nanos();
+    let time = TimeSpec {
+        sec: (nanos / NANOS_PER_SEC) as i64,
+        nsec: (nanos % NANOS_PER_SEC) as i64,
+    };
+    copy_to_user(process, tp, &time)?;
+    Ok(0)
+}
+
+/// Sleeps for the requested time, letting other tasks run. Nothing
+/// interrupts a sleep, so the remaining time is never written back.
+pub fn sys_nanosleep(process: &Arc<Process>, [req, ..]: [usize; 6]) -> SyscallResult {
+    let req: TimeSpec = copy_from_user(process, req)?;
+    if req.sec < 0 || !(0..NANOS_PER_SEC as i64).contains(&req.nsec) {
+        return Err(Errno::EINVAL);
+    }
+    let nanos = (req.sec as u64)
+        .saturating_mul(NANOS_PER_SEC)
+        .saturating_add(req.nsec as u64);
+    task::sleep_until(timer::ticks().saturating_add(timer::nanos_to_ticks(nanos)));
+    Ok(0)
+}
@@ -0,0 +1,23 @@
+/// Kernel registers of a task that is switched out: what `__switch` saves
+/// and restores. The rest are caller-saved, so the code calling it has
+/// saved them already.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct TaskContext {
+    ra: usize,
+    sp: usize,
+    /// Callee-saved registers `s0` to `s11`.
+    s: [usize; 12],
+}
+
+impl TaskContext {
+    /// A context that, switched to, starts running `entry` on the stack
+    /// ending at `sp`.
+    pub fn new(entry: usize, sp: usize) -> Self {
+        Self {
+            ra: entry,
+            sp,
+            s: [0; 12],
+        }
+    }
+}
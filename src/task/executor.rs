@@ -0,0 +1,189 @@
+//! Futures in the kernel: an executor on each hart for those started with
+//! [`spawn`], and [`block_on`] and [`poll_in_place`] to wait for one.
+//!
+//! Each hart's executor is a kernel thread bound to the hart, which polls
+//! the futures woken on it in turn and blocks while there are none.
+//! Waking a future only queues it and wakes the thread, so wakers may be
+//! called from interrupt handlers on any hart.
+
+use super::{block_current, current, Task};
+use crate::hart::{self, MAX_HARTS};
+use crate::trap;
+use ::alloc::{boxed::Box, collections::VecDeque, sync::Arc, task::Wake, vec::Vec};
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+/// A future spawned on an executor.
+struct AsyncTask {
+    /// The future, until it completes.
+    future: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+    /// Hart whose executor polls it.
+    hart: usize,
+    /// Whether it is in its executor's queue, so that waking it again
+    /// before it is polled does not queue it twice.
+    queued: AtomicBool,
+}
+
+impl Wake for AsyncTask {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let mut executor = EXECUTORS[self.hart].lock();
+        executor.queue.push_back(self.clone());
+        if let Some(thread) = &executor.thread {
+            super::wake(thread);
+        }
+    }
+}
+
+struct Executor {
+    /// Futures woken and waiting to be polled.
+    queue: VecDeque<Arc<AsyncTask>>,
+    /// The kernel thread polling them, once the hart has started it.
+    thread: Option<Arc<Task>>,
+}
+
+lazy_static! {
+    static ref EXECUTORS: Vec<Mutex<Executor>> = (0..MAX_HARTS)
+        .map(|_| {
+            Mutex::new(Executor {
+                queue: VecDeque::new(),
+                thread: None,
+            })
+        })
+        .collect();
+}
+
+/// Starts the executor thread of this hart.
+pub(super) fn init_hart() {
+    let hart = hart::id();
+    let thread = super::spawn_kernel_thread_on(hart, move || run(hart));
+    EXECUTORS[hart].lock().thread = Some(thread);
+}
+
+/// Polls the futures woken on `hart`, blocking while there are none.
+fn run(hart: usize) -> ! {
+    loop {
+        let mut executor = EXECUTORS[hart].lock();
+        let Some(task) = executor.queue.pop_front() else {
+            block_current(executor);
+            continue;
+        };
+        drop(executor);
+        // A wakeup from here on queues it again.
+        task.queued.store(false, Ordering::Release);
+        let waker = Waker::from(task.clone());
+        {
+            let mut future = task.future.lock();
+            if let Some(running) = future.as_mut() {
+                if running
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_ready()
+                {
+                    *future = None;
+                }
+            }
+        }
+        super::cond_resched();
+    }
+}
+
+/// Runs `future` on this hart's executor, in the background.
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    let task = Arc::new(AsyncTask {
+        future: Mutex::new(Some(Box::pin(future))),
+        hart: hart::id(),
+        queued: AtomicBool::new(false),
+    });
+    task.wake();
+}
+
+/// Waker of a future run by [`block_on`] or [`poll_in_place`].
+struct Signal {
+    /// The task to wake, or `None` if the hart waits for interrupts.
+    task: Option<Arc<Task>>,
+    /// Hart to interrupt if `task` is `None`.
+    hart: usize,
+    /// Whether the future was woken since it was last polled.
+    woken: Mutex<bool>,
+}
+
+impl Signal {
+    fn new(task: Option<Arc<Task>>) -> Arc<Self> {
+        Arc::new(Signal {
+            task,
+            hart: hart::id(),
+            woken: Mutex::new(false),
+        })
+    }
+
+    /// Returns once the future is woken.
+    fn wait(&self) {
+        let mut woken = self.woken.lock();
+        if *woken {
+            *woken = false;
+            return;
+        }
+        if self.task.is_some() {
+            block_current(woken);
+        } else {
+            drop(woken);
+            // A wakeup from another hart from here on comes with an IPI,
+            // which stays pending until the hart waits for it.
+            trap::wait_for_interrupt();
+        }
+    }
+}
+
+impl Wake for Signal {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        *self.woken.lock() = true;
+        match &self.task {
+            Some(task) => super::wake(task),
+            None if self.hart != hart::id() => hart::send_ipi(self.hart),
+            None => {}
+        }
+    }
+}
+
+/// Polls `future` until it is ready, waiting with `signal` in between.
+fn run_on<F: Future>(future: F, signal: Arc<Signal>) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(signal.clone());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        signal.wait();
+    }
+}
+
+/// Runs `future` to completion, blocking the current task while it waits
+/// so that others run in the meantime. The task may be switched out, so
+/// the caller must not hold a spin lock another task may take. Outside of
+/// a task, during boot or shutdown, waits like [`poll_in_place`].
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    run_on(future, Signal::new(current()))
+}
+
+/// Runs `future` to completion without giving up the hart, waiting for an
+/// interrupt each time it is pending. For callers that may hold spin locks,
+/// such as the filesystems around block I/O.
+pub fn poll_in_place<F: Future>(future: F) -> F::Output {
+    run_on(future, Signal::new(None))
+}
@@ -0,0 +1,232 @@
+//! Tasks: threads of kernel execution, each with a stack of its own, that
+//! harts switch between.
+//!
+//! Every process runs on a task, which enters U-mode and handles the traps
+//! the process takes, and the kernel starts tasks of its own for background
+//! work with [`spawn_kernel_thread`]. Each hart has a queue of tasks ready
+//! to run on it, ordered by the policy in [`scheduler`], and runs them from
+//! its idle loop in [`run_until`]: a task switches back to the idle loop
+//! when it yields, blocks or exits, and the idle loop picks the next one.
+//!
+//! A task runs for a time slice, `timeslice=<ms>` on the kernel command
+//! line, before the timer interrupt marks it to give up the hart. Kernel
+//! code runs with interrupts disabled and is never switched out at an
+//! arbitrary point, so a process's task gives up the hart when the process
+//! traps, and a kernel thread at the points where it calls
+//! [`cond_resched`].
+//!
+//! Drivers and other kernel code may also be written as futures, which
+//! [`spawn`] runs on the hart's executor, and which a task waits for with
+//! [`block_on`]. Sleeps, [`sleep`] and [`sleep_until`], are futures woken by
+//! the timer interrupt.
+
+mod context;
+mod executor;
+mod processor;
+mod scheduler;
+mod sleep;
+
+pub use executor::{block_on, poll_in_place, spawn};
+pub use processor::{cond_resched, current, handle_timer, init, init_hart, run_until};
+pub use scheduler::SchedStats;
+pub use sleep::{sleep, Sleep};
+
+use crate::hart;
+use crate::mm::{frame_alloc_contiguous, FrameTracker, PAGE_SIZE};
+use ::alloc::{boxed::Box, sync::Arc};
+use context::TaskContext;
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use scheduler::SchedEntity;
+use spin::{Mutex, MutexGuard};
+
+global_asm!(include_str!("switch.S"));
+
+extern "C" {
+    fn __switch(current: *mut TaskContext, next: *const TaskContext);
+}
+
+/// Size of a task's kernel stack, the same as the boot stack.
+const KERNEL_STACK_PAGES: usize = 16;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TaskState {
+    /// Waiting in a run queue.
+    Ready,
+    Running,
+    /// Waiting for [`wake`].
+    Blocked,
+    Exited,
+}
+
+/// Memory a task's kernel code runs on. The kernel address space maps all
+/// memory at its physical address.
+struct KernelStack(FrameTracker);
+
+impl KernelStack {
+    fn new() -> Self {
+        let frames =
+            frame_alloc_contiguous(KERNEL_STACK_PAGES).expect("no memory for a kernel stack");
+        KernelStack(frames)
+    }
+
+    fn top(&self) -> usize {
+        self.0.pa().0 + KERNEL_STACK_PAGES * PAGE_SIZE
+    }
+}
+
+pub struct Task {
+    /// ID of the task, unique since boot.
+    tid: usize,
+    kernel_stack: KernelStack,
+    inner: Mutex<TaskInner>,
+    /// Apart from `inner`, so that a hart can look at it with its run queue
+    /// locked.
+    sched: Mutex<SchedEntity>,
+}
+
+pub struct TaskInner {
+    pub state: TaskState,
+    /// Whether a hart runs the task, or is still switching away from it,
+    /// so that its context is not complete yet and waking it must leave
+    /// queueing it to that hart.
+    on_cpu: bool,
+    /// The hart the task last ran on, whose run queue it goes back to if
+    /// that hart is not busier than others.
+    hart: usize,
+    context: TaskContext,
+    /// What the task runs, until it starts.
+    entry: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Task {
+    pub fn inner(&self) -> MutexGuard<'_, TaskInner> {
+        self.inner.lock()
+    }
+
+    fn sched(&self) -> MutexGuard<'_, SchedEntity> {
+        self.sched.lock()
+    }
+
+    pub fn nice(&self) -> i32 {
+        self.sched().nice
+    }
+
+    /// Sets the nice value of the task, which takes effect the next time it
+    /// is queued.
+    pub fn set_nice(&self, nice: i32) {
+        self.sched().nice = nice.clamp(scheduler::NICE_MIN, scheduler::NICE_MAX);
+    }
+
+    pub fn stats(&self) -> SchedStats {
+        self.sched().stats
+    }
+
+    /// Harts the task may run on, one bit per hart ID.
+    pub fn affinity(&self) -> usize {
+        self.sched().affinity
+    }
+
+    /// Restricts the task to the harts in `mask`, one bit per hart ID. A
+    /// task running on another hart moves the next time it may give up the
+    /// hart, and a queued one when its turn comes.
+    pub fn set_affinity(&self, mask: usize) {
+        self.sched().affinity = mask;
+    }
+
+    fn may_run_on(&self, hart: usize) -> bool {
+        self.sched().affinity & (1 << hart) != 0
+    }
+}
+
+/// Where every task starts, on its own stack.
+extern "C" fn task_entry() -> ! {
+    let entry = current()
+        .expect("task started outside a task")
+        .inner()
+        .entry
+        .take()
+        .expect("task started twice");
+    entry();
+    exit_current();
+}
+
+/// Starts a task running `entry` in the kernel, queued on an idle hart if
+/// there is one and on this hart otherwise, and returns it. Processes run
+/// on such tasks too, entering U-mode from them. The task has the nice
+/// value and affinity of the one starting it, if any.
+pub fn spawn_kernel_thread(entry: impl FnOnce() + Send + 'static) -> Arc<Task> {
+    let sched = match current() {
+        Some(task) => {
+            let sched = task.sched();
+            SchedEntity::new(sched.nice, sched.affinity)
+        }
+        None => SchedEntity::new(scheduler::NICE_DEFAULT, usize::MAX),
+    };
+    new_task(entry, sched)
+}
+
+/// Starts a task running `entry` in the kernel that only runs on `hart`.
+fn spawn_kernel_thread_on(hart: usize, entry: impl FnOnce() + Send + 'static) -> Arc<Task> {
+    new_task(entry, SchedEntity::new(scheduler::NICE_DEFAULT, 1 << hart))
+}
+
+fn new_task(entry: impl FnOnce() + Send + 'static, sched: SchedEntity) -> Arc<Task> {
+    static NEXT_TID: AtomicUsize = AtomicUsize::new(1);
+    let task = Arc::new(Task {
+        tid: NEXT_TID.fetch_add(1, Ordering::Relaxed),
+        kernel_stack: KernelStack::new(),
+        inner: Mutex::new(TaskInner {
+            state: TaskState::Ready,
+            on_cpu: false,
+            hart: hart::id(),
+            context: TaskContext::default(),
+            entry: Some(Box::new(entry)),
+        }),
+        sched: Mutex::new(sched),
+    });
+    task.inner().context = TaskContext::new(task_entry as usize, task.kernel_stack.top());
+    processor::push_ready(task.clone());
+    task
+}
+
+/// Moves the current task to the back of its run queue.
+pub fn yield_now() {
+    current().expect("yielding outside a task").inner().state = TaskState::Ready;
+    processor::switch_out();
+}
+
+/// Blocks the current task until it is woken with [`wake`]. `guard` is
+/// dropped once the task counts as blocked, so a wakeup sent by someone
+/// that takes the same lock after checking for the task is not lost.
+pub fn block_current<G>(guard: G) {
+    current().expect("blocking outside a task").inner().state = TaskState::Blocked;
+    drop(guard);
+    processor::switch_out();
+}
+
+/// Ends the current task. Its stack is freed once the hart is off it.
+pub fn exit_current() -> ! {
+    current().expect("exiting outside a task").inner().state = TaskState::Exited;
+    processor::switch_out();
+    unreachable!("exited task resumed");
+}
+
+/// Queues `task` to run again if it is blocked.
+pub fn wake(task: &Arc<Task>) {
+    let mut inner = task.inner();
+    if inner.state != TaskState::Blocked {
+        return;
+    }
+    inner.state = TaskState::Ready;
+    // A hart still switching away from it queues it when done.
+    if !inner.on_cpu {
+        drop(inner);
+        processor::push_ready(task.clone());
+    }
+}
+
+/// Blocks the current task until the time counter reaches `deadline`.
+pub fn sleep_until(deadline: usize) {
+    block_on(Sleep::until(deadline));
+}
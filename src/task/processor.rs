@@ -0,0 +1,329 @@
+//! What each hart runs: its current task, and the queue of those ready to
+//! run on it next.
+//!
+//! Tasks spread across harts in three ways. A task that starts or wakes up
+//! is queued on an idle hart it may run on if there is one, and on the
+//! hart it last ran on otherwise. A hart that runs out of tasks takes one
+//! queued on the busiest other hart. And every few time slices each hart
+//! takes tasks from the busiest one, if that has at least two more.
+
+use super::scheduler::{new_scheduler, Scheduler, POLICY};
+use super::sleep::{next_wakeup, wake_sleepers};
+use super::{__switch, Task, TaskContext, TaskState};
+use crate::devices::device_tree as dt;
+use crate::hart::{self, MAX_HARTS};
+use crate::{sbi, timer, trap};
+use ::alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use riscv::register::sie;
+use spin::{Mutex, MutexGuard};
+
+/// Time slice when the command line does not set one, in milliseconds.
+const DEFAULT_TIME_SLICE_MS: usize = 10;
+
+/// Time slices between two times a hart balances its load with the
+/// others.
+const BALANCE_INTERVAL_SLICES: usize = 4;
+
+/// Length of a time slice, in ticks of the time counter.
+static TIME_SLICE: AtomicUsize = AtomicUsize::new(0);
+
+struct Processor {
+    current: Option<Arc<Task>>,
+    /// Context of the idle loop, which runs on the hart's boot stack
+    /// between tasks.
+    idle: TaskContext,
+    ready: Box<dyn Scheduler>,
+    /// When the current task's time slice is up.
+    slice_end: usize,
+    /// Whether the current task has used up its time slice.
+    need_resched: bool,
+    /// Whether the hart waits for an interrupt in its idle loop, so that a
+    /// task queued on it must wake it up.
+    idling: bool,
+    /// When the hart next balances its load with the others.
+    next_balance: usize,
+}
+
+impl Processor {
+    /// Number of tasks running or ready to run on the hart.
+    fn load(&self) -> usize {
+        self.ready.len() + self.current.is_some() as usize
+    }
+}
+
+lazy_static! {
+    static ref PROCESSORS: Vec<Mutex<Processor>> = (0..MAX_HARTS)
+        .map(|_| {
+            Mutex::new(Processor {
+                current: None,
+                idle: TaskContext::default(),
+                ready: new_scheduler(),
+                slice_end: 0,
+                need_resched: false,
+                idling: false,
+                next_balance: 0,
+            })
+        })
+        .collect();
+}
+
+fn processor() -> MutexGuard<'static, Processor> {
+    PROCESSORS[hart::id()].lock()
+}
+
+/// Reads the scheduler and time slice from the command line, and readies
+/// the boot hart to run tasks.
+pub fn init() {
+    let ms = dt::bootarg("timeslice")
+        .and_then(|ms| ms.parse().ok())
+        .filter(|&ms| ms > 0)
+        .unwrap_or(DEFAULT_TIME_SLICE_MS);
+    let ticks = timer::nanos_to_ticks(ms as u64 * timer::NANOS_PER_SEC / 1000);
+    TIME_SLICE.store(ticks, Ordering::Relaxed);
+    log!("task: {} scheduler, time slice {} ms", POLICY.name(), ms);
+    init_hart();
+}
+
+/// Turns on the timer interrupt, which ends time slices and sleeps, marks
+/// this hart as ready to run tasks, and starts its executor.
+pub fn init_hart() {
+    processor().next_balance = timer::ticks() + balance_interval();
+    unsafe { sie::set_stimer() };
+    hart::set_online();
+    super::executor::init_hart();
+}
+
+/// Length of a time slice, in ticks of the time counter.
+pub(super) fn time_slice() -> usize {
+    TIME_SLICE.load(Ordering::Relaxed)
+}
+
+fn balance_interval() -> usize {
+    time_slice() * BALANCE_INTERVAL_SLICES
+}
+
+/// The task running on this hart, if any.
+pub fn current() -> Option<Arc<Task>> {
+    processor().current.clone()
+}
+
+/// The hart to queue a task that last ran on `last` on: `last` if it is
+/// idle, or else another idle one, or else `last` again, of those in
+/// `affinity`. If `last` is not in it, the least busy of those instead.
+fn select_hart(last: usize, affinity: usize) -> usize {
+    let loads: Vec<(usize, usize)> = hart::online()
+        .filter(|hart| affinity & (1 << hart) != 0)
+        .map(|hart| (PROCESSORS[hart].lock().load(), hart))
+        .collect();
+    if loads.contains(&(0, last)) {
+        return last;
+    }
+    if let Some(&(_, idle)) = loads.iter().find(|&&(load, _)| load == 0) {
+        return idle;
+    }
+    if loads.iter().any(|&(_, hart)| hart == last) {
+        return last;
+    }
+    loads.iter().min().map_or(last, |&(_, hart)| hart)
+}
+
+/// Queues `task` on `hart`, waking the hart up if it is idle.
+fn enqueue(hart: usize, task: Arc<Task>) {
+    let mut processor = PROCESSORS[hart].lock();
+    processor.ready.push(task);
+    if processor.idling && hart != hart::id() {
+        hart::send_ipi(hart);
+    }
+}
+
+/// Queues `task`, which is ready to run, on the hart it fits best.
+pub(super) fn push_ready(task: Arc<Task>) {
+    let last = task.inner().hart;
+    let hart = select_hart(last, task.affinity());
+    task.sched().stats.ready_since = timer::ticks();
+    enqueue(hart, task);
+}
+
+/// Takes a task queued on the busiest other hart that may run on this one,
+/// if that hart has at least `margin` more tasks than this one.
+fn steal(margin: usize) -> Option<Arc<Task>> {
+    let me = hart::id();
+    let load = processor().load();
+    let (busiest_load, busiest) = hart::online()
+        .filter(|&hart| hart != me)
+        .map(|hart| (PROCESSORS[hart].lock().load(), hart))
+        .max()?;
+    if busiest_load < load + margin {
+        return None;
+    }
+    PROCESSORS[busiest]
+        .lock()
+        .ready
+        .steal(&|task| task.may_run_on(me))
+}
+
+/// Takes tasks from the busiest other hart until it has at most one more
+/// than this one.
+fn balance() {
+    while let Some(task) = steal(2) {
+        enqueue(hart::id(), task);
+    }
+}
+
+/// Switches from the current task back to the idle loop, which takes care
+/// of the task according to the state it left it in. Returns when the task
+/// is switched back to, possibly on another hart.
+pub(super) fn switch_out() {
+    let task = current().expect("switching out of no task");
+    let context = {
+        let mut inner = task.inner();
+        &mut inner.context as *mut TaskContext
+    };
+    let idle = {
+        let processor = processor();
+        &processor.idle as *const TaskContext
+    };
+    // The hart keeps the task alive until it is off its stack.
+    drop(task);
+    unsafe { __switch(context, idle) };
+}
+
+/// Runs `task` until it switches back, then charges it for the time it ran
+/// and requeues it if it is ready.
+fn run_task(task: Arc<Task>) {
+    let start = timer::ticks();
+    {
+        let stats = &mut task.sched().stats;
+        stats.wait_time += start - stats.ready_since;
+    }
+    let context = {
+        let mut inner = task.inner();
+        inner.state = TaskState::Running;
+        inner.on_cpu = true;
+        inner.hart = hart::id();
+        &inner.context as *const TaskContext
+    };
+    let idle = {
+        let mut processor = processor();
+        processor.current = Some(task);
+        processor.slice_end = start + time_slice();
+        processor.need_resched = false;
+        &mut processor.idle as *mut TaskContext
+    };
+    arm_timer();
+    unsafe { __switch(idle, context) };
+    let ran = timer::ticks() - start;
+    let task = {
+        let mut processor = processor();
+        let task = processor
+            .current
+            .take()
+            .expect("switched back without a task");
+        processor.ready.charge(&task, ran);
+        task
+    };
+    let mut inner = task.inner();
+    inner.on_cpu = false;
+    let ready = inner.state == TaskState::Ready;
+    {
+        let stats = &mut task.sched().stats;
+        stats.runtime += ran;
+        if ready {
+            stats.involuntary_switches += 1;
+        } else {
+            stats.voluntary_switches += 1;
+        }
+    }
+    // Blocked tasks wait for a wakeup, and exited ones are freed here.
+    if ready {
+        drop(inner);
+        push_ready(task);
+    }
+}
+
+/// Runs tasks on this hart until none is ready and `done` holds, taking
+/// tasks from other harts when it runs out, and sleeping while there is
+/// nothing to run anywhere.
+pub fn run_until(done: impl Fn() -> bool) {
+    loop {
+        let next = processor().ready.pop();
+        let next = next.or_else(|| steal(1));
+        match next {
+            // Its affinity changed while it was queued.
+            Some(task) if !task.may_run_on(hart::id()) => push_ready(task),
+            Some(task) => run_task(task),
+            None if done() => return,
+            None => wait_idle(),
+        }
+    }
+}
+
+/// Sleeps until an interrupt arrives, unless a task is queued on the hart
+/// in the meantime.
+fn wait_idle() {
+    {
+        let mut processor = processor();
+        if processor.ready.len() > 0 {
+            return;
+        }
+        // From here on a task queued on the hart comes with an IPI, which
+        // stays pending until the hart waits for it.
+        processor.idling = true;
+    }
+    arm_timer();
+    trap::wait_for_interrupt();
+    processor().idling = false;
+}
+
+/// Sets the timer for the next sleep to end, the current task's time slice
+/// to end, or the hart to balance its load, whichever comes first.
+pub(super) fn arm_timer() {
+    let wakeup = next_wakeup().unwrap_or(usize::MAX);
+    let processor = processor();
+    let deadline = match processor.current {
+        Some(_) if !processor.need_resched => wakeup.min(processor.slice_end),
+        _ => wakeup,
+    };
+    sbi::set_timer(deadline.min(processor.next_balance));
+}
+
+/// Handles the timer interrupt: wakes the sleeps that are due,
+/// marks the current task to give up the hart if its time is up, and
+/// balances the load if it is time to.
+pub fn handle_timer() {
+    let now = timer::ticks();
+    wake_sleepers(now);
+    let balance_due = {
+        let mut processor = processor();
+        if processor.current.is_some() && now >= processor.slice_end {
+            processor.need_resched = true;
+        }
+        let due = now >= processor.next_balance;
+        if due {
+            processor.next_balance = now + balance_interval();
+        }
+        due
+    };
+    if balance_due {
+        balance();
+    }
+    arm_timer();
+}
+
+/// Whether the current task has used up its time slice.
+pub fn need_resched() -> bool {
+    processor().need_resched
+}
+
+/// Yields if the current task has used up its time slice, or may no longer
+/// run on this hart. Kernel code is never switched out otherwise, so
+/// long-running kernel threads call this between steps of their work.
+pub fn cond_resched() {
+    let task = current().expect("rescheduling outside a task");
+    if need_resched() || !task.may_run_on(hart::id()) {
+        drop(task);
+        super::yield_now();
+    }
+}
@@ -0,0 +1,45 @@
+use super::{weight, Scheduler, Timeline, NICE_0_WEIGHT};
+use crate::task::{processor, Task};
+use ::alloc::sync::Arc;
+
+/// Runs the task that has had the least virtual runtime: the time it ran,
+/// scaled by the weight of nice 0 over its own. Over time each task runs
+/// for a share of the time in proportion to its weight.
+pub struct Fair {
+    timeline: Timeline,
+}
+
+impl Fair {
+    pub fn new() -> Self {
+        Fair {
+            timeline: Timeline::new(),
+        }
+    }
+}
+
+impl Scheduler for Fair {
+    fn push(&mut self, task: Arc<Task>) {
+        // A task that slept goes at most a time slice behind the others, to
+        // run soon after it wakes without making up for all the time it
+        // slept.
+        let lag = processor::time_slice() as u64;
+        self.timeline.push(task, lag);
+    }
+
+    fn pop(&mut self) -> Option<Arc<Task>> {
+        self.timeline.pop()
+    }
+
+    fn charge(&mut self, task: &Task, ticks: usize) {
+        let mut sched = task.sched();
+        sched.vruntime += ticks as u64 * NICE_0_WEIGHT / weight(sched.nice);
+    }
+
+    fn steal(&mut self, can_run: &dyn Fn(&Task) -> bool) -> Option<Arc<Task>> {
+        self.timeline.steal(can_run)
+    }
+
+    fn len(&self) -> usize {
+        self.timeline.len()
+    }
+}
@@ -0,0 +1,208 @@
+//! Policies for choosing which ready task a hart runs next.
+//!
+//! Each hart queues its ready tasks in a [`Scheduler`] of the policy given
+//! as `sched=` on the kernel command line:
+//!
+//! - `rr`, the default: round robin, each task in turn.
+//! - `stride`: stride scheduling, where each run moves a task on by a
+//!   stride inversely proportional to its weight, and the task that is
+//!   furthest behind runs next.
+//! - `fair`: a task is charged for the time it runs, scaled down by its
+//!   weight, and the one charged least so far runs next.
+//!
+//! The weight of a task comes from its nice value as on Linux, each step
+//! of nice changing it by about a quarter. Virtual time is kept per hart,
+//! so a task moved to another hart keeps how far ahead of that hart's
+//! tasks it was.
+
+mod fair;
+mod round_robin;
+mod stride;
+
+use super::Task;
+use crate::devices::device_tree as dt;
+use ::alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
+use fair::Fair;
+use lazy_static::lazy_static;
+use round_robin::RoundRobin;
+use stride::Stride;
+
+/// A queue of tasks ready to run on one hart.
+pub trait Scheduler: Send {
+    /// Queues `task`: new, woken, or back after running.
+    fn push(&mut self, task: Arc<Task>);
+
+    /// Takes the task to run next.
+    fn pop(&mut self) -> Option<Arc<Task>>;
+
+    /// Charges `task`, taken with [`pop`](Self::pop), for the `ticks` it
+    /// ran before it is queued again.
+    fn charge(&mut self, _task: &Task, _ticks: usize) {}
+
+    /// Takes a queued task for which `can_run` holds, to move to another
+    /// hart: the one that would run last.
+    fn steal(&mut self, can_run: &dyn Fn(&Task) -> bool) -> Option<Arc<Task>>;
+
+    /// Number of tasks queued.
+    fn len(&self) -> usize;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Policy {
+    RoundRobin,
+    Stride,
+    Fair,
+}
+
+impl Policy {
+    /// The policy named on the kernel command line.
+    fn from_cmdline() -> Self {
+        match dt::bootarg("sched") {
+            None | Some("rr") => Policy::RoundRobin,
+            Some("stride") => Policy::Stride,
+            Some("fair") => Policy::Fair,
+            Some(name) => {
+                log!("task: unknown scheduler {:?}, using round robin", name);
+                Policy::RoundRobin
+            }
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Policy::RoundRobin => "round robin",
+            Policy::Stride => "stride",
+            Policy::Fair => "fair",
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref POLICY: Policy = Policy::from_cmdline();
+}
+
+/// An empty queue of the policy on the command line.
+pub fn new_scheduler() -> Box<dyn Scheduler> {
+    match *POLICY {
+        Policy::RoundRobin => Box::new(RoundRobin::new()),
+        Policy::Stride => Box::new(Stride::new()),
+        Policy::Fair => Box::new(Fair::new()),
+    }
+}
+
+/// Nice value of tasks that do not set one, and the lowest and highest
+/// there are.
+pub const NICE_DEFAULT: i32 = 0;
+pub const NICE_MIN: i32 = -20;
+pub const NICE_MAX: i32 = 19;
+
+/// Weight of each nice value from [`NICE_MIN`] to [`NICE_MAX`], the same
+/// as Linux's.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// Weight of a task with nice value 0.
+const NICE_0_WEIGHT: u64 = NICE_TO_WEIGHT[(NICE_DEFAULT - NICE_MIN) as usize];
+
+fn weight(nice: i32) -> u64 {
+    NICE_TO_WEIGHT[(nice.clamp(NICE_MIN, NICE_MAX) - NICE_MIN) as usize]
+}
+
+/// How a task stands with the scheduler, and what it has been through.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SchedEntity {
+    pub nice: i32,
+    /// Harts the task may run on, one bit per hart ID.
+    pub affinity: usize,
+    /// How far the task has got in the virtual time of [`Stride`] and
+    /// [`Fair`], which orders them in the queue. Only changes while the
+    /// task is not queued.
+    vruntime: u64,
+    /// Whether the task was taken from another hart's queue, and
+    /// `vruntime` is relative to the last task taken there.
+    migrated: bool,
+    pub stats: SchedStats,
+}
+
+impl SchedEntity {
+    pub fn new(nice: i32, affinity: usize) -> Self {
+        SchedEntity {
+            nice,
+            affinity,
+            ..SchedEntity::default()
+        }
+    }
+}
+
+/// Accounting of a task's time, in ticks of the time counter.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SchedStats {
+    /// Time spent running.
+    pub runtime: usize,
+    /// Time spent queued, ready to run.
+    pub wait_time: usize,
+    /// When the task was last queued.
+    pub ready_since: usize,
+    /// Times the task gave up the hart by blocking or exiting.
+    pub voluntary_switches: usize,
+    /// Times the task gave up the hart while still ready to run: when its
+    /// time slice was up, or when it yielded.
+    pub involuntary_switches: usize,
+}
+
+/// Tasks in order of virtual time, for [`Stride`] and [`Fair`].
+struct Timeline {
+    /// Tasks by virtual time, then ID.
+    tasks: BTreeMap<(u64, usize), Arc<Task>>,
+    /// Virtual time of the last task taken, which only moves forward.
+    min_vruntime: u64,
+}
+
+impl Timeline {
+    fn new() -> Self {
+        Timeline {
+            tasks: BTreeMap::new(),
+            min_vruntime: 0,
+        }
+    }
+
+    /// Queues `task`, at most `lag` behind the last task taken, so that a
+    /// task new or back from blocking does not take over the hart to
+    /// catch up.
+    fn push(&mut self, task: Arc<Task>, lag: u64) {
+        let vruntime = {
+            let mut sched = task.sched();
+            if sched.migrated {
+                sched.vruntime += self.min_vruntime;
+                sched.migrated = false;
+            }
+            sched.vruntime = sched.vruntime.max(self.min_vruntime.saturating_sub(lag));
+            sched.vruntime
+        };
+        self.tasks.insert((vruntime, task.tid), task);
+    }
+
+    fn pop(&mut self) -> Option<Arc<Task>> {
+        let ((vruntime, _), task) = self.tasks.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        Some(task)
+    }
+
+    fn steal(&mut self, can_run: &dyn Fn(&Task) -> bool) -> Option<Arc<Task>> {
+        let key = *self.tasks.iter().rev().find(|(_, task)| can_run(task))?.0;
+        let task = self.tasks.remove(&key)?;
+        {
+            let mut sched = task.sched();
+            sched.vruntime = sched.vruntime.saturating_sub(self.min_vruntime);
+            sched.migrated = true;
+        }
+        Some(task)
+    }
+
+    fn len(&self) -> usize {
+        self.tasks.len()
+    }
+}
@@ -0,0 +1,35 @@
+use super::Scheduler;
+use crate::task::Task;
+use ::alloc::{collections::VecDeque, sync::Arc};
+
+/// Runs tasks in the order they are queued, ignoring their weights.
+pub struct RoundRobin {
+    tasks: VecDeque<Arc<Task>>,
+}
+
+impl RoundRobin {
+    pub fn new() -> Self {
+        RoundRobin {
+            tasks: VecDeque::new(),
+        }
+    }
+}
+
+impl Scheduler for RoundRobin {
+    fn push(&mut self, task: Arc<Task>) {
+        self.tasks.push_back(task);
+    }
+
+    fn pop(&mut self) -> Option<Arc<Task>> {
+        self.tasks.pop_front()
+    }
+
+    fn steal(&mut self, can_run: &dyn Fn(&Task) -> bool) -> Option<Arc<Task>> {
+        let index = self.tasks.iter().rposition(|task| can_run(task))?;
+        self.tasks.remove(index)
+    }
+
+    fn len(&self) -> usize {
+        self.tasks.len()
+    }
+}
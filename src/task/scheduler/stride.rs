@@ -0,0 +1,47 @@
+use super::{weight, Scheduler, Timeline};
+use crate::task::Task;
+use ::alloc::sync::Arc;
+
+/// Stride of a task of weight 1. Every stride is this divided by the
+/// task's weight, so the heaviest task moves on by about 48000.
+const BIG_STRIDE: u64 = 1 << 32;
+
+/// Runs the task with the lowest pass, and moves it on by its stride each
+/// time it runs, however long for. Over time each task runs a number of
+/// times in proportion to its weight.
+pub struct Stride {
+    timeline: Timeline,
+}
+
+impl Stride {
+    pub fn new() -> Self {
+        Stride {
+            timeline: Timeline::new(),
+        }
+    }
+}
+
+impl Scheduler for Stride {
+    fn push(&mut self, task: Arc<Task>) {
+        // A task is never behind the others, or it would run until it
+        // caught up.
+        self.timeline.push(task, 0);
+    }
+
+    fn pop(&mut self) -> Option<Arc<Task>> {
+        let task = self.timeline.pop()?;
+        {
+            let mut sched = task.sched();
+            sched.vruntime += BIG_STRIDE / weight(sched.nice);
+        }
+        Some(task)
+    }
+
+    fn steal(&mut self, can_run: &dyn Fn(&Task) -> bool) -> Option<Arc<Task>> {
+        self.timeline.steal(can_run)
+    }
+
+    fn len(&self) -> usize {
+        self.timeline.len()
+    }
+}
@@ -0,0 +1,101 @@
+//! Timer futures: [`Sleep`] is ready once the time counter reaches its
+//! deadline, and its waker is called from the timer interrupt.
+
+use super::processor;
+use crate::timer;
+use ::alloc::{collections::BTreeMap, vec::Vec};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+lazy_static! {
+    /// Wakers of pending sleeps by deadline and ID.
+    static ref SLEEPERS: Mutex<BTreeMap<(usize, usize), Waker>> = Mutex::new(BTreeMap::new());
+}
+
+/// A future that is ready once the time counter reaches a deadline.
+pub struct Sleep {
+    deadline: usize,
+    id: usize,
+    /// Whether the sleep has a waker in [`SLEEPERS`].
+    registered: bool,
+}
+
+impl Sleep {
+    /// Sleeps until the time counter reaches `deadline`.
+    pub fn until(deadline: usize) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        Sleep {
+            deadline,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            registered: false,
+        }
+    }
+}
+
+/// Sleeps for `duration`.
+pub fn sleep(duration: Duration) -> Sleep {
+    let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+    Sleep::until(timer::ticks().saturating_add(timer::nanos_to_ticks(nanos)))
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let key = (self.deadline, self.id);
+        let first = {
+            let mut sleepers = SLEEPERS.lock();
+            if timer::ticks() >= self.deadline {
+                sleepers.remove(&key);
+                self.registered = false;
+                return Poll::Ready(());
+            }
+            sleepers.insert(key, cx.waker().clone());
+            sleepers.first_key_value().map(|(&first, _)| first) == Some(key)
+        };
+        self.registered = true;
+        // The timer of this hart may be set for later than the new deadline.
+        if first {
+            processor::arm_timer();
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if self.registered {
+            SLEEPERS.lock().remove(&(self.deadline, self.id));
+        }
+    }
+}
+
+/// Wakes the sleeps that are due by `now`.
+pub(super) fn wake_sleepers(now: usize) {
+    let mut due = Vec::new();
+    {
+        let mut sleepers = SLEEPERS.lock();
+        while let Some(entry) = sleepers.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            due.push(entry.remove());
+        }
+    }
+    for waker in due {
+        waker.wake();
+    }
+}
+
+/// When the first pending sleep is due.
+pub(super) fn next_wakeup() -> Option<usize> {
+    SLEEPERS
+        .lock()
+        .first_key_value()
+        .map(|(&(deadline, _), _)| deadline)
+}
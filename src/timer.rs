@@ -0,0 +1,55 @@
+//! The time counter and the supervisor timer interrupt.
+//!
+//! Time counts from boot: there is no real-time clock driver yet.
+
+use crate::devices::device_tree as dt;
+use crate::{sbi, task};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use riscv::register::time;
+
+pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Used when the device tree does not give the timebase: QEMU's `virt`.
+const DEFAULT_FREQUENCY: usize = 10_000_000;
+
+/// Ticks of the time counter per second.
+pub fn frequency() -> usize {
+    static FREQUENCY: AtomicUsize = AtomicUsize::new(0);
+    match FREQUENCY.load(Ordering::Relaxed) {
+        0 => {
+            let frequency = dt::tree()
+                .root
+                .child("cpus")
+                .and_then(|cpus| cpus.prop_u32("timebase-frequency"))
+                .map_or(DEFAULT_FREQUENCY, |frequency| frequency as usize);
+            FREQUENCY.store(frequency, Ordering::Relaxed);
+            frequency
+        }
+        frequency => frequency,
+    }
+}
+
+/// Ticks since boot.
+pub fn ticks() -> usize {
+    time::read()
+}
+
+/// Nanoseconds since boot.
+pub fn nanos() -> u64 {
+    ticks_to_nanos(ticks())
+}
+
+pub fn ticks_to_nanos(ticks: usize) -> u64 {
+    (ticks as u128 * NANOS_PER_SEC as u128 / frequency() as u128) as u64
+}
+
+pub fn nanos_to_ticks(nanos: u64) -> usize {
+    (nanos as u128 * frequency() as u128).div_ceil(NANOS_PER_SEC as u128) as usize
+}
+
+/// Handles the timer interrupt by pushing the next one out of reach, which
+/// clears it, and letting the scheduler set the next one.
+pub fn handle_interrupt() {
+    sbi::set_timer(usize::MAX);
+    task::handle_timer();
+}
@@ -0,0 +1,56 @@
+/// `sstatus` bits set up for entering U-mode.
+const SSTATUS_SIE: usize = 1 << 1;
+const SSTATUS_SPIE: usize = 1 << 5;
+const SSTATUS_SPP: usize = 1 << 8;
+
+/// Registers saved on trap entry, in the order the entry code stores them.
+#[repr(C)]
+#[derive(Debug)]
+pub struct TrapContext {
+    /// General purpose registers `x0` to `x31`.
+    pub x: [usize; 32],
+    pub sstatus: usize,
+    pub sepc: usize,
+}
+
+/// State of a process's hart while it is not running, kept in the page at
+/// [`crate::mm::TRAP_CONTEXT`] of its address space.
+///
+/// The trampoline stores the user registers on a trap, then restores the
+/// kernel registers saved when the process was entered, so that the trap
+/// looks like a return from [`super::enter_user`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct UserContext {
+    pub user: TrapContext,
+    kernel_satp: usize,
+    kernel_sp: usize,
+    kernel_ra: usize,
+    /// The hart ID, which user code is free to overwrite in `tp`.
+    kernel_tp: usize,
+    /// Callee-saved registers `s0` to `s11`.
+    kernel_s: [usize; 12],
+}
+
+impl UserContext {
+    /// A context that starts U-mode at `entry` with the stack at `sp` and
+    /// interrupts enabled.
+    pub fn new(entry: usize, sp: usize) -> Self {
+        let sstatus: usize;
+        unsafe { core::arch::asm!("csrr {}, sstatus", out(reg) sstatus) };
+        let mut user = TrapContext {
+            x: [0; 32],
+            sstatus: (sstatus & !(SSTATUS_SPP | SSTATUS_SIE)) | SSTATUS_SPIE,
+            sepc: entry,
+        };
+        user.x[2] = sp;
+        Self {
+            user,
+            kernel_satp: 0,
+            kernel_sp: 0,
+            kernel_ra: 0,
+            kernel_tp: 0,
+            kernel_s: [0; 12],
+        }
+    }
+}
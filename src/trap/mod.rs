@@ -0,0 +1,83 @@
+mod context;
+
+use crate::devices::plic;
+use crate::hart;
+use crate::mm::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::timer;
+use core::arch::global_asm;
+use riscv::register::{
+    scause::{self, Interrupt, Trap},
+    sstatus, stval,
+    stvec::{self, TrapMode},
+};
+
+pub use context::{TrapContext, UserContext};
+
+global_asm!(include_str!("trap.S"));
+
+/// Points `stvec` at the S-mode trap entry.
+pub fn init() {
+    extern "C" {
+        fn __kernel_trap();
+    }
+    unsafe {
+        stvec::write(__kernel_trap as usize, TrapMode::Direct);
+    }
+}
+
+/// Address of trampoline code `f` in the trampoline page at [`TRAMPOLINE`].
+fn trampoline_alias(f: usize) -> usize {
+    extern "C" {
+        fn strampoline();
+    }
+    f - strampoline as usize + TRAMPOLINE
+}
+
+/// Runs U-mode in the address space `satp`, with the [`UserContext`] at
+/// [`TRAP_CONTEXT`] in it, until the next trap, and returns its cause.
+///
+/// The caller handles the trap with the user registers in the context and
+/// calls this again to resume.
+pub fn enter_user(satp: usize) -> Trap {
+    extern "C" {
+        fn __user_trap();
+        fn __enter_user();
+    }
+    unsafe {
+        stvec::write(trampoline_alias(__user_trap as usize), TrapMode::Direct);
+        let enter: extern "C" fn(usize, usize) =
+            core::mem::transmute(trampoline_alias(__enter_user as usize));
+        enter(TRAP_CONTEXT, satp);
+    }
+    init();
+    scause::read().cause()
+}
+
+/// Sleeps until an interrupt arrives and lets it be handled.
+///
+/// The kernel otherwise runs with interrupts disabled, so a handler never
+/// interrupts code at an arbitrary point. `wfi` wakes up on a pending
+/// interrupt even while they are disabled, so one that arrived before the
+/// call is not missed.
+pub fn wait_for_interrupt() {
+    unsafe {
+        riscv::asm::wfi();
+        sstatus::set_sie();
+        sstatus::clear_sie();
+    }
+}
+
+#[no_mangle]
+extern "C" fn kernel_trap_handler(cx: &mut TrapContext) {
+    match scause::read().cause() {
+        Trap::Interrupt(Interrupt::SupervisorExternal) => plic::handle_external(),
+        Trap::Interrupt(Interrupt::SupervisorTimer) => timer::handle_interrupt(),
+        Trap::Interrupt(Interrupt::SupervisorSoft) => hart::handle_ipi(),
+        cause => panic!(
+            "Unsupported trap {:?} in kernel, stval = {:#x}, sepc = {:#x}",
+            cause,
+            stval::read(),
+            cx.sepc
+        ),
+    }
+}
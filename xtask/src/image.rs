@@ -0,0 +1,404 @@
+//! Builds a FAT32 disk image from a host directory.
+//!
+//! FAT32 is the filesystem the kernel can both read and write. Files are laid
+//! out in contiguous clusters, directories before their contents. Symbolic
+//! links and special files have no FAT equivalent and are skipped; hard
+//! links become separate copies.
+
+use std::{collections::HashSet, convert::TryFrom, fs, io, os::unix::fs::MetadataExt, path::Path};
+
+const SECTOR: usize = 512;
+const RESERVED_SECTORS: usize = 32;
+const NUM_FATS: usize = 2;
+const FS_INFO_SECTOR: usize = 1;
+const BACKUP_BOOT_SECTOR: usize = 6;
+const ROOT_CLUSTER: u32 = 2;
+/// Fewer clusters than this and the volume would be FAT16.
+const MIN_CLUSTERS: usize = 65525;
+const END_OF_CHAIN: u32 = 0x0fff_ffff;
+
+const ENTRY_SIZE: usize = 32;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0f;
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+/// Byte offsets of the 13 UTF-16 characters in a long name entry.
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+fn error(message: String) -> io::Error {
+    io::Error::other(message)
+}
+
+struct Image {
+    data: Vec<u8>,
+    sectors_per_cluster: usize,
+    fat_sectors: usize,
+    cluster_count: usize,
+    next_cluster: u32,
+}
+
+impl Image {
+    fn new(size: usize) -> io::Result<Image> {
+        let total_sectors = size / SECTOR;
+        // Cluster sizes Microsoft recommends for FAT32 volumes of this size.
+        let sectors_per_cluster = match size {
+            s if s <= 260 << 20 => 1,
+            s if s <= 8 << 30 => 8,
+            s if s <= 16 << 30 => 16,
+            _ => 32,
+        };
+        // FAT size from the formula in the FAT specification.
+        let fat_sectors =
+            (total_sectors - RESERVED_SECTORS).div_ceil((256 * sectors_per_cluster + NUM_FATS) / 2);
+        let data_sectors = total_sectors - RESERVED_SECTORS - NUM_FATS * fat_sectors;
+        let cluster_count = data_sectors / sectors_per_cluster;
+        if cluster_count < MIN_CLUSTERS || total_sectors > u32::MAX as usize {
+            return Err(error(format!(
+                "{} MiB is outside the sizes FAT32 supports",
+                size >> 20
+            )));
+        }
+        Ok(Image {
+            data: vec![0; total_sectors * SECTOR],
+            sectors_per_cluster,
+            fat_sectors,
+            cluster_count,
+            next_cluster: ROOT_CLUSTER,
+        })
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster * SECTOR
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> usize {
+        let data_start = RESERVED_SECTORS + NUM_FATS * self.fat_sectors;
+        (data_start + (cluster as usize - 2) * self.sectors_per_cluster) * SECTOR
+    }
+
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) {
+        for fat in 0..NUM_FATS {
+            let offset =
+                (RESERVED_SECTORS + fat * self.fat_sectors) * SECTOR + cluster as usize * 4;
+            self.data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    /// Allocates a chain of clusters for `len` bytes, 0 for an empty file.
+    fn alloc(&mut self, len: usize) -> io::Result<u32> {
+        let count = len.div_ceil(self.cluster_size());
+        if count == 0 {
+            return Ok(0);
+        }
+        let first = self.next_cluster;
+        if first as usize + count > self.cluster_count + 2 {
+            return Err(error(String::from("the files do not fit in the image")));
+        }
+        for i in 0..count as u32 {
+            let next = if i + 1 == count as u32 {
+                END_OF_CHAIN
+            } else {
+                first + i + 1
+            };
+            self.set_fat_entry(first + i, next);
+        }
+        self.next_cluster += count as u32;
+        Ok(first)
+    }
+
+    fn write_clusters(&mut self, first: u32, bytes: &[u8]) {
+        if first != 0 {
+            let offset = self.cluster_offset(first);
+            self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+
+    /// Writes the directory `dir` and everything under it, returning its
+    /// first cluster. `parent` is the cluster of the parent directory, or
+    /// `None` for the root itself.
+    fn add_dir(&mut self, dir: &Path, parent: Option<u32>) -> io::Result<u32> {
+        let mut children = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry
+                .file_name()
+                .into_string()
+                .map_err(|name| error(format!("{:?} in {} is not UTF-8", name, dir.display())))?;
+            let meta = fs::symlink_metadata(entry.path())?;
+            if !meta.is_file() && !meta.is_dir() {
+                eprintln!("xtask: image: skipping {}", entry.path().display());
+                continue;
+            }
+            if !is_valid_name(&name) {
+                return Err(error(format!("{} is not a valid FAT name", name)));
+            }
+            children.push((name, meta));
+        }
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut used = HashSet::new();
+        let names: Vec<([u8; 11], bool)> = children
+            .iter()
+            .map(|(name, _)| short_name(name, &mut used))
+            .collect();
+        let mut len = if parent.is_some() { 2 } else { 0 };
+        for ((name, _), (_, needs_long)) in children.iter().zip(&names) {
+            len += 1 + if *needs_long {
+                long_entry_count(name)
+            } else {
+                0
+            };
+        }
+        // Keep the directory non-empty so it has a cluster of its own.
+        let first = self.alloc((len * ENTRY_SIZE).max(1))?;
+
+        let mut entries = Vec::with_capacity(len * ENTRY_SIZE);
+        if let Some(parent) = parent {
+            let (date, time) = fat_timestamp(fs::metadata(dir)?.mtime());
+            entries.extend(short_entry(
+                b".          ",
+                ATTR_DIRECTORY,
+                first,
+                0,
+                date,
+                time,
+            ));
+            entries.extend(short_entry(
+                b"..         ",
+                ATTR_DIRECTORY,
+                parent,
+                0,
+                date,
+                time,
+            ));
+        }
+        // `..` of a directory in the root names cluster 0.
+        let this = if parent.is_some() { first } else { 0 };
+        for ((name, meta), (short, needs_long)) in children.iter().zip(names) {
+            let path = dir.join(name);
+            let (attr, cluster, size) = if meta.is_dir() {
+                (ATTR_DIRECTORY, self.add_dir(&path, Some(this))?, 0)
+            } else {
+                let data = fs::read(&path)?;
+                let size = u32::try_from(data.len())
+                    .map_err(|_| error(format!("{} is too large for FAT", path.display())))?;
+                let cluster = self.alloc(data.len())?;
+                self.write_clusters(cluster, &data);
+                (ATTR_ARCHIVE, cluster, size)
+            };
+            if needs_long {
+                for entry in long_entries(name, &short) {
+                    entries.extend(entry);
+                }
+            }
+            let (date, time) = fat_timestamp(meta.mtime());
+            entries.extend(short_entry(&short, attr, cluster, size, date, time));
+        }
+        self.write_clusters(first, &entries);
+        Ok(first)
+    }
+
+    fn write_boot_sectors(&mut self) {
+        let total_sectors = self.data.len() / SECTOR;
+        let mut boot = [0u8; SECTOR];
+        boot[0..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+        boot[3..11].copy_from_slice(b"MSWIN4.1");
+        boot[11..13].copy_from_slice(&(SECTOR as u16).to_le_bytes());
+        boot[13] = self.sectors_per_cluster as u8;
+        boot[14..16].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+        boot[16] = NUM_FATS as u8;
+        boot[21] = 0xf8; // fixed disk
+        boot[24..26].copy_from_slice(&32u16.to_le_bytes()); // sectors per track
+        boot[26..28].copy_from_slice(&64u16.to_le_bytes()); // heads
+        boot[32..36].copy_from_slice(&(total_sectors as u32).to_le_bytes());
+        boot[36..40].copy_from_slice(&(self.fat_sectors as u32).to_le_bytes());
+        boot[44..48].copy_from_slice(&ROOT_CLUSTER.to_le_bytes());
+        boot[48..50].copy_from_slice(&(FS_INFO_SECTOR as u16).to_le_bytes());
+        boot[50..52].copy_from_slice(&(BACKUP_BOOT_SECTOR as u16).to_le_bytes());
+        boot[64] = 0x80; // drive number
+        boot[66] = 0x29; // extended boot signature
+        boot[67..71].copy_from_slice(&0x524f_5321u32.to_le_bytes()); // volume id
+        boot[71..82].copy_from_slice(b"ROS        ");
+        boot[82..90].copy_from_slice(b"FAT32   ");
+        boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+        let used = self.next_cluster as usize - ROOT_CLUSTER as usize;
+        let mut fs_info = [0u8; SECTOR];
+        fs_info[0..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+        fs_info[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+        fs_info[488..492].copy_from_slice(&((self.cluster_count - used) as u32).to_le_bytes());
+        fs_info[492..496].copy_from_slice(&self.next_cluster.to_le_bytes());
+        fs_info[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+        for base in [0, BACKUP_BOOT_SECTOR] {
+            self.data[base * SECTOR..(base + 1) * SECTOR].copy_from_slice(&boot);
+            let info = (base + FS_INFO_SECTOR) * SECTOR;
+            self.data[info..info + SECTOR].copy_from_slice(&fs_info);
+        }
+        // Media descriptor and end-of-chain marker in the reserved entries.
+        self.set_fat_entry(0, 0x0fff_fff8);
+        self.set_fat_entry(1, END_OF_CHAIN);
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.ends_with(' ')
+        && !name.ends_with('.')
+        && name.encode_utf16().count() <= 255
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+}
+
+fn is_short_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || "!#$%&'()-@^_`{}~".contains(c)
+}
+
+/// The 8.3 name for `name`, unique among `used`, and whether a long name is
+/// needed as well.
+fn short_name(name: &str, used: &mut HashSet<[u8; 11]>) -> ([u8; 11], bool) {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    if !base.is_empty()
+        && base.len() <= 8
+        && ext.len() <= 3
+        && base.chars().chain(ext.chars()).all(is_short_char)
+    {
+        let mut short = [b' '; 11];
+        short[..base.len()].copy_from_slice(base.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+        if used.insert(short) {
+            return (short, false);
+        }
+    }
+    let convert = |part: &str, max: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let c = c.to_ascii_uppercase();
+                if is_short_char(c) {
+                    c as u8
+                } else {
+                    b'_'
+                }
+            })
+            .take(max)
+            .collect()
+    };
+    let (base, ext) = match name.rfind('.') {
+        Some(index) if index > 0 => (&name[..index], &name[index + 1..]),
+        _ => (name, ""),
+    };
+    let ext = convert(ext, 3);
+    for n in 1.. {
+        let tail = format!("~{}", n).into_bytes();
+        let mut base = convert(base, 8);
+        base.truncate(8 - tail.len());
+        if base.is_empty() {
+            base.push(b'_');
+        }
+        base.extend_from_slice(&tail);
+        let mut short = [b' '; 11];
+        short[..base.len()].copy_from_slice(&base);
+        short[8..8 + ext.len()].copy_from_slice(&ext);
+        if used.insert(short) {
+            return (short, true);
+        }
+    }
+    unreachable!()
+}
+
+fn checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+fn long_entry_count(name: &str) -> usize {
+    name.encode_utf16().count().div_ceil(LFN_CHARS)
+}
+
+/// The long name entries for `name`, in on-disk order.
+fn long_entries(name: &str, short: &[u8; 11]) -> Vec<[u8; ENTRY_SIZE]> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let count = long_entry_count(name);
+    let sum = checksum(short);
+    (1..=count)
+        .rev()
+        .map(|order| {
+            let mut raw = [0u8; ENTRY_SIZE];
+            raw[0] = order as u8 | if order == count { LFN_LAST } else { 0 };
+            raw[11] = ATTR_LONG_NAME;
+            raw[13] = sum;
+            for (i, &offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                let index = (order - 1) * LFN_CHARS + i;
+                // NUL terminated if the name does not fill the entry, then
+                // padded with 0xffff.
+                let c = match index.cmp(&units.len()) {
+                    std::cmp::Ordering::Less => units[index],
+                    std::cmp::Ordering::Equal => 0,
+                    std::cmp::Ordering::Greater => 0xffff,
+                };
+                raw[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+            }
+            raw
+        })
+        .collect()
+}
+
+fn short_entry(
+    name: &[u8; 11],
+    attr: u8,
+    cluster: u32,
+    size: u32,
+    date: u16,
+    time: u16,
+) -> [u8; ENTRY_SIZE] {
+    let mut raw = [0u8; ENTRY_SIZE];
+    raw[0..11].copy_from_slice(name);
+    raw[11] = attr;
+    for offset in [14, 22] {
+        raw[offset..offset + 2].copy_from_slice(&time.to_le_bytes());
+        raw[offset + 2..offset + 4].copy_from_slice(&date.to_le_bytes());
+    }
+    raw[18..20].copy_from_slice(&date.to_le_bytes()); // last access
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    raw[28..32].copy_from_slice(&size.to_le_bytes());
+    raw
+}
+
+/// FAT date and time of the UNIX time `secs`, in UTC, clamped to the
+/// years FAT can represent.
+fn fat_timestamp(secs: i64) -> (u16, u16) {
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    if year < 1980 {
+        return ((1 << 5) | 1, 0);
+    }
+    let year = year.min(2107);
+    let date = (((year - 1980) << 9) | (month << 5) | day) as u16;
+    let time = (((rem / 3600) << 11) | ((rem % 3600 / 60) << 5) | ((rem % 60) / 2)) as u16;
+    (date, time)
+}
+
+/// Writes a FAT32 image of `size` bytes to `out`, holding the tree under
+/// `dir`. A missing `dir` gives an empty filesystem.
+pub fn build(dir: &Path, size: usize, out: &Path) -> io::Result<()> {
+    let mut image = Image::new(size)?;
+    if dir.is_dir() {
+        image.add_dir(dir, None)?;
+    } else {
+        image.alloc(1)?;
+    }
+    image.write_boot_sectors();
+    fs::write(out, image.data)
+}
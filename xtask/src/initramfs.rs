@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+/// Writes a `newc` cpio archive.
+struct Writer {
+    out: Vec<u8>,
+    next_ino: u32,
+    // host (dev, ino) of hard-linked files => archive ino
+    links: HashMap<(u64, u64), u32>,
+}
+
+impl Writer {
+    fn entry(&mut self, name: &str, ino: u32, mode: u32, nlink: u32, mtime: u32, data: &[u8]) {
+        let fields = [
+            ino,
+            mode,
+            0, // uid
+            0, // gid
+            nlink,
+            mtime,
+            data.len() as u32,
+            0, // devmajor
+            0, // devminor
+            0, // rdevmajor
+            0, // rdevminor
+            name.len() as u32 + 1,
+            0, // check
+        ];
+        self.out.extend_from_slice(b"070701");
+        for field in fields {
+            self.out
+                .extend_from_slice(format!("{:08x}", field).as_bytes());
+        }
+        self.out.extend_from_slice(name.as_bytes());
+        self.out.push(0);
+        self.pad();
+        self.out.extend_from_slice(data);
+        self.pad();
+    }
+
+    fn pad(&mut self) {
+        while !self.out.len().is_multiple_of(4) {
+            self.out.push(0);
+        }
+    }
+
+    fn add_dir(&mut self, root: &Path, dir: &Path) -> io::Result<()> {
+        let mut children = fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<Vec<PathBuf>>>()?;
+        children.sort();
+        for path in children {
+            let meta = fs::symlink_metadata(&path)?;
+            let name = path.strip_prefix(root).unwrap();
+            let name = name
+                .to_str()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "non-UTF-8 file name"))?;
+            let mtime = meta.mtime() as u32;
+            let file_type = meta.file_type();
+            if file_type.is_dir() {
+                let ino = self.new_ino();
+                self.entry(name, ino, meta.mode(), 2, mtime, &[]);
+                self.add_dir(root, &path)?;
+            } else if file_type.is_symlink() {
+                let target = fs::read_link(&path)?;
+                let target = target.to_str().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "non-UTF-8 link target")
+                })?;
+                let ino = self.new_ino();
+                self.entry(name, ino, meta.mode(), 1, mtime, target.as_bytes());
+            } else if file_type.is_file() {
+                // Hard links share an ino; only the first carries the data.
+                let key = (meta.dev(), meta.ino());
+                let (ino, data) = match self.links.get(&key) {
+                    Some(&ino) => (ino, Vec::new()),
+                    None => {
+                        let ino = self.new_ino();
+                        if meta.nlink() > 1 {
+                            self.links.insert(key, ino);
+                        }
+                        (ino, fs::read(&path)?)
+                    }
+                };
+                self.entry(name, ino, meta.mode(), meta.nlink() as u32, mtime, &data);
+            } else {
+                eprintln!("xtask: initramfs: skipping special file {}", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    fn new_ino(&mut self) -> u32 {
+        self.next_ino += 1;
+        self.next_ino
+    }
+}
+
+/// Packs the tree under `dir` into a `newc` cpio archive at `out`. A missing
+/// `dir` gives an empty archive.
+pub fn pack(dir: &Path, out: &Path) -> io::Result<()> {
+    let mut writer = Writer {
+        out: Vec::new(),
+        next_ino: 0,
+        links: HashMap::new(),
+    };
+    if dir.is_dir() {
+        writer.add_dir(dir, dir)?;
+    }
+    writer.entry("TRAILER!!!", 0, 0, 1, 0, &[]);
+    // Keep the archive a whole number of 512-byte blocks, as cpio does.
+    while !writer.out.len().is_multiple_of(512) {
+        writer.out.push(0);
+    }
+    if let Some(parent) = out.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(out, writer.out)
+}
@@ -1,6 +1,9 @@
 #[macro_use]
 extern crate clap;
 
+mod image;
+mod initramfs;
+
 use std::{
     env,
     path::{Path, PathBuf},
@@ -10,6 +13,9 @@ use std::{
 // 不要修改DEFAULT_TARGET；如果你需要编译到别的目标，请使用--target编译选项！
 const DEFAULT_TARGET: &'static str = "riscv64imac-unknown-none-elf";
 
+/// Size of the disk image in MiB, the smallest common size that fits FAT32.
+const DEFAULT_IMAGE_SIZE: usize = 64;
+
 #[derive(Debug)]
 struct XtaskEnv {
     compile_mode: CompileMode,
@@ -33,6 +39,8 @@ fn main() {
         (@subcommand qemu =>
             (about: "Run QEMU")
             (@arg release: --release "Build artifacts in release mode, with optimizations")
+            (@arg initrd: --initrd "Load the initramfs with -initrd instead of building it into the kernel")
+            (@arg append: --append +takes_value "Kernel command line, such as \"sched=fair timeslice=5\"")
         )
         (@subcommand test =>
             (about: "Run tests")
@@ -41,6 +49,14 @@ fn main() {
         (@subcommand debug =>
             (about: "Debug with QEMU and GDB stub")
         )
+        (@subcommand image =>
+            (about: "Build the disk image from disk/")
+            (@arg size: --size +takes_value "Image size in MiB [default: 64]")
+            (@arg dir: --dir +takes_value "Directory to copy into the image [default: disk]")
+        )
+        (@subcommand initramfs =>
+            (about: "Pack rootfs/ into the initramfs archive")
+        )
     )
     .get_matches();
     let mut xtask_env = XtaskEnv {
@@ -59,24 +75,72 @@ fn main() {
         }
         xtask_build_kernel(&xtask_env);
         xtask_binary_kernel(&xtask_env);
-        xtask_qemu_run(&xtask_env);
+        xtask_default_image();
+        xtask_qemu_run(
+            &xtask_env,
+            matches.is_present("initrd"),
+            matches.value_of("append"),
+        );
     } else if let Some(matches) = matches.subcommand_matches("test") {
         if matches.is_present("release") {
             xtask_env.compile_mode = CompileMode::Release;
         }
         xtask_build_kernel(&xtask_env);
         xtask_binary_kernel(&xtask_env);
+        xtask_default_image();
         xtask_qemu_test(&xtask_env);
     } else if let Some(_matches) = matches.subcommand_matches("debug") {
         xtask_build_kernel(&xtask_env);
         xtask_binary_kernel(&xtask_env);
         xtask_qemu_debug(&xtask_env);
+    } else if let Some(matches) = matches.subcommand_matches("image") {
+        let size = match matches.value_of("size").map(str::parse::<usize>) {
+            None => DEFAULT_IMAGE_SIZE,
+            Some(Ok(size)) => size,
+            Some(Err(err)) => {
+                println!("invalid image size: {}", err);
+                process::exit(1);
+            }
+        };
+        let dir = matches
+            .value_of("dir")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| project_root().join("disk"));
+        xtask_image(&dir, size);
+    } else if let Some(_matches) = matches.subcommand_matches("initramfs") {
+        xtask_pack_initramfs();
     } else {
         eprintln!("Use `cargo qemu` to run, `cargo xtask --help` for help")
     }
 }
 
+fn xtask_image(dir: &Path, size_mib: usize) {
+    let out = project_root().join("image.img");
+    if let Err(err) = image::build(dir, size_mib << 20, &out) {
+        println!("building {} failed: {}", out.display(), err);
+        process::exit(1);
+    }
+    eprintln!("xtask: built {} from {}", out.display(), dir.display());
+}
+
+/// Builds `image.img` with the default settings unless it already exists,
+/// so changes the kernel made to it survive between runs.
+fn xtask_default_image() {
+    if !project_root().join("image.img").exists() {
+        xtask_image(&project_root().join("disk"), DEFAULT_IMAGE_SIZE);
+    }
+}
+
+fn xtask_pack_initramfs() {
+    let dir = project_root().join("rootfs");
+    if let Err(err) = initramfs::pack(&dir, &initramfs_path()) {
+        println!("packing {} failed: {}", dir.display(), err);
+        process::exit(1);
+    }
+}
+
 fn xtask_build_kernel(xtask_env: &XtaskEnv) {
+    xtask_pack_initramfs();
     let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
     let mut command = Command::new(cargo);
     command.current_dir(project_root());
@@ -89,6 +153,7 @@ fn xtask_build_kernel(xtask_env: &XtaskEnv) {
     }
     command.args(&["--package", "ros"]);
     command.args(&["--target", DEFAULT_TARGET]);
+    command.env("ROS_INITRAMFS", initramfs_path());
     let status = command.status().unwrap();
     if !status.success() {
         println!("cargo build failed");
@@ -113,7 +178,7 @@ fn xtask_binary_kernel(xtask_env: &XtaskEnv) {
     }
 }
 
-fn xtask_qemu_run(xtask_env: &XtaskEnv) {
+fn xtask_qemu_run(xtask_env: &XtaskEnv, initrd: bool, append: Option<&str>) {
     /*
     qemu: build
     @qemu-system-riscv64 \
@@ -124,7 +189,8 @@ fn xtask_qemu_run(xtask_env: &XtaskEnv) {
             -device loader,file={{test-kernel-bin}},addr=0x80200000 \
             -smp threads={{threads}}
     */
-    let status = Command::new("qemu-system-riscv64")
+    let mut command = Command::new("qemu-system-riscv64");
+    command
         .current_dir(project_root())
         .args(&["-machine", "virt"])
         // .args(&["-bios", "bin/rustsbi-qemu.bin"])
@@ -140,9 +206,14 @@ fn xtask_qemu_run(xtask_env: &XtaskEnv) {
         ])
         .args(&["-smp", "2"]) // 8 cores
         .arg("-nographic")
-        .args(&["-m", "32m"])
-        .status()
-        .unwrap();
+        .args(&["-m", "32m"]);
+    if initrd {
+        command.args(&["-initrd", initramfs_path().to_str().unwrap()]);
+    }
+    if let Some(append) = append {
+        command.args(&["-append", append]);
+    }
+    let status = command.status().unwrap();
 
     if !status.success() {
         println!("qemu failed");
@@ -218,6 +289,10 @@ fn project_root() -> PathBuf {
         .to_path_buf()
 }
 
+fn initramfs_path() -> PathBuf {
+    project_root().join("target").join("initramfs.cpio")
+}
+
 fn dist_dir(xtask_env: &XtaskEnv) -> PathBuf {
     let mut path_buf = project_root().join("target").join(DEFAULT_TARGET);
     path_buf = match xtask_env.compile_mode {